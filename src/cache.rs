@@ -0,0 +1,181 @@
+//! On-disk cache of per-file last-commit lookups, so regenerating the output
+//! of an unchanged repository skips the history walks.
+//!
+//! The cache lives in the repository's Git directory, where it is never
+//! walked or committed. Every lookup starts from one commit (HEAD or
+//! `--rev`), which also pins the blob of every file, so the whole cache is
+//! dropped as soon as that commit changes.
+
+use crate::git::GitInfo;
+use anyhow::{Context, Result};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Name of the cache file inside the Git directory
+pub const CACHE_FILE_NAME: &str = "amc-cache.json";
+
+/// Bumped whenever the stored layout changes, discarding older caches
+const CACHE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    /// Commit every entry was resolved from
+    start: String,
+    /// Repository-relative path to its last commit, `None` when the file is
+    /// not part of `start`
+    files: HashMap<PathBuf, Option<GitInfo>>,
+}
+
+/// Last-commit lookups shared by every worker of a run. Without a start
+/// commit it only lives in memory.
+#[derive(Default)]
+pub struct HistoryCache {
+    start: Option<String>,
+    files: Mutex<HashMap<PathBuf, Option<GitInfo>>>,
+    changed: AtomicBool,
+}
+
+impl HistoryCache {
+    /// Read the cache at `path` if it was written for `start`, otherwise
+    /// start empty. A missing or unreadable file is not an error.
+    pub fn load(path: &Path, start: &str) -> Self {
+        let files = fs::read_to_string(path)
+            .ok()
+            .and_then(|text| match serde_json::from_str::<CacheFile>(&text) {
+                Ok(file) => Some(file),
+                Err(err) => {
+                    debug!("Ignoring unreadable cache {}: {}", path.display(), err);
+                    None
+                }
+            })
+            .filter(|file| file.version == CACHE_VERSION && file.start == start)
+            .map(|file| file.files)
+            .unwrap_or_default();
+        debug!("Loaded {} cached lookup(s) for {}", files.len(), start);
+
+        Self {
+            start: Some(start.to_string()),
+            files: Mutex::new(files),
+            changed: AtomicBool::new(false),
+        }
+    }
+
+    pub fn get(&self, relative: &Path) -> Option<Option<GitInfo>> {
+        self.files.lock().unwrap().get(relative).cloned()
+    }
+
+    pub fn insert(&self, relative: PathBuf, info: Option<GitInfo>) {
+        self.files.lock().unwrap().insert(relative, info);
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    /// Write the cache to `path` if lookups were added since loading it
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let Some(start) = &self.start else {
+            return Ok(());
+        };
+        if !self.changed.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let file = CacheFile {
+            version: CACHE_VERSION,
+            start: start.clone(),
+            files: self.files.lock().unwrap().clone(),
+        };
+        // Write a temporary file first so concurrent runs never read half a
+        // cache
+        let temporary = path.with_extension(format!("json.{}", std::process::id()));
+        fs::write(&temporary, serde_json::to_vec(&file)?)
+            .and_then(|()| fs::rename(&temporary, path))
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// Location of the cache for the repository with Git directory `git_dir`
+pub fn cache_path(git_dir: &Path) -> PathBuf {
+    git_dir.join(CACHE_FILE_NAME)
+}
+
+/// Delete the cache at `path`, returning whether there was one
+pub fn clear(path: &Path) -> Result<bool> {
+    if !path.exists() {
+        return Ok(false);
+    }
+    fs::remove_file(path)
+        .with_context(|| format!("Failed to remove {}", path.display()))?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn info(hash: &str) -> GitInfo {
+        GitInfo {
+            commit_hash: hash.to_string(),
+            commit_time: 100,
+            commit_offset_minutes: 60,
+            author_name: "Jane Doe".to_string(),
+            author_email: "jane@example.com".to_string(),
+            summary: "Initial commit".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_round_trip_for_the_same_start() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = cache_path(temp_dir.path());
+
+        let cache = HistoryCache::load(&path, "abc");
+        cache.insert("src/a.rs".into(), Some(info("123")));
+        cache.insert("new.rs".into(), None);
+        cache.save(&path)?;
+
+        let cache = HistoryCache::load(&path, "abc");
+        let cached = cache.get(Path::new("src/a.rs")).unwrap().unwrap();
+        assert_eq!(cached.commit_hash, "123");
+        assert_eq!(cached.commit_offset_minutes, 60);
+        assert_eq!(cache.get(Path::new("new.rs")), Some(None));
+        assert_eq!(cache.get(Path::new("other.rs")), None);
+
+        // A new start commit invalidates every entry
+        let cache = HistoryCache::load(&path, "def");
+        assert_eq!(cache.get(Path::new("src/a.rs")), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unreadable_cache_starts_empty_and_clear_removes_it() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = cache_path(temp_dir.path());
+        fs::write(&path, "{not json")?;
+        assert_eq!(
+            HistoryCache::load(&path, "abc").get(Path::new("a.rs")),
+            None
+        );
+
+        assert!(clear(&path)?);
+        assert!(!path.exists());
+        assert!(!clear(&path)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_in_memory_cache_is_never_saved() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = cache_path(temp_dir.path());
+        let cache = HistoryCache::default();
+        cache.insert("a.rs".into(), None);
+        cache.save(&path)?;
+        assert!(!path.exists());
+        Ok(())
+    }
+}
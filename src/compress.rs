@@ -0,0 +1,94 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use flate2::write::GzEncoder;
+use std::io::Write;
+
+/// Output compression selected with `--compress`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// File extension conventionally used for this format, without the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+
+    /// Append the format's extension to `path` unless it already has it
+    pub fn output_path(self, path: &str) -> String {
+        let suffix = format!(".{}", self.extension());
+        if path.ends_with(&suffix) {
+            path.to_string()
+        } else {
+            format!("{}{}", path, suffix)
+        }
+    }
+}
+
+/// Run `write` against `out`, compressed when `compression` is set. The
+/// encoder is finished explicitly so errors writing the trailer surface.
+pub fn with_encoder<W: Write, T>(
+    mut out: W,
+    compression: Option<Compression>,
+    write: impl FnOnce(&mut dyn Write) -> Result<T>,
+) -> Result<T> {
+    let result = match compression {
+        None => write(&mut out)?,
+        Some(Compression::Gzip) => {
+            let mut encoder = GzEncoder::new(out, flate2::Compression::default());
+            let result = write(&mut encoder)?;
+            out = encoder.finish()?;
+            result
+        }
+        Some(Compression::Zstd) => {
+            let mut encoder = zstd::Encoder::new(out, 0)?;
+            let result = write(&mut encoder)?;
+            out = encoder.finish()?;
+            result
+        }
+    };
+    out.flush()?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn compress(compression: Option<Compression>, text: &str) -> Vec<u8> {
+        let mut out = Vec::new();
+        with_encoder(&mut out, compression, |w| Ok(w.write_all(text.as_bytes())?))
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let text = "fn main() {}\n".repeat(100);
+
+        assert_eq!(compress(None, &text), text.as_bytes());
+
+        let gzip = compress(Some(Compression::Gzip), &text);
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&gzip[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, text);
+
+        let zstd = compress(Some(Compression::Zstd), &text);
+        assert_eq!(zstd::decode_all(&zstd[..]).unwrap(), text.as_bytes());
+    }
+
+    #[test]
+    fn test_output_path_appends_extension_once() {
+        assert_eq!(Compression::Gzip.output_path("out.txt"), "out.txt.gz");
+        assert_eq!(Compression::Gzip.output_path("out.txt.gz"), "out.txt.gz");
+        assert_eq!(Compression::Zstd.output_path("out"), "out.zst");
+    }
+}
@@ -1,14 +1,110 @@
-use anyhow::{Context, Result};
+use crate::git::DateFormat;
+use anyhow::{bail, Context, Result};
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-#[derive(Deserialize)]
+/// Name of the config file looked up when `--config` isn't given
+pub const CONFIG_FILE_NAME: &str = ".amc.toml";
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub delimiter: String,
     pub extensions: Vec<String>,
+    /// Glob patterns for files to include regardless of extension
+    #[serde(default)]
+    pub include_globs: Vec<String>,
+    /// Glob patterns for files to exclude, taking precedence over inclusion
+    #[serde(default)]
+    pub exclude_globs: Vec<String>,
+    /// Exact paths, relative to the scanned directory, to leave out
+    #[serde(default)]
+    pub excluded_files: Vec<String>,
+    /// Skip files whose first lines contain any of these markers,
+    /// e.g. `@generated` or `DO NOT EDIT`
+    #[serde(default)]
+    pub exclude_if_contains: Vec<String>,
+    /// Paths or globs of files to write first, in this order
+    #[serde(default)]
+    pub priority: Vec<String>,
     #[serde(default = "default_llm_prompt")]
     pub llm_prompt: String,
+    /// File to read the prompt from, relative to the config file. Takes
+    /// precedence over `llm_prompt`.
+    #[serde(default)]
+    pub llm_prompt_file: Option<PathBuf>,
+    /// Write plain-format headers as comments in each file's language
+    #[serde(default)]
+    pub comment_delimiters: bool,
+    /// Start the output with the file count, total size, token estimate and
+    /// directory tree, laid out like gitingest's summary
+    #[serde(default)]
+    pub summary_block: bool,
+    /// Closing instructions written after the last file, empty for none
+    #[serde(default)]
+    pub footer_prompt: String,
+    /// Collapse runs of blank lines in file content down to this many
+    #[serde(default)]
+    pub max_blank_lines: Option<usize>,
+    /// Plain-format file header, with `{path}`, `{hash}`, `{short_hash}`,
+    /// `{time}`, `{author}`, `{message}` and `{size}` placeholders
+    #[serde(default = "default_header_template")]
+    pub header_template: String,
+    /// Written as the commit, author and message of a file whose history
+    /// can't be resolved
+    #[serde(default = "default_unknown")]
+    pub unknown_commit: String,
+    /// Written as that file's time instead of its filesystem mtime
+    #[serde(default)]
+    pub unknown_time: Option<String>,
+    /// Per-extension settings, e.g. `[formats."rs"]`, overriding the ones above
+    #[serde(default)]
+    pub formats: BTreeMap<String, FormatOverride>,
+    /// Shell command per extension that file content is piped through, its
+    /// stdout replacing the content. Only run with `Options::allow_filters`.
+    #[serde(default)]
+    pub filters: BTreeMap<String, String>,
+}
+
+/// Syntax of a config file, picked from its extension
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// `.yaml`/`.yml` and `.json` files; anything else, `.amc.toml`
+    /// included, is TOML
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("yaml") => ConfigFormat::Yaml,
+            Some(ext) if ext.eq_ignore_ascii_case("yml") => ConfigFormat::Yaml,
+            Some(ext) if ext.eq_ignore_ascii_case("json") => ConfigFormat::Json,
+            _ => ConfigFormat::Toml,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ConfigFormat::Toml => "TOML",
+            ConfigFormat::Yaml => "YAML",
+            ConfigFormat::Json => "JSON",
+        }
+    }
+}
+
+/// Settings from a `[formats."<ext>"]` table; unset fields fall back to the
+/// global value
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FormatOverride {
+    pub delimiter: Option<String>,
+    pub line_numbers: Option<bool>,
 }
 
 impl Config {
@@ -21,6 +117,28 @@ impl Config {
         }
     }
 
+    /// Find the nearest config file by walking up from `start`, the way Cargo
+    /// finds `Cargo.toml`. The search stops at `root` (usually the Git
+    /// top-level) when given.
+    pub fn discover<P: AsRef<Path>>(
+        start: P,
+        root: Option<&Path>,
+    ) -> Option<PathBuf> {
+        let start = start.as_ref().canonicalize().ok()?;
+        let root = root.and_then(|root| root.canonicalize().ok());
+
+        for dir in start.ancestors() {
+            let candidate = dir.join(CONFIG_FILE_NAME);
+            if candidate.is_file() {
+                return Some(candidate);
+            }
+            if root.as_deref() == Some(dir) {
+                break;
+            }
+        }
+        None
+    }
+
     /// Load configuration from the specified file path
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
         let config_content = fs::read_to_string(&path).context(format!(
@@ -28,32 +146,269 @@ impl Config {
             path.as_ref().display()
         ))?;
 
-        Self::from_str(&config_content)
+        let format = ConfigFormat::from_path(path.as_ref());
+        let mut config = Self::parse(&config_content, format)?;
+        let base_dir = path.as_ref().parent().unwrap_or(Path::new("."));
+        config.load_prompt_file(base_dir)?;
+        Ok(config)
+    }
+
+    /// Parse configuration written in `format`. `llm_prompt_file` is only
+    /// resolved by `from_file`, which knows where the config lives.
+    pub fn parse(content: &str, format: ConfigFormat) -> Result<Self> {
+        let parsed = match format {
+            ConfigFormat::Toml => {
+                toml::from_str(content).map_err(anyhow::Error::from)
+            }
+            ConfigFormat::Yaml => {
+                serde_yaml::from_str(content).map_err(anyhow::Error::from)
+            }
+            ConfigFormat::Json => {
+                serde_json::from_str(content).map_err(anyhow::Error::from)
+            }
+        };
+        let config: Self = parsed
+            .with_context(|| format!("Failed to parse {} config", format.name()))?;
+        config.validate()?;
+        Ok(config)
     }
 
-    /// Parse configuration from a string
-    pub fn from_str(content: &str) -> Result<Self> {
-        toml::from_str(content).context("Failed to parse config")
+    /// Overrides for files with `path`'s extension, if any are configured
+    pub fn format_for(&self, path: &Path) -> Option<&FormatOverride> {
+        let ext = path.extension()?.to_str()?;
+        self.formats
+            .iter()
+            .find(|(key, _)| key.trim_start_matches('.') == ext)
+            .map(|(_, format)| format)
     }
 
-    /// Create a default configuration
-    pub fn default() -> Self {
+    /// Every delimiter files can be written with: the global one first, then
+    /// those of `[formats]` overrides
+    pub fn delimiters(&self) -> Vec<&str> {
+        let mut delimiters = vec![self.delimiter.as_str()];
+        for format in self.formats.values() {
+            if let Some(delimiter) = format.delimiter.as_deref() {
+                if !delimiters.contains(&delimiter) {
+                    delimiters.push(delimiter);
+                }
+            }
+        }
+        delimiters
+    }
+
+    /// Filter command for files with `path`'s extension, if one is configured
+    pub fn filter_for(&self, path: &Path) -> Option<&str> {
+        let ext = path.extension()?.to_str()?;
+        self.filters
+            .iter()
+            .find(|(key, _)| key.trim_start_matches('.') == ext)
+            .map(|(_, command)| command.as_str())
+    }
+
+    /// Catch settings that parse fine but can never produce useful output
+    fn validate(&self) -> Result<()> {
+        if self.delimiter.is_empty() {
+            bail!("Invalid config: `delimiter` must not be empty");
+        }
+        if self.extensions.is_empty() && self.include_globs.is_empty() {
+            bail!(
+                "Invalid config: `extensions` is empty and no `include_globs` are \
+                 set, so no files would match. Add e.g. extensions = [\"rs\"]"
+            );
+        }
+        if let Some(ext) = self.extensions.iter().find(|ext| ext.trim().is_empty())
+        {
+            bail!(
+                "Invalid config: `extensions` contains an empty entry {:?}",
+                ext
+            );
+        }
+        if let Some(ext) = self.formats.iter().find_map(|(ext, format)| {
+            (format.delimiter.as_deref() == Some("")).then_some(ext)
+        }) {
+            bail!(
+                "Invalid config: `formats.{}.delimiter` must not be empty",
+                ext
+            );
+        }
+        Ok(())
+    }
+
+    /// Replace `llm_prompt` with the contents of `llm_prompt_file`, if set
+    fn load_prompt_file(&mut self, base_dir: &Path) -> Result<()> {
+        if let Some(prompt_file) = &self.llm_prompt_file {
+            let prompt_path = base_dir.join(prompt_file);
+            self.llm_prompt =
+                fs::read_to_string(&prompt_path).with_context(|| {
+                    format!(
+                        "Failed to read llm_prompt_file: {}",
+                        prompt_path.display()
+                    )
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Write a commented default configuration to `path`
+    pub fn write_default<P: AsRef<Path>>(path: P) -> Result<()> {
+        fs::write(&path, DEFAULT_CONFIG_TEMPLATE).with_context(|| {
+            format!("Failed to write config file: {}", path.as_ref().display())
+        })
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
         Self {
             delimiter: "---".to_string(),
             extensions: vec!["rs".to_string()],
+            include_globs: Vec::new(),
+            exclude_globs: Vec::new(),
+            excluded_files: Vec::new(),
+            priority: Vec::new(),
+            exclude_if_contains: Vec::new(),
             llm_prompt: default_llm_prompt(),
+            llm_prompt_file: None,
+            comment_delimiters: false,
+            summary_block: false,
+            footer_prompt: String::new(),
+            max_blank_lines: None,
+            header_template: default_header_template(),
+            unknown_commit: default_unknown(),
+            unknown_time: None,
+            formats: BTreeMap::new(),
+            filters: BTreeMap::new(),
         }
     }
 }
 
-fn default_llm_prompt() -> String {
-    r#"
+/// Parse TOML configuration from a string, see [`Config::parse`]
+impl FromStr for Config {
+    type Err = anyhow::Error;
+
+    fn from_str(content: &str) -> Result<Self> {
+        Self::parse(content, ConfigFormat::Toml)
+    }
+}
+
+/// Template written by `amc init`, documenting every field
+const DEFAULT_CONFIG_TEMPLATE: &str = r#"# all-my-circuits configuration
+
+# Line written before and after each file header
+delimiter = "---"
+
+# Write each header as comments in the file's language (`// File: ...` for
+# Rust, `# File: ...` for Python) so the output stays close to valid code.
+# Unknown extensions keep the bare delimiter.
+comment_delimiters = false
+
+# Start the output with a summary like gitingest writes: files analyzed, total
+# size, estimated tokens and the directory structure (which --tree shows alone)
+summary_block = false
+
+# File extensions to include (with or without the leading dot)
+extensions = ["rs"]
+
+# Glob patterns for extra files to include regardless of extension,
+# e.g. ["Dockerfile", "Makefile"]
+include_globs = []
+
+# Glob patterns for files to leave out; these win over everything above,
+# e.g. ["*_test.rs", "**/generated/**"]
+exclude_globs = []
+
+# Single files to leave out by exact path relative to the scanned directory,
+# e.g. ["CHANGELOG.md", "tests/fixtures/big.json"]
+excluded_files = []
+
+# Skip files whose first few lines contain one of these markers, which is how
+# protoc, buf, bindgen and friends label their output
+exclude_if_contains = []
+
+# Files to put first, in this order, ahead of the --sort order; paths relative
+# to the scanned directory or globs, e.g. ["src/main.rs", "src/lib.rs"]
+priority = []
+
+# Prompt written at the top of the output. Leave unset to use the built-in
+# prompt that describes the output layout, including the --date-format of the
+# Last update line.
+# llm_prompt = """
+# Review the following code and suggest improvements.
+# """
+
+# Read the prompt from a file instead, relative to this config file
+# llm_prompt_file = "prompts/review.md"
+
+# Instructions repeated after the last file, where long contexts make models
+# pay more attention to them
+# footer_prompt = "Answer based on the files above."
+
+# Collapse runs of more blank lines than this in file content (also enabled,
+# with a limit of 1, by --squeeze-blank)
+# max_blank_lines = 1
+
+# Lines between the delimiters of each plain-format file header. Placeholders:
+# {path}, {hash}, {short_hash}, {time}, {author}, {message}, {size}
+# header_template = """
+# File: {path}
+# Last commit: {hash}
+# Last update: {time}
+# Author: {author}
+# Message: {message}"""
+
+# Written in place of the commit, author and message when a file's history
+# can't be resolved, and in place of its filesystem mtime if unknown_time is
+# set. Untracked files keep saying "untracked".
+# unknown_commit = "unknown"
+# unknown_time = ""
+
+# Settings for files with one extension, falling back to the ones above
+# [formats."md"]
+# delimiter = "==="
+# line_numbers = false
+
+# Shell command per extension that each file's content is piped through; its
+# output is included instead. The path is in $AMC_FILE. When the command fails
+# the raw content is kept with a warning. Filters only run with
+# --allow-filters, since a config found in a cloned repository could otherwise
+# run any command.
+# [filters]
+# json = "jq ."
+# rs = "rustfmt --emit stdout"
+"#;
+
+fn default_unknown() -> String {
+    "unknown".to_string()
+}
+
+fn default_header_template() -> String {
+    "File: {path}\nLast commit: {hash}\nLast update: {time}\nAuthor: {author}\n\
+     Message: {message}"
+        .to_string()
+}
+
+/// The prompt used when the config sets none, as written with the default
+/// delimiter and `--date-format`
+pub fn default_llm_prompt() -> String {
+    default_llm_prompt_for("---", DateFormat::default())
+}
+
+/// The built-in prompt, describing the default header layout as written with
+/// `delimiter` and times rendered in `date_format`
+pub fn default_llm_prompt_for(delimiter: &str, date_format: DateFormat) -> String {
+    format!(
+        r#"
 This is a concatenated source code file containing multiple source files from a project.
-Each file section begins and ends with a delimiter line "---".
+Each file section begins and ends with a delimiter line "{delimiter}".
 After the opening delimiter, there is metadata about the file:
 - File: relative path to the source file
 - Last commit: Git commit hash of the last change
-- Last update: Unix timestamp of the last change
+- Last update: {date}
+- Author: name and email of the author of the last change
+- Message: first line of the last commit message
+Files without Git history show "untracked" or "unknown" instead, with their
+modification time followed by "(filesystem mtime)". A "Note:" line may follow,
+e.g. "test" or "truncated".
 
 Please analyze the code with these aspects in mind:
 1. The relationship and dependencies between files
@@ -62,7 +417,11 @@ Please analyze the code with these aspects in mind:
 4. Consider the context of changes based on the Git metadata
 
 The code sections follow below:
-"#.trim().to_string()
+"#,
+        date = date_format.description(),
+    )
+    .trim()
+    .to_string()
 }
 
 #[cfg(test)]
@@ -88,6 +447,24 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_default_prompt_describes_the_headers() {
+        let prompt = default_llm_prompt();
+        assert!(prompt.contains("delimiter line \"---\""));
+        assert!(prompt.contains(
+            "- Last update: date and time of the last change (RFC 3339)\n"
+        ));
+        assert!(prompt.contains("- Author: "));
+        assert!(prompt.contains("- Message: "));
+        assert!(!prompt.contains("Unix timestamp"));
+
+        let prompt = default_llm_prompt_for("===", DateFormat::Unix);
+        assert!(prompt.contains("delimiter line \"===\""));
+        assert!(
+            prompt.contains("- Last update: Unix timestamp of the last change\n")
+        );
+    }
+
     #[test]
     fn test_load_nonexistent_config() -> Result<()> {
         let config = Config::load("nonexistent-config.toml")?;
@@ -111,6 +488,43 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_yaml_and_json_configs() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let yaml = temp_dir.path().join("amc.yml");
+        fs::write(
+            &yaml,
+            "delimiter: \"===\"\nextensions: [rs, py]\nformats:\n  md:\n    line_numbers: true\n",
+        )?;
+        let config = Config::from_file(&yaml)?;
+        assert_eq!(config.delimiter, "===");
+        assert_eq!(config.extensions, vec!["rs", "py"]);
+        assert_eq!(
+            config.format_for(Path::new("a.md")).unwrap().line_numbers,
+            Some(true)
+        );
+
+        let json = temp_dir.path().join("amc.json");
+        fs::write(&json, r#"{"delimiter": "+++", "extensions": ["ts"]}"#)?;
+        let config = Config::from_file(&json)?;
+        assert_eq!(config.delimiter, "+++");
+        assert_eq!(config.extensions, vec!["ts"]);
+
+        fs::write(&json, r#"{"delimiter": "+++", "extensions": ["ts"],}"#)?;
+        let err = Config::from_file(&json).unwrap_err();
+        assert!(err.to_string().contains("Failed to parse JSON config"));
+
+        // Validation and unknown-field checks apply to every format
+        fs::write(&yaml, "delimiter: \"\"\nextensions: [rs]\n")?;
+        assert!(Config::from_file(&yaml).is_err());
+        fs::write(
+            &yaml,
+            "delimiter: \"-\"\nextensions: [rs]\nextenions: [py]\n",
+        )?;
+        assert!(Config::from_file(&yaml).is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_default_config() {
         let config = Config::default();
@@ -118,6 +532,138 @@ mod tests {
         assert_eq!(config.extensions, vec!["rs"]);
     }
 
+    #[test]
+    fn test_glob_config() -> Result<()> {
+        let config_content = r#"
+            delimiter = "---"
+            extensions = ["rs"]
+            include_globs = ["Dockerfile"]
+            exclude_globs = ["**/generated/**"]
+            excluded_files = ["Cargo.lock"]
+            exclude_if_contains = ["@generated"]
+        "#;
+
+        let config = Config::from_str(config_content)?;
+        assert_eq!(config.include_globs, vec!["Dockerfile"]);
+        assert_eq!(config.exclude_globs, vec!["**/generated/**"]);
+        assert_eq!(config.excluded_files, vec!["Cargo.lock"]);
+        assert_eq!(config.exclude_if_contains, vec!["@generated"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_overrides() -> Result<()> {
+        let config = Config::from_str(
+            r#"
+            delimiter = "---"
+            extensions = ["rs", "md"]
+
+            [formats."rs"]
+            line_numbers = true
+
+            [formats.".md"]
+            delimiter = "==="
+        "#,
+        )?;
+
+        let rust = config.format_for(Path::new("src/main.rs")).unwrap();
+        assert_eq!(rust.line_numbers, Some(true));
+        assert_eq!(rust.delimiter, None);
+        let markdown = config.format_for(Path::new("README.md")).unwrap();
+        assert_eq!(markdown.delimiter.as_deref(), Some("==="));
+        assert!(config.format_for(Path::new("Makefile")).is_none());
+
+        let err = Config::from_str(
+            "delimiter = \"---\"\nextensions = [\"rs\"]\n[formats.rs]\ndelimiter = \"\"\n",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("formats.rs.delimiter"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_filters() -> Result<()> {
+        let config = Config::from_str(
+            r#"
+            delimiter = "---"
+            extensions = ["rs", "json"]
+
+            [filters]
+            ".json" = "jq ."
+        "#,
+        )?;
+        assert_eq!(config.filter_for(Path::new("data/a.json")), Some("jq ."));
+        assert_eq!(config.filter_for(Path::new("src/main.rs")), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_default_round_trips() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let path = temp_dir.path().join(".amc.toml");
+
+        Config::write_default(&path)?;
+        let config = Config::from_file(&path)?;
+
+        assert_eq!(config.delimiter, "---");
+        assert_eq!(config.extensions, vec!["rs"]);
+        assert!(config.include_globs.is_empty());
+        assert!(config.llm_prompt.contains("concatenated source code"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_discover_walks_up_to_root() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let root = temp_dir.path().join("project");
+        let nested = root.join("crates/core/src");
+        fs::create_dir_all(&nested)?;
+        fs::write(root.join(CONFIG_FILE_NAME), "delimiter = \"---\"")?;
+
+        let found = Config::discover(&nested, Some(&root)).unwrap();
+        assert_eq!(found, root.join(CONFIG_FILE_NAME).canonicalize()?);
+
+        // A config above the root is never picked up
+        let outside = temp_dir.path().join("other");
+        fs::create_dir_all(outside.join("sub"))?;
+        fs::write(
+            temp_dir.path().join(CONFIG_FILE_NAME),
+            "delimiter = \"---\"",
+        )?;
+        assert!(Config::discover(outside.join("sub"), Some(&outside)).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_key_is_rejected() {
+        let config_content = r#"
+            delimiter = "---"
+            extensions = ["rs"]
+            extenions = ["py"]
+        "#;
+
+        let err = Config::from_str(config_content).unwrap_err();
+        assert!(format!("{:#}", err).contains("unknown field `extenions`"));
+    }
+
+    #[test]
+    fn test_empty_values_are_rejected() {
+        let err = Config::from_str("delimiter = \"\"\nextensions = [\"rs\"]")
+            .unwrap_err();
+        assert!(err.to_string().contains("delimiter"));
+
+        let err =
+            Config::from_str("delimiter = \"---\"\nextensions = []").unwrap_err();
+        assert!(err.to_string().contains("extensions"));
+
+        // Globs alone are enough to select files
+        let config = Config::from_str(
+            "delimiter = \"---\"\nextensions = []\ninclude_globs = [\"Dockerfile\"]",
+        );
+        assert!(config.is_ok());
+    }
+
     #[test]
     fn test_custom_prompt_config() -> Result<()> {
         let config_content = r#"
@@ -130,4 +676,41 @@ mod tests {
         assert_eq!(config.llm_prompt, "Custom prompt for analysis");
         Ok(())
     }
+
+    #[test]
+    fn test_prompt_file_relative_to_config() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        fs::create_dir_all(temp_dir.path().join("prompts"))?;
+        fs::write(temp_dir.path().join("prompts/review.md"), "Review this")?;
+        fs::write(
+            temp_dir.path().join(".amc.toml"),
+            r#"
+            delimiter = "---"
+            extensions = ["rs"]
+            llm_prompt = "Inline prompt"
+            llm_prompt_file = "prompts/review.md"
+            "#,
+        )?;
+
+        let config = Config::from_file(temp_dir.path().join(".amc.toml"))?;
+        assert_eq!(config.llm_prompt, "Review this");
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_prompt_file_is_an_error() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        fs::write(
+            temp_dir.path().join(".amc.toml"),
+            r#"
+            delimiter = "---"
+            extensions = ["rs"]
+            llm_prompt_file = "missing.md"
+            "#,
+        )?;
+
+        let err = Config::from_file(temp_dir.path().join(".amc.toml")).unwrap_err();
+        assert!(err.to_string().contains("missing.md"));
+        Ok(())
+    }
 }
@@ -0,0 +1,177 @@
+//! File-level comparison of two generated outputs for `amc diff`.
+
+use crate::parse::ParsedFile;
+use similar::{ChangeTag, TextDiff};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// How one file differs between the old and the new output
+#[derive(Debug, PartialEq, Eq)]
+pub struct FileChange {
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+    pub lines_added: usize,
+    pub lines_removed: usize,
+}
+
+/// Changed files between two outputs, in path order. Files whose content is
+/// identical are left out even if their commit changed.
+pub fn diff_outputs(old: &[ParsedFile], new: &[ParsedFile]) -> Vec<FileChange> {
+    let old = by_path(old);
+    let new = by_path(new);
+    let mut paths: Vec<&Path> = old.keys().chain(new.keys()).copied().collect();
+    paths.sort();
+    paths.dedup();
+
+    paths
+        .into_iter()
+        .filter_map(|path| {
+            let (kind, old_content, new_content) =
+                match (old.get(path), new.get(path)) {
+                    (Some(old), Some(new)) if old == new => return None,
+                    (Some(old), Some(new)) => (ChangeKind::Modified, *old, *new),
+                    (Some(old), None) => (ChangeKind::Removed, *old, ""),
+                    (None, Some(new)) => (ChangeKind::Added, "", *new),
+                    (None, None) => return None,
+                };
+
+            let (mut lines_added, mut lines_removed) = (0, 0);
+            for change in
+                TextDiff::from_lines(old_content, new_content).iter_all_changes()
+            {
+                match change.tag() {
+                    ChangeTag::Insert => lines_added += 1,
+                    ChangeTag::Delete => lines_removed += 1,
+                    ChangeTag::Equal => {}
+                }
+            }
+            Some(FileChange {
+                path: path.to_path_buf(),
+                kind,
+                lines_added,
+                lines_removed,
+            })
+        })
+        .collect()
+}
+
+/// Unified diff of one file's content between the outputs
+pub fn unified_diff(path: &Path, old: &str, new: &str) -> String {
+    let path = path.display().to_string();
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(3)
+        .header(&format!("a/{}", path), &format!("b/{}", path))
+        .to_string()
+}
+
+fn by_path(files: &[ParsedFile]) -> BTreeMap<&Path, &str> {
+    files
+        .iter()
+        .map(|file| (file.path.as_path(), file.content.as_str()))
+        .collect()
+}
+
+/// Summary table for a list of changes, one line per file plus totals
+pub struct ChangeSummary<'a>(pub &'a [FileChange]);
+
+impl fmt::Display for ChangeSummary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = |kind| self.0.iter().filter(|c| c.kind == kind).count();
+        for change in self.0 {
+            let marker = match change.kind {
+                ChangeKind::Added => 'A',
+                ChangeKind::Removed => 'D',
+                ChangeKind::Modified => 'M',
+            };
+            writeln!(
+                f,
+                "{}  {}  (+{} -{})",
+                marker,
+                change.path.display(),
+                change.lines_added,
+                change.lines_removed
+            )?;
+        }
+        writeln!(
+            f,
+            "{} file(s) changed: {} added, {} removed, {} modified",
+            self.0.len(),
+            count(ChangeKind::Added),
+            count(ChangeKind::Removed),
+            count(ChangeKind::Modified)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, content: &str) -> ParsedFile {
+        ParsedFile {
+            path: path.into(),
+            commit: None,
+            content: content.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_diff_outputs() {
+        let old = [
+            file("a.rs", "a\nb\n"),
+            file("gone.rs", "x\ny\n"),
+            file("same.rs", "s\n"),
+        ];
+        let new = [
+            file("a.rs", "a\nc\nd\n"),
+            file("new.rs", "n\n"),
+            file("same.rs", "s\n"),
+        ];
+
+        let changes = diff_outputs(&old, &new);
+        assert_eq!(
+            changes,
+            vec![
+                FileChange {
+                    path: "a.rs".into(),
+                    kind: ChangeKind::Modified,
+                    lines_added: 2,
+                    lines_removed: 1,
+                },
+                FileChange {
+                    path: "gone.rs".into(),
+                    kind: ChangeKind::Removed,
+                    lines_added: 0,
+                    lines_removed: 2,
+                },
+                FileChange {
+                    path: "new.rs".into(),
+                    kind: ChangeKind::Added,
+                    lines_added: 1,
+                    lines_removed: 0,
+                },
+            ]
+        );
+
+        let summary = ChangeSummary(&changes).to_string();
+        assert!(summary.starts_with("M  a.rs  (+2 -1)\n"));
+        assert!(summary
+            .ends_with("3 file(s) changed: 1 added, 1 removed, 1 modified\n"));
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let diff = unified_diff(Path::new("a.rs"), "a\nb\n", "a\nc\n");
+        assert!(diff.starts_with("--- a/a.rs\n+++ b/a.rs\n@@ -1,2 +1,2 @@\n"));
+        assert!(diff.contains("-b\n+c\n"));
+    }
+}
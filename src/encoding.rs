@@ -0,0 +1,117 @@
+//! Decoding file content that isn't plain UTF-8.
+//!
+//! A byte order mark wins, valid UTF-8 is taken as is, anything else is
+//! transcoded from the `--encoding` override or from `chardetng`'s guess.
+//! Content with NUL bytes and no BOM is treated as binary.
+
+use chardetng::EncodingDetector;
+use encoding_rs::Encoding;
+
+/// How many leading bytes are checked for NUL when looking for binary
+/// content, the same window Git uses
+const BINARY_SCAN_BYTES: usize = 8000;
+
+/// File content converted to UTF-8
+#[derive(Debug, PartialEq, Eq)]
+pub struct Decoded {
+    pub content: String,
+    /// Encoding the content was transcoded from, `None` for UTF-8
+    pub transcoded_from: Option<&'static str>,
+    /// Malformed sequences were replaced with U+FFFD
+    pub had_errors: bool,
+}
+
+/// Convert `bytes` to UTF-8 as described in the module docs. Returns `None`
+/// for binary content.
+pub fn decode(
+    bytes: &[u8],
+    fallback: Option<&'static Encoding>,
+) -> Option<Decoded> {
+    if let Some((encoding, bom_length)) = Encoding::for_bom(bytes) {
+        return Some(transcode(encoding, &bytes[bom_length..]));
+    }
+    if let Ok(content) = std::str::from_utf8(bytes) {
+        return Some(Decoded {
+            content: content.to_string(),
+            transcoded_from: None,
+            had_errors: false,
+        });
+    }
+    if fallback.is_none() && bytes.iter().take(BINARY_SCAN_BYTES).any(|&b| b == 0) {
+        return None;
+    }
+
+    let encoding = fallback.unwrap_or_else(|| {
+        let mut detector = EncodingDetector::new();
+        detector.feed(bytes, true);
+        detector.guess(None, true)
+    });
+    Some(transcode(encoding, bytes))
+}
+
+fn transcode(encoding: &'static Encoding, bytes: &[u8]) -> Decoded {
+    let (content, had_errors) = encoding.decode_without_bom_handling(bytes);
+    Decoded {
+        content: content.into_owned(),
+        transcoded_from: (encoding != encoding_rs::UTF_8).then(|| encoding.name()),
+        had_errors,
+    }
+}
+
+/// Parse an `--encoding` label such as `latin1`, `utf-16le` or `shift_jis`
+pub fn parse_encoding(label: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| format!("unknown encoding '{}'", label))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utf8_is_kept() {
+        let decoded = decode("fn main() {} // ünïcödé".as_bytes(), None).unwrap();
+        assert_eq!(decoded.content, "fn main() {} // ünïcödé");
+        assert_eq!(decoded.transcoded_from, None);
+
+        // A UTF-8 BOM is dropped without counting as a transcode
+        let decoded = decode(b"\xEF\xBB\xBFx = 1", None).unwrap();
+        assert_eq!(decoded.content, "x = 1");
+        assert_eq!(decoded.transcoded_from, None);
+    }
+
+    #[test]
+    fn test_utf16_with_bom() {
+        let bytes: Vec<u8> = [0xFF, 0xFE]
+            .into_iter()
+            .chain("hé\n".encode_utf16().flat_map(u16::to_le_bytes))
+            .collect();
+        let decoded = decode(&bytes, None).unwrap();
+        assert_eq!(decoded.content, "hé\n");
+        assert_eq!(decoded.transcoded_from, Some("UTF-16LE"));
+        assert!(!decoded.had_errors);
+    }
+
+    #[test]
+    fn test_latin1_is_detected_or_forced() {
+        let bytes = b"# Caf\xE9 cr\xE8me br\xFBl\xE9e, d\xE9j\xE0 vu\n";
+        let decoded = decode(bytes, None).unwrap();
+        assert_eq!(decoded.content, "# Café crème brûlée, déjà vu\n");
+        assert_eq!(decoded.transcoded_from, Some("windows-1252"));
+
+        let koi8 = parse_encoding("koi8-r").unwrap();
+        let decoded = decode(b"\xF0\xD2\xC9\xD7\xC5\xD4", Some(koi8)).unwrap();
+        assert_eq!(decoded.content, "Привет");
+        assert_eq!(decoded.transcoded_from, Some("KOI8-R"));
+    }
+
+    #[test]
+    fn test_binary_and_unknown_labels() {
+        assert_eq!(
+            decode(b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR\xFF", None),
+            None
+        );
+        assert!(parse_encoding("latin1").is_ok());
+        assert!(parse_encoding("klingon").is_err());
+    }
+}
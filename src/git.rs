@@ -0,0 +1,786 @@
+use crate::cache::{self, HistoryCache};
+use anyhow::{Context, Result};
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, Utc};
+use clap::ValueEnum;
+use git2::{
+    AttrCheckFlags, AttrValue, Commit, Oid, Repository, Sort, Status, StatusOptions,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Commit metadata attached to each file in the output
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GitInfo {
+    pub commit_hash: String,
+    /// Commit time in seconds since the Unix epoch
+    pub commit_time: i64,
+    /// Timezone offset of the commit in minutes east of UTC
+    pub commit_offset_minutes: i32,
+    pub author_name: String,
+    pub author_email: String,
+    /// First line of the commit message
+    pub summary: String,
+}
+
+impl GitInfo {
+    /// Author formatted as `Name <email>`
+    pub fn author(&self) -> String {
+        format!("{} <{}>", self.author_name, self.author_email)
+    }
+
+    /// Abbreviated commit hash, as `git log --oneline` shows it
+    pub fn short_hash(&self) -> &str {
+        self.commit_hash.get(..7).unwrap_or(&self.commit_hash)
+    }
+
+    /// Render the commit time in the requested format
+    pub fn format_time(&self, format: DateFormat) -> String {
+        format.render(self.commit_time, self.commit_offset_minutes, Utc::now())
+    }
+
+    fn from_commit(commit: &Commit) -> Self {
+        let author = commit.author();
+        Self {
+            commit_hash: commit.id().to_string(),
+            commit_time: commit.time().seconds(),
+            commit_offset_minutes: commit.time().offset_minutes(),
+            author_name: author.name().unwrap_or("unknown").to_string(),
+            author_email: author.email().unwrap_or("unknown").to_string(),
+            summary: commit.summary().unwrap_or("").to_string(),
+        }
+    }
+}
+
+/// How commit timestamps are rendered, selected with `--date-format`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum DateFormat {
+    /// Seconds since the Unix epoch
+    Unix,
+    /// RFC 3339 in the commit's own timezone, e.g. `2024-10-25T09:29:56+02:00`
+    #[default]
+    Rfc3339,
+    /// Age relative to now, e.g. `3 days ago`
+    Relative,
+}
+
+impl DateFormat {
+    /// What a rendered commit time says, for the built-in prompt
+    pub fn description(self) -> &'static str {
+        match self {
+            DateFormat::Unix => "Unix timestamp of the last change",
+            DateFormat::Rfc3339 => "date and time of the last change (RFC 3339)",
+            DateFormat::Relative => {
+                "how long ago the last change was made, e.g. \"3 days ago\""
+            }
+        }
+    }
+
+    /// Render a filesystem timestamp, in the local timezone for RFC 3339
+    pub fn render_system_time(self, time: SystemTime) -> String {
+        let time: DateTime<Local> = time.into();
+        let offset_minutes = time.offset().local_minus_utc() / 60;
+        self.render(time.timestamp(), offset_minutes, Utc::now())
+    }
+
+    fn render(
+        self,
+        seconds: i64,
+        offset_minutes: i32,
+        now: DateTime<Utc>,
+    ) -> String {
+        let Some(time) = DateTime::from_timestamp(seconds, 0) else {
+            return seconds.to_string();
+        };
+
+        match self {
+            DateFormat::Unix => seconds.to_string(),
+            DateFormat::Rfc3339 => {
+                let offset = FixedOffset::east_opt(offset_minutes * 60)
+                    .unwrap_or_else(|| FixedOffset::east_opt(0).unwrap());
+                time.with_timezone(&offset).to_rfc3339()
+            }
+            DateFormat::Relative => relative_age((now - time).num_seconds()),
+        }
+    }
+}
+
+/// Approximate unit lengths used for relative ages, longest first
+const AGE_UNITS: &[(i64, &str)] = &[
+    (365 * 24 * 3600, "year"),
+    (30 * 24 * 3600, "month"),
+    (7 * 24 * 3600, "week"),
+    (24 * 3600, "day"),
+    (3600, "hour"),
+    (60, "minute"),
+];
+
+fn relative_age(seconds: i64) -> String {
+    if seconds < 0 {
+        return "in the future".to_string();
+    }
+
+    for (unit_seconds, name) in AGE_UNITS {
+        let count = seconds / unit_seconds;
+        if count > 0 {
+            let plural = if count == 1 { "" } else { "s" };
+            return format!("{} {}{} ago", count, name, plural);
+        }
+    }
+    "just now".to_string()
+}
+
+/// Lower bound given with `--since`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Since {
+    /// Seconds since the Unix epoch
+    Time(i64),
+    /// Only commits that descend from this one count as newer
+    Commit(Oid),
+}
+
+/// Parse a `--since` date: RFC 3339, `YYYY-MM-DD[ HH:MM[:SS]]` in local time,
+/// or an age such as `2 weeks ago`
+fn parse_since_date(spec: &str, now: DateTime<Utc>) -> Option<i64> {
+    let spec = spec.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(spec) {
+        return Some(time.timestamp());
+    }
+
+    let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(spec, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(spec, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        });
+    if let Some(naive) = naive {
+        return naive
+            .and_local_timezone(Local)
+            .earliest()
+            .map(|time| time.timestamp());
+    }
+
+    let words: Vec<&str> = spec.split_whitespace().collect();
+    let (count, unit) = match words.as_slice() {
+        [count, unit] | [count, unit, "ago"] => (count.parse::<i64>().ok()?, *unit),
+        _ => return None,
+    };
+    let unit = unit.strip_suffix('s').unwrap_or(unit);
+    let (unit_seconds, _) = AGE_UNITS.iter().find(|(_, name)| *name == unit)?;
+    Some(now.timestamp() - count * unit_seconds)
+}
+
+/// Resolves the last commit that modified each file, caching per path
+pub struct GitHistory {
+    repo: Repository,
+    workdir: PathBuf,
+    /// Commit the history search starts from, HEAD unless overridden.
+    /// `None` in a repository without any commits yet.
+    start: Option<Oid>,
+    cache: Arc<HistoryCache>,
+    /// Histories of submodules, by their path in the work tree, which answer
+    /// for the files inside them
+    submodules: Vec<(PathBuf, GitHistory)>,
+}
+
+impl GitHistory {
+    /// Open the repository containing `path`
+    pub fn discover<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open(Repository::discover(path.as_ref())?)
+    }
+
+    fn open(repo: Repository) -> Result<Self> {
+        let workdir = repo
+            .workdir()
+            .context("Bare repositories are not supported")?
+            .canonicalize()?;
+        let start = repo
+            .head()
+            .and_then(|head| head.peel_to_commit())
+            .map(|commit| commit.id())
+            .ok();
+
+        Ok(Self {
+            repo,
+            workdir,
+            start,
+            cache: Arc::default(),
+            submodules: Vec::new(),
+        })
+    }
+
+    /// Answer for files inside initialized submodules, nested ones included,
+    /// from the submodule's own history. It starts at the checked-out commit,
+    /// which is what the work tree holds.
+    pub fn with_submodules(mut self) -> Result<Self> {
+        for submodule in self.repo.submodules()? {
+            let Ok(repo) = submodule.open() else {
+                continue;
+            };
+            let history = Self::open(repo)?.with_submodules()?;
+            self.submodules
+                .push((submodule.path().to_path_buf(), history));
+        }
+        Ok(self)
+    }
+
+    /// Search history from `rev` (a branch, tag or any revspec) instead of HEAD
+    pub fn with_rev(mut self, rev: &str) -> Result<Self> {
+        let start = self
+            .repo
+            .revparse_single(rev)
+            .and_then(|object| object.peel_to_commit())
+            .map(|commit| commit.id())
+            .with_context(|| format!("Failed to resolve revision '{}'", rev))?;
+        self.start = Some(start);
+        self.cache = Arc::default();
+        Ok(self)
+    }
+
+    /// Share `cache` with other histories of the same repository and start
+    /// commit, e.g. one loaded from disk
+    pub fn with_cache(mut self, cache: Arc<HistoryCache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Full hash of the commit the history search starts from
+    pub fn start(&self) -> Option<String> {
+        self.start.map(|start| start.to_string())
+    }
+
+    /// Where the on-disk lookup cache of this repository lives
+    pub fn cache_path(&self) -> PathBuf {
+        cache::cache_path(self.repo.path())
+    }
+
+    /// Find the most recent commit reachable from the start revision that
+    /// changed `path`, following `git log -1 <rev> -- <path>` semantics.
+    /// Returns `None` for files that are not part of that revision.
+    pub fn get_git_info(&mut self, path: &Path) -> Result<Option<GitInfo>> {
+        let relative = self.repo_relative(path)?;
+        // Submodule files stay out of the cache, which is keyed by this
+        // repository's start commit only
+        if let Some((_, submodule)) = self
+            .submodules
+            .iter_mut()
+            .find(|(root, _)| relative.starts_with(root))
+        {
+            return submodule.get_git_info(path);
+        }
+        if let Some(cached) = self.cache.get(&relative) {
+            return Ok(cached);
+        }
+
+        let info = self.find_last_commit(&relative)?;
+        self.cache.insert(relative, info.clone());
+        Ok(info)
+    }
+
+    /// Interpret a `--since` value as a date first and as a revision otherwise
+    pub fn resolve_since(&self, spec: &str) -> Result<Since> {
+        if let Some(time) = parse_since_date(spec, Utc::now()) {
+            return Ok(Since::Time(time));
+        }
+        let commit = self
+            .repo
+            .revparse_single(spec)
+            .and_then(|object| object.peel_to_commit())
+            .with_context(|| {
+                format!("'{}' is neither a date nor a known revision", spec)
+            })?;
+        Ok(Since::Commit(commit.id()))
+    }
+
+    /// Whether the commit described by `info` is newer than `since`. A commit
+    /// of a submodule can't descend from a revision of this repository, so it
+    /// is compared by time with that revision instead.
+    pub fn changed_since(&self, info: &GitInfo, since: Since) -> Result<bool> {
+        match since {
+            Since::Time(time) => Ok(info.commit_time > time),
+            Since::Commit(base) => {
+                let commit = Oid::from_str(&info.commit_hash)?;
+                if self.repo.find_commit(commit).is_err() {
+                    let base_time = self.repo.find_commit(base)?.time().seconds();
+                    return Ok(info.commit_time > base_time);
+                }
+                Ok(
+                    commit != base
+                        && self.repo.graph_descendant_of(commit, base)?,
+                )
+            }
+        }
+    }
+
+    /// Whether Git does not know about `path` (untracked or ignored)
+    pub fn is_untracked(&self, path: &Path) -> Result<bool> {
+        let relative = self.repo_relative(path)?;
+        if let Some((_, submodule)) = self
+            .submodules
+            .iter()
+            .find(|(root, _)| relative.starts_with(root))
+        {
+            return submodule.is_untracked(path);
+        }
+        let status = self.repo.status_file(&relative)?;
+        Ok(status.intersects(Status::WT_NEW | Status::IGNORED))
+    }
+
+    /// Canonical root of the work tree
+    pub fn workdir(&self) -> &Path {
+        &self.workdir
+    }
+
+    /// `path` relative to the work tree root
+    pub fn repo_relative(&self, path: &Path) -> Result<PathBuf> {
+        let absolute = path.canonicalize()?;
+        let relative = absolute.strip_prefix(&self.workdir).with_context(|| {
+            format!("{} is outside the repository", absolute.display())
+        })?;
+        Ok(relative.to_path_buf())
+    }
+
+    fn find_last_commit(&self, relative: &Path) -> Result<Option<GitInfo>> {
+        let Some(start) = self.start else {
+            return Ok(None);
+        };
+        let start = self.repo.find_commit(start)?;
+        if blob_id(&start, relative).is_none() {
+            return Ok(None);
+        }
+
+        let mut revwalk = self.repo.revwalk()?;
+        revwalk.set_sorting(Sort::TIME)?;
+        revwalk.push(start.id())?;
+
+        for oid in revwalk {
+            let commit = self.repo.find_commit(oid?)?;
+            let Some(id) = blob_id(&commit, relative) else {
+                continue;
+            };
+
+            // A commit touched the file when it differs from every parent;
+            // merges that kept one side's version are skipped like `git log`
+            let touched = commit
+                .parents()
+                .all(|parent| blob_id(&parent, relative) != Some(id));
+            if touched {
+                return Ok(Some(GitInfo::from_commit(&commit)));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+fn blob_id(commit: &Commit, relative: &Path) -> Option<Oid> {
+    commit
+        .tree()
+        .ok()?
+        .get_path(relative)
+        .ok()
+        .map(|entry| entry.id())
+}
+
+/// `.gitattributes` flags marking files that don't belong in the output
+const EXCLUDING_ATTRIBUTES: &[&str] = &["linguist-generated", "export-ignore"];
+
+/// Looks up `.gitattributes` for files below a scanned directory
+pub struct GitAttributes {
+    repo: Repository,
+    /// Scanned directory relative to the repository working tree
+    prefix: PathBuf,
+}
+
+impl GitAttributes {
+    /// Attributes for the repository containing `dir`, if there is one
+    pub fn open<P: AsRef<Path>>(dir: P) -> Option<Self> {
+        let repo = Repository::discover(dir.as_ref()).ok()?;
+        let workdir = repo.workdir()?.canonicalize().ok()?;
+        let prefix = dir
+            .as_ref()
+            .canonicalize()
+            .ok()?
+            .strip_prefix(&workdir)
+            .ok()?
+            .to_path_buf();
+        Some(Self { repo, prefix })
+    }
+
+    /// The first excluding attribute set on `relative`, a path relative to
+    /// the directory passed to [`GitAttributes::open`]
+    pub fn excluding_attribute(&self, relative: &Path) -> Option<&'static str> {
+        let path = self.prefix.join(relative);
+        EXCLUDING_ATTRIBUTES.iter().copied().find(|name| {
+            let value = self
+                .repo
+                .get_attr(&path, name, AttrCheckFlags::FILE_THEN_INDEX)
+                .ok()
+                .flatten();
+            matches!(
+                AttrValue::from_string(value),
+                AttrValue::True | AttrValue::String("true")
+            )
+        })
+    }
+}
+
+/// Overall repository state, written before the files with `--repo-info`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RepoInfo {
+    /// Checked-out branch, `None` when HEAD is detached
+    pub branch: Option<String>,
+    /// Short hash of HEAD, `None` before the first commit
+    pub head: Option<String>,
+    /// Commits reachable from HEAD
+    pub commits: usize,
+    /// Modified, staged or untracked files in the working tree
+    pub uncommitted_changes: usize,
+}
+
+impl RepoInfo {
+    /// Read the state of the repository containing `path`
+    pub fn collect<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let repo = Repository::discover(path.as_ref())
+            .context("Failed to open Git repository")?;
+
+        let (branch, head, commits) = match repo.head() {
+            Ok(head) => {
+                let commit = head.peel_to_commit()?;
+                let mut revwalk = repo.revwalk()?;
+                revwalk.push(commit.id())?;
+                (
+                    head.is_branch()
+                        .then(|| head.shorthand().map(str::to_string))
+                        .flatten(),
+                    Some(commit.id().to_string()[..7].to_string()),
+                    revwalk.count(),
+                )
+            }
+            // Unborn branch: HEAD names a branch without commits yet
+            Err(_) => {
+                let branch = repo
+                    .find_reference("HEAD")
+                    .ok()
+                    .and_then(|head| head.symbolic_target().map(str::to_string))
+                    .map(|target| {
+                        target.trim_start_matches("refs/heads/").to_string()
+                    });
+                (branch, None, 0)
+            }
+        };
+
+        let mut options = StatusOptions::new();
+        options.include_untracked(true).exclude_submodules(true);
+        let uncommitted_changes = repo.statuses(Some(&mut options))?.len();
+
+        Ok(Self {
+            branch,
+            head,
+            commits,
+            uncommitted_changes,
+        })
+    }
+}
+
+impl fmt::Display for RepoInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Branch: {}",
+            self.branch.as_deref().unwrap_or("(detached)")
+        )?;
+        writeln!(
+            f,
+            "HEAD: {}",
+            self.head.as_deref().unwrap_or("(no commits)")
+        )?;
+        writeln!(f, "Commits: {}", self.commits)?;
+        match self.uncommitted_changes {
+            0 => writeln!(f, "Working tree: clean"),
+            n => writeln!(f, "Working tree: {} uncommitted change(s)", n),
+        }
+    }
+}
+
+/// Working tree root of the repository containing `path`
+pub fn repository_root<P: AsRef<Path>>(path: P) -> Option<PathBuf> {
+    let repo = Repository::discover(path.as_ref()).ok()?;
+    repo.workdir().map(Path::to_path_buf)
+}
+
+/// Work trees of the submodules of the repository containing `path`, as
+/// absolute paths. Empty outside a repository.
+pub fn submodule_workdirs<P: AsRef<Path>>(path: P) -> Vec<PathBuf> {
+    let Ok(repo) = Repository::discover(path.as_ref()) else {
+        return Vec::new();
+    };
+    let (Some(workdir), Ok(submodules)) = (repo.workdir(), repo.submodules())
+    else {
+        return Vec::new();
+    };
+    submodules
+        .iter()
+        .filter_map(|submodule| workdir.join(submodule.path()).canonicalize().ok())
+        .collect()
+}
+
+pub fn is_git_repository(path: &str) -> bool {
+    Repository::discover(path).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::{Signature, Time};
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn commit_files(
+        repo: &Repository,
+        files: &[(&str, &str)],
+        message: &str,
+        time: i64,
+    ) -> Result<Oid> {
+        let workdir = repo.workdir().unwrap();
+        let mut index = repo.index()?;
+        for (path, content) in files {
+            fs::write(workdir.join(path), content)?;
+            index.add_path(Path::new(path))?;
+        }
+        index.write()?;
+
+        let tree = repo.find_tree(index.write_tree()?)?;
+        let signature =
+            Signature::new("Test User", "test@example.com", &Time::new(time, 0))?;
+        let parent = repo.head().ok().and_then(|h| h.peel_to_commit().ok());
+        let parents: Vec<&Commit> = parent.iter().collect();
+
+        Ok(repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parents,
+        )?)
+    }
+
+    #[test]
+    fn test_reports_last_modifying_commit_per_file() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let repo = Repository::init(temp_dir.path())?;
+
+        let first =
+            commit_files(&repo, &[("a.rs", "a"), ("b.rs", "b")], "Add", 100)?;
+        let second = commit_files(&repo, &[("b.rs", "b2")], "Change b", 200)?;
+
+        let mut history = GitHistory::discover(temp_dir.path())?;
+
+        let a = history
+            .get_git_info(&temp_dir.path().join("a.rs"))?
+            .unwrap();
+        assert_eq!(a.commit_hash, first.to_string());
+        assert_eq!(a.commit_time, 100);
+        assert_eq!(a.summary, "Add");
+        assert_eq!(a.author(), "Test User <test@example.com>");
+
+        let b = history
+            .get_git_info(&temp_dir.path().join("b.rs"))?
+            .unwrap();
+        assert_eq!(b.commit_hash, second.to_string());
+        assert_eq!(b.commit_time, 200);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_history_from_older_revision() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let repo = Repository::init(temp_dir.path())?;
+
+        let first = commit_files(&repo, &[("a.rs", "a")], "Add", 100)?;
+        repo.tag_lightweight("v1", &repo.find_object(first, None)?, false)?;
+        commit_files(&repo, &[("a.rs", "a2")], "Change a", 200)?;
+
+        let mut history = GitHistory::discover(temp_dir.path())?.with_rev("v1")?;
+        let a = history
+            .get_git_info(&temp_dir.path().join("a.rs"))?
+            .unwrap();
+        assert_eq!(a.commit_hash, first.to_string());
+
+        assert!(GitHistory::discover(temp_dir.path())?
+            .with_rev("no-such-rev")
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_repository_without_commits() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        Repository::init(temp_dir.path())?;
+        fs::write(temp_dir.path().join("a.rs"), "a")?;
+
+        let mut history = GitHistory::discover(temp_dir.path())?;
+        assert!(history
+            .get_git_info(&temp_dir.path().join("a.rs"))?
+            .is_none());
+        assert!(history.is_untracked(&temp_dir.path().join("a.rs"))?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_changed_since() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let repo = Repository::init(temp_dir.path())?;
+        commit_files(&repo, &[("a.rs", "a"), ("b.rs", "b")], "Add", 100)?;
+        let base = commit_files(&repo, &[("c.rs", "c")], "Add c", 200)?;
+        commit_files(&repo, &[("b.rs", "b2")], "Change b", 300)?;
+
+        let mut history = GitHistory::discover(temp_dir.path())?;
+        let mut changed = |path: &str, since: Since| -> Result<bool> {
+            let info = history.get_git_info(&temp_dir.path().join(path))?.unwrap();
+            history.changed_since(&info, since)
+        };
+
+        assert!(!changed("a.rs", Since::Time(150))?);
+        assert!(changed("c.rs", Since::Time(150))?);
+        assert!(!changed("c.rs", Since::Commit(base))?);
+        assert!(changed("b.rs", Since::Commit(base))?);
+
+        let history = GitHistory::discover(temp_dir.path())?;
+        let short = base.to_string()[..8].to_string();
+        assert_eq!(history.resolve_since(&short)?, Since::Commit(base));
+        assert!(matches!(
+            history.resolve_since("1970-01-02")?,
+            Since::Time(_)
+        ));
+        assert!(history.resolve_since("not-a-rev").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_repo_info() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let repo = Repository::init(temp_dir.path())?;
+        let info = RepoInfo::collect(temp_dir.path())?;
+        assert_eq!(info.head, None);
+        assert_eq!(info.commits, 0);
+
+        commit_files(&repo, &[("a.rs", "a")], "First", 100)?;
+        let second = commit_files(&repo, &[("a.rs", "b")], "Second", 200)?;
+        fs::write(temp_dir.path().join("new.rs"), "new")?;
+
+        let info = RepoInfo::collect(temp_dir.path())?;
+        assert_eq!(info.branch, repo.head()?.shorthand().map(str::to_string));
+        assert_eq!(info.head.as_deref(), Some(&second.to_string()[..7]));
+        assert_eq!(info.commits, 2);
+        assert_eq!(info.uncommitted_changes, 1);
+        assert!(info
+            .to_string()
+            .ends_with("Commits: 2\nWorking tree: 1 uncommitted change(s)\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_since_date() {
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+
+        assert_eq!(
+            parse_since_date("2023-11-14T22:13:20Z", now),
+            Some(1_700_000_000)
+        );
+        assert_eq!(
+            parse_since_date("2 weeks ago", now),
+            Some(1_700_000_000 - 14 * 24 * 3600)
+        );
+        assert_eq!(parse_since_date("1 day", now), Some(1_700_000_000 - 86400));
+        assert!(parse_since_date("2023-11-14", now).is_some());
+        assert!(parse_since_date("2023-11-14 10:30", now).is_some());
+        assert_eq!(parse_since_date("main", now), None);
+        assert_eq!(parse_since_date("2 fortnights ago", now), None);
+    }
+
+    #[test]
+    fn test_date_formats() {
+        let now =
+            DateTime::from_timestamp(1_700_000_000 + 3 * 24 * 3600, 0).unwrap();
+
+        assert_eq!(
+            DateFormat::Unix.render(1_700_000_000, 120, now),
+            "1700000000"
+        );
+        assert_eq!(
+            DateFormat::Rfc3339.render(1_700_000_000, 120, now),
+            "2023-11-15T00:13:20+02:00"
+        );
+        assert_eq!(
+            DateFormat::Relative.render(1_700_000_000, 120, now),
+            "3 days ago"
+        );
+        assert_eq!(relative_age(30), "just now");
+        assert_eq!(relative_age(3600), "1 hour ago");
+    }
+
+    #[test]
+    fn test_render_system_time() {
+        let mtime =
+            SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        assert_eq!(DateFormat::Unix.render_system_time(mtime), "1700000000");
+        // Local timezone, so only the shape is fixed
+        let rfc3339 = DateFormat::Rfc3339.render_system_time(mtime);
+        assert!(DateTime::parse_from_rfc3339(&rfc3339)
+            .is_ok_and(|time| time.timestamp() == 1_700_000_000));
+    }
+
+    #[test]
+    fn test_excluding_attributes() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        Repository::init(temp_dir.path())?;
+        fs::create_dir_all(temp_dir.path().join("src"))?;
+        fs::write(
+            temp_dir.path().join(".gitattributes"),
+            "*.pb.rs linguist-generated\nvendor/** export-ignore\n\
+             src/kept.pb.rs linguist-generated=false\n",
+        )?;
+
+        let attributes = GitAttributes::open(temp_dir.path()).unwrap();
+        let check = |path: &str| attributes.excluding_attribute(Path::new(path));
+        assert_eq!(check("src/api.pb.rs"), Some("linguist-generated"));
+        assert_eq!(check("vendor/lib.rs"), Some("export-ignore"));
+        assert_eq!(check("src/kept.pb.rs"), None);
+        assert_eq!(check("src/main.rs"), None);
+
+        // Paths are resolved relative to the opened directory
+        let nested = GitAttributes::open(temp_dir.path().join("src")).unwrap();
+        assert_eq!(
+            nested.excluding_attribute(Path::new("api.pb.rs")),
+            Some("linguist-generated")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_untracked_file_has_no_git_info() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let repo = Repository::init(temp_dir.path())?;
+        commit_files(&repo, &[("a.rs", "a")], "Add", 100)?;
+        fs::write(temp_dir.path().join("new.rs"), "new")?;
+
+        let mut history = GitHistory::discover(temp_dir.path())?;
+        assert!(history
+            .get_git_info(&temp_dir.path().join("new.rs"))?
+            .is_none());
+        assert!(history.is_untracked(&temp_dir.path().join("new.rs"))?);
+        assert!(!history.is_untracked(&temp_dir.path().join("a.rs"))?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+//! Running user-supplied shell commands: the `--post-hook` run once an
+//! output file is written, and the `[filters]` each file's content is piped
+//! through.
+//!
+//! Commands go through the platform shell (`sh -c`, or `cmd /C` on
+//! Windows). A post-hook gets the output path as `AMC_OUTPUT` and as the
+//! `{output}` placeholder, which is replaced with a quoted path, so it must
+//! not be quoted again in the command. A filter gets the file's path as
+//! `AMC_FILE`.
+
+use anyhow::{Context, Result};
+use log::info;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Placeholder replaced with the quoted output path
+pub const OUTPUT_PLACEHOLDER: &str = "{output}";
+
+/// Environment variable carrying the unquoted output path
+pub const OUTPUT_ENV: &str = "AMC_OUTPUT";
+
+/// Environment variable carrying the path of the file a filter reads
+pub const FILE_ENV: &str = "AMC_FILE";
+
+/// Run `command` for the file at `output` (`-` for stdout). Its stdout and
+/// stderr are inherited; a non-zero exit status is an error.
+pub fn run(command: &str, output: &str) -> Result<()> {
+    let command = command.replace(OUTPUT_PLACEHOLDER, &shell_quote(output));
+    info!("Running post-hook: {}", command);
+
+    let status = shell(&command)
+        .env(OUTPUT_ENV, output)
+        .status()
+        .with_context(|| format!("Failed to run post-hook: {}", command))?;
+    match status.code() {
+        Some(0) => {
+            info!("Post-hook exited with status 0");
+            Ok(())
+        }
+        Some(code) => anyhow::bail!("Post-hook exited with status {}", code),
+        None => anyhow::bail!("Post-hook was terminated by a signal"),
+    }
+}
+
+/// Pipe `content` of the file at `path` through `command` and return its
+/// stdout. Fails when the command can't start, exits non-zero or prints
+/// something other than UTF-8; the error includes its stderr.
+pub fn filter(command: &str, content: &str, path: &Path) -> Result<String> {
+    let mut child = shell(command)
+        .env(FILE_ENV, path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run filter: {}", command))?;
+
+    // Feed stdin from another thread so a command that writes before reading
+    // everything can't deadlock on a full pipe
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = content.to_string();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output()?;
+    // A filter that exits without reading all of its input is fine
+    let _ = writer.join();
+
+    if !output.status.success() {
+        let status = match output.status.code() {
+            Some(code) => format!("exited with status {}", code),
+            None => "was terminated by a signal".to_string(),
+        };
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!(
+            "`{}` {}{}{}",
+            command,
+            status,
+            if stderr.trim().is_empty() { "" } else { ": " },
+            stderr.trim()
+        );
+    }
+    String::from_utf8(output.stdout)
+        .with_context(|| format!("`{}` printed invalid UTF-8", command))
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+/// Quote `value` as a single shell word
+#[cfg(not(windows))]
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Quote `value` as a single shell word. `cmd` has no escape for `"`, which
+/// cannot appear in Windows paths anyway.
+#[cfg(windows)]
+fn shell_quote(value: &str) -> String {
+    format!("\"{}\"", value)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_output_path_is_quoted() {
+        assert_eq!(shell_quote("out.txt"), "'out.txt'");
+        assert_eq!(shell_quote("it's $HOME.txt"), r"'it'\''s $HOME.txt'");
+    }
+
+    #[test]
+    fn test_hook_sees_output_and_reports_status() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let output = temp_dir.path().join("my 'out'.txt");
+        fs::write(&output, "content")?;
+        let output = output.to_string_lossy();
+
+        run("cp {output} \"$AMC_OUTPUT.copy\"", &output)?;
+        assert_eq!(fs::read_to_string(format!("{}.copy", output))?, "content");
+
+        let err = run("exit 3", &output).unwrap_err();
+        assert_eq!(err.to_string(), "Post-hook exited with status 3");
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_pipes_content_through_the_command() -> Result<()> {
+        let path = Path::new("src/data.json");
+        let filtered =
+            filter("tr a-z A-Z; echo \"$AMC_FILE\"", "{\"a\": 1}\n", path)?;
+        assert_eq!(filtered, "{\"A\": 1}\nsrc/data.json\n");
+
+        // Large input must not deadlock against a command that ignores it
+        let big = "x".repeat(1 << 20);
+        assert_eq!(filter("echo done", &big, path)?, "done\n");
+
+        let err = filter("echo 'bad json' >&2; exit 4", "{", path).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`echo 'bad json' >&2; exit 4` exited with status 4: bad json"
+        );
+        Ok(())
+    }
+}
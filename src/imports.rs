@@ -0,0 +1,207 @@
+//! Extraction of a file's imports for `--annotate-imports`: Rust `use` and
+//! `extern crate`, Python `import` and `from ... import`, and JavaScript or
+//! TypeScript `import`, `export ... from` and `require`.
+//!
+//! The patterns run over the whole content without parsing it, so an import
+//! inside a comment or string still counts. That is close enough for an
+//! overview of what a file depends on.
+
+use regex::Regex;
+use std::sync::OnceLock;
+
+struct Patterns {
+    rust_use: Regex,
+    rust_extern: Regex,
+    python_import: Regex,
+    python_from: Regex,
+    js_import: Regex,
+    js_export: Regex,
+    js_call: Regex,
+}
+
+fn patterns() -> &'static Patterns {
+    static PATTERNS: OnceLock<Patterns> = OnceLock::new();
+    PATTERNS.get_or_init(|| {
+        let regex = |pattern| Regex::new(pattern).expect("valid import pattern");
+        Patterns {
+            rust_use: regex(r"(?m)^\s*(?:pub(?:\([^)]*\))?\s+)?use\s+([^;]+);"),
+            rust_extern: regex(r"(?m)^\s*extern\s+crate\s+(\w+)"),
+            python_import: regex(r"(?m)^[ \t]*import[ \t]+([^\n#;]+)"),
+            python_from: regex(r"(?m)^[ \t]*from[ \t]+(\S+)[ \t]+import\b"),
+            js_import: regex(
+                r#"(?m)^\s*import\s+(?:[^'";]*?\s*from\s*)?['"]([^'"]+)['"]"#,
+            ),
+            js_export: regex(
+                r#"(?m)^\s*export\s+[^'";]*?\s*from\s*['"]([^'"]+)['"]"#,
+            ),
+            js_call: regex(r#"\b(?:require|import)\(\s*['"]([^'"]+)['"]\s*\)"#),
+        }
+    })
+}
+
+/// Imports of `content` in first-seen order without duplicates, or `None`
+/// when `language` (as named by `output::detect_language`) isn't supported
+pub fn extract_imports(language: &str, content: &str) -> Option<Vec<String>> {
+    let imports = match language {
+        "rust" => rust_imports(content),
+        "python" => python_imports(content),
+        "javascript" | "typescript" | "jsx" | "tsx" => js_imports(content),
+        _ => return None,
+    };
+
+    let mut unique: Vec<String> = Vec::with_capacity(imports.len());
+    for import in imports {
+        if !unique.contains(&import) {
+            unique.push(import);
+        }
+    }
+    Some(unique)
+}
+
+/// `use` paths with whitespace collapsed, e.g. `std::io::{self, Write}`,
+/// followed by `extern crate` names
+fn rust_imports(content: &str) -> Vec<String> {
+    let patterns = patterns();
+    let uses = patterns.rust_use.captures_iter(content).map(|captures| {
+        captures[1]
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .replace("{ ", "{")
+            .replace(", }", "}")
+            .replace(" }", "}")
+    });
+    let externs = patterns
+        .rust_extern
+        .captures_iter(content)
+        .map(|captures| captures[1].to_string());
+    uses.chain(externs).collect()
+}
+
+/// Imported module names: `import os, numpy as np` gives `os` and `numpy`,
+/// `from .models import User` gives `.models`
+fn python_imports(content: &str) -> Vec<String> {
+    let patterns = patterns();
+    let mut imports: Vec<(usize, String)> = Vec::new();
+    for captures in patterns.python_import.captures_iter(content) {
+        let start = captures.get(0).map_or(0, |m| m.start());
+        for module in captures[1].split(',') {
+            let module = module.split_whitespace().next().unwrap_or_default();
+            if !module.is_empty() {
+                imports.push((start, module.to_string()));
+            }
+        }
+    }
+    for captures in patterns.python_from.captures_iter(content) {
+        let start = captures.get(0).map_or(0, |m| m.start());
+        imports.push((start, captures[1].to_string()));
+    }
+    // Keep source order across both statement kinds
+    imports.sort_by_key(|(start, _)| *start);
+    imports.into_iter().map(|(_, module)| module).collect()
+}
+
+/// Module specifiers of static imports, re-exports, `require` and dynamic
+/// `import()` calls, in source order
+fn js_imports(content: &str) -> Vec<String> {
+    let patterns = patterns();
+    let mut imports: Vec<(usize, String)> =
+        [&patterns.js_import, &patterns.js_export, &patterns.js_call]
+            .into_iter()
+            .flat_map(|pattern| pattern.captures_iter(content))
+            .map(|captures| {
+                let specifier = captures.get(1).expect("specifier group");
+                (specifier.start(), specifier.as_str().to_string())
+            })
+            .collect();
+    imports.sort_by_key(|(start, _)| *start);
+    imports
+        .into_iter()
+        .map(|(_, specifier)| specifier)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rust_imports() {
+        let content = "\
+extern crate serde;
+use std::fs;
+pub use crate::output::Format;
+pub(crate) use std::{
+    io::{self, Write},
+    path::Path,
+};
+
+fn main() {
+    use std::fs;
+}
+";
+        assert_eq!(
+            extract_imports("rust", content).unwrap(),
+            [
+                "std::fs",
+                "crate::output::Format",
+                "std::{io::{self, Write}, path::Path}",
+                "serde",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_python_imports() {
+        let content = "\
+import os, sys
+import numpy as np  # arrays
+from .models import User
+from typing import (
+    Any,
+)
+
+def f():
+    import json
+";
+        assert_eq!(
+            extract_imports("python", content).unwrap(),
+            ["os", "sys", "numpy", ".models", "typing", "json"]
+        );
+    }
+
+    #[test]
+    fn test_js_imports() {
+        let content = r#"
+import React from "react";
+import {
+  useState,
+  useEffect,
+} from 'react';
+import type { Props } from "./types";
+import "./styles.css";
+export { helper } from "./helper";
+export * from "./all";
+const fs = require("fs");
+const lazy = () => import("./lazy");
+"#;
+        assert_eq!(
+            extract_imports("typescript", content).unwrap(),
+            [
+                "react",
+                "./types",
+                "./styles.css",
+                "./helper",
+                "./all",
+                "fs",
+                "./lazy"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unsupported_languages() {
+        assert_eq!(extract_imports("go", "import \"fmt\""), None);
+        assert_eq!(extract_imports("rust", "fn main() {}"), Some(vec![]));
+    }
+}
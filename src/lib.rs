@@ -0,0 +1,2543 @@
+//! Library behind the `amc` binary: walk a directory, annotate every file
+//! with its last Git commit and render the result in one of several layouts.
+//!
+//! [`generate`] runs the whole pipeline and returns the output as a string:
+//!
+//! ```no_run
+//! use all_my_circuits::{config::Config, generate, Options};
+//!
+//! let options = Options {
+//!     dirs: vec!["src".to_string()],
+//!     ..Options::default()
+//! };
+//! let context = generate(&Config::default(), &options)?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! The individual stages, [`collect_files`], [`gather`] and [`render`], are
+//! public as well for callers that want to inspect or stream the output.
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::{info, warn};
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+pub mod cache;
+pub mod compress;
+pub mod config;
+pub mod diff;
+pub mod encoding;
+pub mod git;
+pub mod hook;
+pub mod imports;
+pub mod minified;
+pub mod output;
+pub mod parse;
+pub mod redact;
+pub mod size;
+pub mod stats;
+pub mod strip;
+pub mod testfile;
+pub mod tokens;
+pub mod tree;
+pub mod walk;
+
+pub use config::Config;
+pub use walk::{FileEntry, FileWalker};
+
+use cache::HistoryCache;
+use git::{DateFormat, GitHistory, GitInfo, RepoInfo, Since};
+use output::{FileSection, Format, ManifestEntry};
+use testfile::FileKind;
+
+/// Everything about a run that isn't part of the config file. The fields
+/// mirror the `amc` command line flags.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Directories to scan; paths are prefixed with their directory when
+    /// there is more than one
+    pub dirs: Vec<String>,
+    /// Newline-separated paths to annotate instead of walking `dirs`
+    pub file_list: Option<String>,
+    /// Fail when more files than this match, `0` for no limit
+    pub max_files: usize,
+    /// Paths to leave out on top of the config's `excluded_files`
+    pub exclude_files: Vec<String>,
+    /// Config file in use, never included itself
+    pub config_file: Option<PathBuf>,
+    /// Show paths relative to this directory rather than to each of `dirs`
+    pub repo_root: Option<PathBuf>,
+    pub format: Format,
+    /// Revision to resolve history from instead of HEAD
+    pub rev: Option<String>,
+    /// Only keep files changed after this date or revision
+    pub since: Option<String>,
+    /// With `since`, keep files that have no Git history
+    pub since_include_untracked: bool,
+    /// File order, by default path order (or commit time under `max_tokens`)
+    pub sort: Option<SortOrder>,
+    pub date_format: DateFormat,
+    pub skip_untracked: bool,
+    /// Skip files marked `linguist-generated`/`export-ignore`
+    pub git_attributes: bool,
+    /// Honor `.gitignore` files and `.git/info/exclude`
+    pub gitignore: bool,
+    /// Honor the user's global Git excludes file
+    pub global_gitignore: bool,
+    /// Skip files whose content looks minified
+    pub skip_minified: bool,
+    /// Skip files `testfile::classify` calls tests
+    pub exclude_tests: bool,
+    /// Keep only tests and source files with inline tests
+    pub only_tests: bool,
+    /// Follow symlinked files and directories while walking
+    pub follow_symlinks: bool,
+    /// Walk dotfiles and dot-directories too
+    pub include_hidden: bool,
+    /// Leave out dependency lockfiles such as `Cargo.lock`
+    pub exclude_lockfiles: bool,
+    /// Walk submodule work trees, annotating their files from their own history
+    pub include_submodules: bool,
+    /// Levels to descend below each scanned directory, unlimited when unset
+    pub max_depth: Option<usize>,
+    /// Skip files larger than this many bytes
+    pub max_file_size: Option<u64>,
+    pub tree: bool,
+    pub line_numbers: bool,
+    /// Remove comments from files in supported languages
+    pub strip_comments: bool,
+    /// Collapse runs of blank lines in file content down to this many
+    pub max_blank_lines: Option<usize>,
+    /// Keep only this many leading lines of longer files...
+    pub head: Option<usize>,
+    /// ...and this many trailing ones, marking what was omitted in between
+    pub tail: Option<usize>,
+    /// Leave out the prompt and footer
+    pub no_prompt: bool,
+    /// Documents written verbatim before the prompt
+    pub prepend: Vec<String>,
+    /// Documents written verbatim after the last file
+    pub append: Vec<String>,
+    /// List every written file with its size and commit after the last one
+    pub manifest: bool,
+    /// Worker threads, all CPUs when unset
+    pub concurrency: Option<usize>,
+    pub dedupe: bool,
+    /// Token budget for the whole output
+    pub max_tokens: Option<usize>,
+    /// Mask likely secrets in file content
+    pub redact: bool,
+    /// Show a progress counter on stderr when it is a terminal
+    pub progress: bool,
+    /// Abort on the first file that can't be read instead of skipping it
+    pub fail_fast: bool,
+    /// Which `--split-tokens` part this output is, as (number, count)
+    pub part: Option<(usize, usize)>,
+    /// Add each file's mode bits to its header
+    pub show_perms: bool,
+    /// Keep each directory's files together under a directory heading
+    pub group_by_dir: bool,
+    /// Reuse and update the on-disk cache of last-commit lookups
+    pub cache: bool,
+    /// Decode files that aren't valid UTF-8 with this instead of guessing
+    pub encoding: Option<&'static encoding_rs::Encoding>,
+    /// Work without a repository: no history lookups, and headers carry
+    /// the filesystem mtime instead of commit metadata
+    pub no_git: bool,
+    /// List each file's imports in its header
+    pub annotate_imports: bool,
+    /// Run the config's `[filters]` commands, which are ignored otherwise so a
+    /// config that came with a cloned repository can't run anything
+    pub allow_filters: bool,
+    /// Start each header with the file's position, e.g. `[File 3/27]`
+    pub number_files: bool,
+    /// Start the output with the `# amc-format: N` line, plain format only
+    pub format_version: bool,
+    /// Template text laying out the whole output instead of `format`, see
+    /// [`output::TemplateFormatter`]
+    pub template: Option<String>,
+    /// Repository state to write between the prompt and the files
+    pub repo_info: Option<RepoInfo>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            dirs: vec![".".to_string()],
+            file_list: None,
+            max_files: 1000,
+            exclude_files: Vec::new(),
+            config_file: None,
+            repo_root: None,
+            format: Format::Plain,
+            rev: None,
+            since: None,
+            since_include_untracked: false,
+            sort: None,
+            date_format: DateFormat::Rfc3339,
+            skip_untracked: false,
+            git_attributes: true,
+            gitignore: true,
+            global_gitignore: true,
+            skip_minified: true,
+            exclude_tests: false,
+            only_tests: false,
+            follow_symlinks: false,
+            include_hidden: false,
+            exclude_lockfiles: true,
+            include_submodules: false,
+            max_depth: None,
+            max_file_size: None,
+            tree: false,
+            line_numbers: false,
+            strip_comments: false,
+            max_blank_lines: None,
+            head: None,
+            tail: None,
+            no_prompt: false,
+            prepend: Vec::new(),
+            append: Vec::new(),
+            manifest: true,
+            concurrency: None,
+            dedupe: false,
+            max_tokens: None,
+            redact: false,
+            progress: false,
+            fail_fast: false,
+            part: None,
+            show_perms: false,
+            group_by_dir: false,
+            cache: true,
+            encoding: None,
+            no_git: false,
+            annotate_imports: false,
+            allow_filters: false,
+            number_files: false,
+            format_version: false,
+            template: None,
+            repo_info: None,
+        }
+    }
+}
+
+/// Run the whole pipeline and return the rendered output
+pub fn generate(config: &Config, options: &Options) -> Result<String> {
+    let files = collect_files(config, options)?;
+    let gathered = gather(config, options, files)?;
+    let mut out = Vec::new();
+    render(&mut out, config, options, &gathered.files)?;
+    Ok(String::from_utf8(out)?)
+}
+
+/// Walk the configured directories (or take the explicit file list), sorted
+/// by path and checked against `max_files`
+pub fn collect_files(config: &Config, options: &Options) -> Result<Vec<FileEntry>> {
+    let mut files = match &options.file_list {
+        Some(list) => {
+            let base = options
+                .repo_root
+                .as_deref()
+                .unwrap_or(Path::new(&options.dirs[0]));
+            walk::entries_from_list(list, base)?
+        }
+        None => {
+            let mut walker = FileWalker::new(config.extensions.clone())
+                .with_globs(&config.include_globs, &config.exclude_globs)?
+                .with_excluded_files(
+                    &[config.excluded_files.as_slice(), &options.exclude_files]
+                        .concat(),
+                )
+                .with_excluded_paths(options.config_file.as_slice())
+                .with_git_attributes(options.git_attributes)
+                .with_gitignore(options.gitignore, options.global_gitignore)
+                .with_follow_symlinks(options.follow_symlinks)
+                .with_include_hidden(options.include_hidden)
+                .with_exclude_lockfiles(options.exclude_lockfiles)
+                .with_include_submodules(options.include_submodules)
+                .with_max_depth(options.max_depth);
+            if let Some(root) = &options.repo_root {
+                walker = walker.with_path_base(root);
+            }
+            walker.walk_many(&options.dirs)?
+        }
+    };
+    files.sort_by(|a, b| a.relative_path.cmp(&b.relative_path));
+
+    if options.max_files > 0 && files.len() > options.max_files {
+        anyhow::bail!(
+            "Found {} matching files, more than the --max-files limit of {}. \
+             Check that --dir points where you meant, or pass --max-files 0 to \
+             disable the limit.",
+            files.len(),
+            options.max_files
+        );
+    }
+    Ok(files)
+}
+
+/// Files that made it through the per-file filters, in output order
+pub struct Gathered {
+    pub files: Vec<GatheredFile>,
+    /// How many files were skipped for exceeding `max_file_size`
+    pub oversized: usize,
+    /// How many files were skipped because reading them failed
+    pub unreadable: usize,
+    /// Every walked file left out by a per-file filter, oversized ones included
+    pub skipped: Vec<SkippedFile>,
+}
+
+/// A walked file that didn't make it into the output
+#[derive(Debug)]
+pub struct SkippedFile {
+    pub path: PathBuf,
+    /// Which filter dropped it, e.g. "untracked"
+    pub reason: String,
+}
+
+/// Read every file and resolve its Git metadata
+pub fn gather(
+    config: &Config,
+    options: &Options,
+    files: Vec<FileEntry>,
+) -> Result<Gathered> {
+    // Reading and history lookups dominate the run time, so do them in
+    // parallel. Repository handles aren't Sync, so every worker opens its own.
+    let repo_root = &options.dirs[0];
+    let open_history = || {
+        let mut history = GitHistory::discover(repo_root)?;
+        if options.include_submodules {
+            history = history.with_submodules()?;
+        }
+        match &options.rev {
+            Some(rev) => history.with_rev(rev),
+            None => Ok(history),
+        }
+    };
+
+    // Surface a bad --rev or --since once, up front, rather than from every
+    // worker
+    let history = if options.no_git {
+        None
+    } else {
+        let history = open_history()?;
+        check_one_repository(&history, options)?;
+        Some(history)
+    };
+    let since = match (&history, options.since.as_deref()) {
+        (Some(history), Some(spec)) => Some(history.resolve_since(spec)?),
+        (None, Some(_)) => anyhow::bail!("--since needs Git history"),
+        (_, None) => None,
+    };
+    let cache_path = history.as_ref().map(GitHistory::cache_path);
+    let start = history.as_ref().and_then(GitHistory::start);
+    let cache = Arc::new(match (start, &cache_path) {
+        (Some(start), Some(path)) if options.cache => {
+            HistoryCache::load(path, &start)
+        }
+        _ => HistoryCache::default(),
+    });
+    drop(history);
+    // Workers without Git get no history at all
+    let open_history = || -> Result<Option<GitHistory>> {
+        if options.no_git {
+            return Ok(None);
+        }
+        open_history().map(|history| Some(history.with_cache(cache.clone())))
+    };
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.concurrency.unwrap_or(0))
+        .build()?;
+    let progress = if options.progress {
+        progress_bar(files.len())
+    } else {
+        ProgressBar::hidden()
+    };
+
+    let outcomes: Vec<Result<Outcome>> = pool.install(|| {
+        files
+            .into_par_iter()
+            .map_init(open_history, |history, file| {
+                let outcome = match history {
+                    Ok(history) => {
+                        gather_file(config, options, since, history, file)
+                    }
+                    Err(err) => {
+                        Err(anyhow::anyhow!("Failed to open repository: {}", err))
+                    }
+                };
+                progress.inc(1);
+                outcome
+            })
+            .collect()
+    });
+    progress.finish_and_clear();
+    if let Some(path) = &cache_path {
+        if let Err(err) = cache.save(path) {
+            warn!("Failed to update the history cache: {:#}", err);
+        }
+    }
+
+    let mut gathered = Vec::with_capacity(outcomes.len());
+    let mut oversized = 0;
+    let mut unreadable = 0;
+    let mut skipped = Vec::new();
+    for outcome in outcomes {
+        match outcome? {
+            Outcome::File(file) => gathered.push(*file),
+            Outcome::Oversized(file) => {
+                oversized += 1;
+                skipped.push(file);
+            }
+            Outcome::Unreadable(file) => {
+                unreadable += 1;
+                skipped.push(file);
+            }
+            Outcome::Skipped(file) => skipped.push(file),
+        }
+    }
+
+    // Files arrive sorted by path. Under a budget the most recently committed
+    // files should survive, unless the user picked an order explicitly.
+    let sort = options.sort.unwrap_or(if options.max_tokens.is_some() {
+        SortOrder::GitTime
+    } else {
+        SortOrder::Path
+    });
+    sort_gathered(&mut gathered, sort);
+    sort_by_priority(&mut gathered, &config.priority)?;
+
+    Ok(Gathered {
+        files: gathered,
+        oversized,
+        unreadable,
+        skipped,
+    })
+}
+
+/// Output group for files directly in the scanned directory
+pub const ROOT_GROUP: &str = "_root";
+
+/// Group files by the first component of their relative path, keeping their
+/// order within each group. Files without a directory go to [`ROOT_GROUP`].
+pub fn split_by_top_dir(
+    files: Vec<GatheredFile>,
+) -> BTreeMap<String, Vec<GatheredFile>> {
+    let mut groups: BTreeMap<String, Vec<GatheredFile>> = BTreeMap::new();
+    for file in files {
+        let mut components = file.entry.relative_path.components();
+        let first = components.next();
+        let group = match (first, components.next()) {
+            (Some(dir), Some(_)) => dir.as_os_str().to_string_lossy().into_owned(),
+            _ => ROOT_GROUP.to_string(),
+        };
+        groups.entry(group).or_default().push(file);
+    }
+    groups
+}
+
+/// Pack files into consecutive parts that each stay within `budget` estimated
+/// tokens, keeping the output order. Parts are measured as rendered, so
+/// headers, the manifest, the tree and the prompt and footer all count. A
+/// file that can't fit even on its own is cut at line boundaries into chunks
+/// that fill one part each; only a single line too long for a part can still
+/// exceed the budget.
+pub fn split_by_tokens(
+    config: &Config,
+    options: &Options,
+    files: Vec<GatheredFile>,
+    budget: usize,
+) -> Result<Vec<Vec<GatheredFile>>> {
+    // The part count isn't known yet; the file count bounds it, which gives
+    // the widest possible heading
+    let bound = files.len().max(1);
+    let options = Options {
+        part: Some((bound, bound)),
+        ..options.clone()
+    };
+    let measure = |files: &[GatheredFile]| part_tokens(config, &options, files);
+    let overhead = measure(&[])?;
+    if overhead >= budget {
+        anyhow::bail!(
+            "--split-tokens {} leaves no room for files after the prompt, footer \
+             and other overhead (about {} tokens)",
+            budget,
+            overhead
+        );
+    }
+
+    let mut parts = Vec::new();
+    let mut current = Vec::new();
+    let mut used = overhead;
+    for file in files {
+        let cost = measure(std::slice::from_ref(&file))?.saturating_sub(overhead);
+        if overhead + cost > budget {
+            info!(
+                "Splitting {} ({} tokens) across parts",
+                file.entry.relative_path.display(),
+                cost
+            );
+            flush_parts(&mut parts, &mut current, &measure, budget)?;
+            used = overhead;
+            let chunks = split_to_fit(&file, &measure, overhead, budget)?;
+            parts.extend(chunks.into_iter().map(|chunk| vec![chunk]));
+            continue;
+        }
+        if used + cost > budget {
+            flush_parts(&mut parts, &mut current, &measure, budget)?;
+            used = overhead;
+        }
+        used += cost;
+        current.push(file);
+    }
+    flush_parts(&mut parts, &mut current, &measure, budget)?;
+    Ok(parts)
+}
+
+/// Estimated tokens of `files` rendered as one part
+fn part_tokens(
+    config: &Config,
+    options: &Options,
+    files: &[GatheredFile],
+) -> Result<usize> {
+    let mut out = Vec::new();
+    render(&mut out, config, options, files)?;
+    Ok(tokens::estimate_tokens(&String::from_utf8_lossy(&out)))
+}
+
+/// Move `current` into `parts`. Per-file costs don't add up exactly (the
+/// manifest pads every row to the longest path), so each part is measured
+/// whole and its last files move on to the next part while it is over.
+fn flush_parts(
+    parts: &mut Vec<Vec<GatheredFile>>,
+    current: &mut Vec<GatheredFile>,
+    measure: &dyn Fn(&[GatheredFile]) -> Result<usize>,
+    budget: usize,
+) -> Result<()> {
+    let mut rest = std::mem::take(current);
+    while !rest.is_empty() {
+        let mut end = rest.len();
+        while end > 1 && measure(&rest[..end])? > budget {
+            end -= 1;
+        }
+        let remaining = rest.split_off(end);
+        parts.push(rest);
+        rest = remaining;
+    }
+    Ok(())
+}
+
+/// Chunks of `file` that each fit a part of `budget`, shrinking the content
+/// room until the rendered chunks do
+fn split_to_fit(
+    file: &GatheredFile,
+    measure: &dyn Fn(&[GatheredFile]) -> Result<usize>,
+    overhead: usize,
+    budget: usize,
+) -> Result<Vec<GatheredFile>> {
+    let empty = GatheredFile {
+        content: String::new(),
+        chunk: Some((1, 1)),
+        ..file.clone()
+    };
+    let header = measure(std::slice::from_ref(&empty))?;
+    let mut room = budget.saturating_sub(header).max(1);
+    loop {
+        let chunks = split_file(file, room);
+        let mut largest = 0;
+        for chunk in &chunks {
+            if chunk.content.lines().count() > 1 {
+                largest = largest.max(measure(std::slice::from_ref(chunk))?);
+            }
+        }
+        if largest <= budget || room == 1 {
+            return Ok(chunks);
+        }
+        // Shrink in proportion to the overshoot, by at least one token
+        let content = largest.saturating_sub(overhead).max(1);
+        let target = room * budget.saturating_sub(overhead) / content;
+        room = target.min(room - 1).max(1);
+    }
+}
+
+/// Cut `file` into line-aligned chunks of at most `room` tokens; a single
+/// longer line still becomes one chunk
+fn split_file(file: &GatheredFile, room: usize) -> Vec<GatheredFile> {
+    let mut pieces = vec![String::new()];
+    for line in file.content.split_inclusive('\n') {
+        let last = pieces.last_mut().expect("pieces is never empty");
+        if !last.is_empty()
+            && tokens::estimate_tokens(last) + tokens::estimate_tokens(line) > room
+        {
+            pieces.push(String::new());
+        }
+        pieces
+            .last_mut()
+            .expect("pieces is never empty")
+            .push_str(line);
+    }
+
+    let count = pieces.len();
+    pieces
+        .into_iter()
+        .enumerate()
+        .map(|(index, content)| GatheredFile {
+            content,
+            chunk: Some((index + 1, count)),
+            ..file.clone()
+        })
+        .collect()
+}
+
+/// With `group_by_dir`, files ordered so each parent directory's files are
+/// adjacent; directories appear in the order of their first file and files
+/// keep their relative order
+fn group_by_dir<'a>(
+    options: &Options,
+    gathered: &'a [GatheredFile],
+) -> Vec<&'a GatheredFile> {
+    let mut files: Vec<&GatheredFile> = gathered.iter().collect();
+    if options.group_by_dir {
+        let mut first_seen: HashMap<&Path, usize> = HashMap::new();
+        for (index, file) in files.iter().enumerate() {
+            first_seen
+                .entry(parent_dir(&file.entry.relative_path))
+                .or_insert(index);
+        }
+        files.sort_by_key(|file| first_seen[parent_dir(&file.entry.relative_path)]);
+    }
+    files
+}
+
+/// Directory of a relative path, `.` for files at the top
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// First line of every `--split-tokens` part
+fn part_heading(number: usize, count: usize) -> String {
+    format!("Part {} of {}", number, count)
+}
+
+/// The prompt and footer as written, both empty with `no_prompt`. The
+/// built-in prompt describes the headers, so it follows the delimiter and
+/// `date_format` in use.
+fn prompt_and_footer<'a>(
+    config: &'a Config,
+    options: &Options,
+) -> (Cow<'a, str>, &'a str) {
+    if options.no_prompt {
+        return (Cow::Borrowed(""), "");
+    }
+    let prompt = if config.llm_prompt == config::default_llm_prompt() {
+        Cow::Owned(config::default_llm_prompt_for(
+            &config.delimiter,
+            options.date_format,
+        ))
+    } else {
+        Cow::Borrowed(config.llm_prompt.as_str())
+    };
+    (prompt, config.footer_prompt.trim_end())
+}
+
+/// Estimated tokens of everything written around the files
+fn overhead_tokens(prompt: &str, footer: &str, options: &Options) -> usize {
+    tokens::estimate_tokens(prompt)
+        + tokens::estimate_tokens(footer)
+        + options
+            .prepend
+            .iter()
+            .chain(&options.append)
+            .map(|doc| tokens::estimate_tokens(doc))
+            .sum::<usize>()
+}
+
+/// `processed/total` counter on stderr, hidden when stderr isn't a terminal
+fn progress_bar(total: usize) -> ProgressBar {
+    if !io::stderr().is_terminal() {
+        return ProgressBar::hidden();
+    }
+    let progress = ProgressBar::with_draw_target(
+        Some(total as u64),
+        ProgressDrawTarget::stderr(),
+    );
+    progress.set_style(
+        ProgressStyle::with_template("{spinner} {pos}/{len} files {wide_bar}")
+            .expect("valid progress template"),
+    );
+    progress
+}
+
+/// File ordering selected with `--sort`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SortOrder {
+    /// Alphabetically by relative path
+    Path,
+    /// Most recently modified on disk first
+    Mtime,
+    /// Most recently committed first
+    GitTime,
+    /// Largest first
+    Size,
+}
+
+/// Files with equal keys, or without a time at all (listed last), fall back
+/// to path order
+fn sort_gathered(gathered: &mut [GatheredFile], sort: SortOrder) {
+    let by_path = |a: &GatheredFile, b: &GatheredFile| {
+        a.entry.relative_path.cmp(&b.entry.relative_path)
+    };
+    let commit_time =
+        |file: &GatheredFile| file.git.as_ref().map(|g| g.commit_time);
+    match sort {
+        SortOrder::Path => gathered.sort_by(by_path),
+        SortOrder::Mtime => gathered.sort_by(|a, b| {
+            b.modified.cmp(&a.modified).then_with(|| by_path(a, b))
+        }),
+        SortOrder::GitTime => gathered.sort_by(|a, b| {
+            commit_time(b)
+                .cmp(&commit_time(a))
+                .then_with(|| by_path(a, b))
+        }),
+        SortOrder::Size => gathered.sort_by(|a, b| {
+            b.content
+                .len()
+                .cmp(&a.content.len())
+                .then_with(|| by_path(a, b))
+        }),
+    }
+}
+
+/// Move files matching a `priority` pattern to the front, in pattern order.
+/// The sort is stable, so everything else keeps its `sort_gathered` order.
+fn sort_by_priority(
+    gathered: &mut [GatheredFile],
+    patterns: &[String],
+) -> Result<()> {
+    if patterns.is_empty() {
+        return Ok(());
+    }
+    let globs = walk::build_glob_set(patterns)?;
+    gathered.sort_by_cached_key(|file| {
+        globs
+            .matches(&file.entry.relative_path)
+            .into_iter()
+            .min()
+            .unwrap_or(usize::MAX)
+    });
+    Ok(())
+}
+
+/// History comes from the repository of the first `--dir`, so every other one
+/// must lie in the same repository, or with `--include-submodules` in one of
+/// its submodules, which that history answers for
+fn check_one_repository(history: &GitHistory, options: &Options) -> Result<()> {
+    for dir in options.dirs.iter().skip(1) {
+        let other = GitHistory::discover(dir)
+            .with_context(|| format!("--dir {} is not in a Git repository", dir))?;
+        let submodule = options.include_submodules
+            && other.workdir().starts_with(history.workdir());
+        if other.workdir() != history.workdir() && !submodule {
+            anyhow::bail!(
+                "--dir {} is in another Git repository ({}) than --dir {}; run amc \
+                 once per repository, or pass --no-git",
+                dir,
+                other.workdir().display(),
+                options.dirs[0]
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Result of gathering a single walked file
+enum Outcome {
+    File(Box<GatheredFile>),
+    Oversized(SkippedFile),
+    Unreadable(SkippedFile),
+    Skipped(SkippedFile),
+}
+
+/// Log why `file` is left out and record it
+fn skip(file: FileEntry, reason: String) -> SkippedFile {
+    info!("Skipping {}: {}", file.relative_path.display(), reason);
+    SkippedFile {
+        path: file.relative_path,
+        reason,
+    }
+}
+
+/// Skip a file whose read failed, or fail the run under `fail_fast`
+fn unreadable(
+    options: &Options,
+    file: FileEntry,
+    err: io::Error,
+) -> Result<Outcome> {
+    if options.fail_fast {
+        let path = file.absolute_path.display();
+        return Err(
+            anyhow::Error::new(err).context(format!("Failed to read {}", path))
+        );
+    }
+    warn!(
+        "Skipping unreadable file {}: {}",
+        file.relative_path.display(),
+        err
+    );
+    Ok(Outcome::Unreadable(SkippedFile {
+        path: file.relative_path,
+        reason: format!("unreadable ({})", err),
+    }))
+}
+
+/// Mode bits for `show_perms`, e.g. "0644" or "0755 (executable)"
+#[cfg(unix)]
+fn describe_permissions(permissions: &fs::Permissions) -> String {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = permissions.mode() & 0o7777;
+    if mode & 0o111 != 0 {
+        format!("{:04o} (executable)", mode)
+    } else {
+        format!("{:04o}", mode)
+    }
+}
+
+/// Windows has no mode bits to report
+#[cfg(not(unix))]
+fn describe_permissions(_permissions: &fs::Permissions) -> String {
+    "n/a".to_string()
+}
+
+/// Read a file and resolve its Git metadata, applying the per-file filters
+fn gather_file(
+    config: &Config,
+    options: &Options,
+    since: Option<Since>,
+    history: &mut Option<GitHistory>,
+    file: FileEntry,
+) -> Result<Outcome> {
+    if let Some(limit) = options.max_file_size {
+        let len = match fs::metadata(&file.absolute_path) {
+            Ok(metadata) => metadata.len(),
+            Err(err) => return unreadable(options, file, err),
+        };
+        if len > limit {
+            let reason =
+                format!("over --max-file-size ({})", size::format_size(len));
+            return Ok(Outcome::Oversized(skip(file, reason)));
+        }
+    }
+
+    let untracked = history.as_ref().is_some_and(|history| {
+        history.is_untracked(&file.absolute_path).unwrap_or(false)
+    });
+    if untracked && options.skip_untracked {
+        return Ok(Outcome::Skipped(skip(file, "untracked".to_string())));
+    }
+
+    let marker =
+        match walk::find_marker(&file.absolute_path, &config.exclude_if_contains) {
+            Ok(marker) => marker,
+            Err(err) => return unreadable(options, file, err),
+        };
+    if let Some(marker) = marker {
+        let reason = format!("contains marker {:?}", marker);
+        return Ok(Outcome::Skipped(skip(file, reason)));
+    }
+
+    // Get git information
+    let git =
+        match history.as_mut() {
+            Some(history) => history
+                .get_git_info(&file.absolute_path)
+                .unwrap_or_else(|err| {
+                    info!(
+                        "No git info for {}: {}",
+                        file.absolute_path.display(),
+                        err
+                    );
+                    None
+                }),
+            None => None,
+        };
+
+    // `since` is only resolved when there is a history
+    if let (Some(since), Some(history)) = (since, history.as_ref()) {
+        let recent = match &git {
+            Some(git) => history.changed_since(git, since)?,
+            None => options.since_include_untracked,
+        };
+        if !recent {
+            let reason = format!(
+                "unchanged since {}",
+                options.since.as_deref().unwrap_or_default()
+            );
+            return Ok(Outcome::Skipped(skip(file, reason)));
+        }
+    }
+
+    let permissions = if options.show_perms {
+        match fs::metadata(&file.absolute_path) {
+            Ok(metadata) => Some(describe_permissions(&metadata.permissions())),
+            Err(err) => return unreadable(options, file, err),
+        }
+    } else {
+        None
+    };
+
+    info!("Processing file: {}", file.absolute_path.display());
+    let bytes = match fs::read(&file.absolute_path) {
+        Ok(bytes) => bytes,
+        Err(err) => return unreadable(options, file, err),
+    };
+    let Some(decoded) = encoding::decode(&bytes, options.encoding) else {
+        return Ok(Outcome::Skipped(skip(file, "binary".to_string())));
+    };
+    if let Some(encoding) = decoded.transcoded_from {
+        warn!(
+            "{} is not UTF-8, transcoded from {}{}",
+            file.relative_path.display(),
+            encoding,
+            if decoded.had_errors {
+                " (invalid sequences replaced with U+FFFD)"
+            } else {
+                ""
+            }
+        );
+    }
+    let transcoded_from = decoded.transcoded_from;
+    let mut content = decoded.content;
+    // Test directories are recognized from the repository root, so scanning
+    // `tests/` itself still finds tests
+    let kind = {
+        let repo_path = history
+            .as_ref()
+            .and_then(|history| history.repo_relative(&file.absolute_path).ok());
+        let path = repo_path.as_deref().unwrap_or(&file.relative_path);
+        testfile::classify(path, &content)
+    };
+    if options.exclude_tests && kind == FileKind::Test {
+        return Ok(Outcome::Skipped(skip(file, "test file".to_string())));
+    }
+    if options.only_tests && kind == FileKind::Source {
+        return Ok(Outcome::Skipped(skip(file, "not a test file".to_string())));
+    }
+    // Filter first, so e.g. a pretty-printer runs before the minified check
+    let mut filtered = false;
+    let command = config
+        .filter_for(&file.relative_path)
+        .filter(|_| options.allow_filters);
+    if let Some(command) = command {
+        match hook::filter(command, &content, &file.absolute_path) {
+            Ok(output) => {
+                content = output;
+                filtered = true;
+            }
+            Err(err) => warn!(
+                "Filter failed for {}, keeping the raw content: {:#}",
+                file.relative_path.display(),
+                err
+            ),
+        }
+    }
+    if options.skip_minified && minified::is_minifiable(&file.relative_path) {
+        if let Some(reason) = minified::minified_reason(&content) {
+            let reason = format!("looks minified ({})", reason);
+            return Ok(Outcome::Skipped(skip(file, reason)));
+        }
+    }
+    let mut comments_stripped = false;
+    if options.strip_comments {
+        let stripped = output::detect_language(&file.relative_path, &content)
+            .and_then(|language| strip::strip_comments(language, &content));
+        if let Some(stripped) = stripped.filter(|stripped| *stripped != content) {
+            content = stripped;
+            comments_stripped = true;
+        }
+    }
+    if let Some(max) = options.max_blank_lines {
+        if let Cow::Owned(squeezed) = output::squeeze_blank_lines(&content, max) {
+            content = squeezed;
+        }
+    }
+    let mut redactions = 0;
+    if options.redact {
+        let (redacted, count) = redact::redact(&content);
+        if count > 0 {
+            info!(
+                "Redacted {} secret(s) in {}",
+                count,
+                file.relative_path.display()
+            );
+            content = redacted.into_owned();
+            redactions = count;
+        }
+    }
+    let imports = if options.annotate_imports {
+        output::detect_language(&file.relative_path, &content)
+            .and_then(|language| imports::extract_imports(language, &content))
+            .filter(|imports| !imports.is_empty())
+    } else {
+        None
+    };
+    let modified = match fs::metadata(&file.absolute_path) {
+        Ok(metadata) => metadata.modified().ok(),
+        Err(err) => return unreadable(options, file, err),
+    };
+    if let Some(git) = &git {
+        info!(
+            "Git info - commit: {}, time: {}",
+            git.commit_hash, git.commit_time
+        );
+    }
+
+    Ok(Outcome::File(Box::new(GatheredFile {
+        entry: file,
+        content,
+        modified,
+        git,
+        untracked,
+        redactions,
+        comments_stripped,
+        filtered,
+        kind,
+        permissions,
+        imports,
+        transcoded_from,
+        chunk: None,
+    })))
+}
+
+/// A walked file together with everything needed to write its section
+#[derive(Clone)]
+pub struct GatheredFile {
+    pub entry: FileEntry,
+    pub content: String,
+    pub modified: Option<SystemTime>,
+    /// Last commit that changed the file, `None` without history
+    pub git: Option<GitInfo>,
+    pub untracked: bool,
+    /// Secrets masked in `content` by `redact`
+    pub redactions: usize,
+    /// `content` went through `strip::strip_comments`
+    pub comments_stripped: bool,
+    /// `content` is the output of the file's `[filters]` command
+    pub filtered: bool,
+    /// Test, source or source with inline tests
+    pub kind: FileKind,
+    /// Mode bits read for `show_perms`
+    pub permissions: Option<String>,
+    /// What `imports::extract_imports` found, with `annotate_imports`
+    pub imports: Option<Vec<String>>,
+    /// Encoding `content` was converted from, `None` for UTF-8 files
+    pub transcoded_from: Option<&'static str>,
+    /// Piece (number, count) of a file too large for a single
+    /// `split_by_tokens` part
+    pub chunk: Option<(usize, usize)>,
+}
+
+/// Copy a `--prepend`/`--append` document as is, ending it with a newline
+fn write_verbatim(out: &mut dyn Write, document: &str) -> io::Result<()> {
+    write!(out, "{}", document)?;
+    if !document.is_empty() && !document.ends_with('\n') {
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Header values for a file without Git history, all set to `label` except
+/// the time, which falls back to the file's modification time when known
+fn missing_git_fields(
+    label: &str,
+    modified: Option<SystemTime>,
+    date_format: DateFormat,
+) -> (String, String, String, String) {
+    let label = label.to_string();
+    let time = match modified {
+        Some(modified) => format!(
+            "{} (filesystem mtime)",
+            date_format.render_system_time(modified)
+        ),
+        None => label.clone(),
+    };
+    (label.clone(), time, label.clone(), label)
+}
+
+/// Apply `--head`/`--tail` to a file's content
+fn truncate<'a>(options: &Options, content: &'a str) -> Cow<'a, str> {
+    if options.head.is_none() && options.tail.is_none() {
+        return Cow::Borrowed(content);
+    }
+    output::truncate_lines(
+        content,
+        options.head.unwrap_or(0),
+        options.tail.unwrap_or(0),
+    )
+}
+
+/// A file chosen for output, with the content that will actually be written
+struct SelectedFile<'a> {
+    file: &'a GatheredFile,
+    content: Cow<'a, str>,
+    is_duplicate: bool,
+    /// Shortened by `--head`/`--tail`
+    truncated: bool,
+}
+
+/// Totals collected while rendering, reported once output is complete
+pub struct RenderSummary<'a> {
+    /// Bytes written to `out`, before any compression
+    pub bytes: usize,
+    /// Estimated tokens of everything written, prompt, headers and footer
+    /// included; the same estimate as for `bytes` of text
+    pub total_tokens: usize,
+    /// Estimated tokens of each written file, in output order
+    pub file_tokens: Vec<(&'a Path, usize)>,
+    /// Files left out because the token budget ran out
+    pub omitted: Vec<&'a Path>,
+}
+
+/// Write the prompt and every gathered file that fits the token budget.
+///
+/// The budget is checked against the rendered output, headers, line numbers,
+/// tree and manifest included: the most files that fit are found by rendering
+/// into a byte counter, and only then is the output written. Files are taken
+/// in output order, so once one is over the budget all later ones are left out.
+///
+/// Output is streamed to `out` section by section, so only the gathered
+/// files themselves are held in memory: every format writes each file as soon
+/// as it comes up, JSON included, which serializes its `files` array one
+/// element at a time. `SingleFence` renders the plain layout twice, first into
+/// a sink that only measures backtick runs to pick the fence.
+pub fn render<'a>(
+    out: &mut dyn Write,
+    config: &Config,
+    options: &Options,
+    gathered: &'a [GatheredFile],
+) -> Result<RenderSummary<'a>> {
+    let limit = match options.max_tokens {
+        Some(max) => files_within_budget(config, options, gathered, max)?,
+        None => gathered.len(),
+    };
+    let mut counter = ByteCounter {
+        inner: out,
+        bytes: 0,
+    };
+    let mut summary =
+        render_sections(&mut counter, config, options, gathered, limit)?;
+    summary.bytes = counter.bytes;
+    summary.total_tokens = tokens::estimate_tokens_from_bytes(counter.bytes);
+    for path in &summary.omitted {
+        info!("Skipping file over token budget: {}", path.display());
+    }
+    Ok(summary)
+}
+
+/// How many files, in output order, fit in `max` tokens once rendered. More
+/// files never render shorter, so the count is found by bisection.
+fn files_within_budget(
+    config: &Config,
+    options: &Options,
+    gathered: &[GatheredFile],
+    max: usize,
+) -> Result<usize> {
+    let fits = |limit: usize| -> Result<bool> {
+        let mut sink = io::sink();
+        let mut counter = ByteCounter {
+            inner: &mut sink,
+            bytes: 0,
+        };
+        render_sections(&mut counter, config, options, gathered, limit)?;
+        Ok(tokens::estimate_tokens_from_bytes(counter.bytes) <= max)
+    };
+    let (mut low, mut high) = (0, gathered.len());
+    while low < high {
+        let middle = (low + high).div_ceil(2);
+        if fits(middle)? {
+            low = middle;
+        } else {
+            high = middle - 1;
+        }
+    }
+    Ok(low)
+}
+
+/// Passes writes through, counting the bytes
+struct ByteCounter<'a> {
+    inner: &'a mut dyn Write,
+    bytes: usize,
+}
+
+impl Write for ByteCounter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Render like [`render`], writing only the first `limit` files in output
+/// order and listing the rest as omitted
+fn render_sections<'a>(
+    out: &mut dyn Write,
+    config: &Config,
+    options: &Options,
+    gathered: &'a [GatheredFile],
+    limit: usize,
+) -> Result<RenderSummary<'a>> {
+    let mut formatter: Box<dyn output::Formatter> = match &options.template {
+        Some(template) => {
+            Box::new(output::TemplateFormatter::new(template, config)?)
+        }
+        None => options.format.formatter(config),
+    };
+
+    let documents = options.prepend.iter().chain(&options.append);
+    if documents.clone().next().is_some()
+        && matches!(
+            options.format,
+            Format::Json | Format::Xml | Format::SingleFence
+        )
+    {
+        anyhow::bail!(
+            "--prepend and --append only work with the plain and markdown formats"
+        );
+    }
+
+    if options.format_version
+        && (options.format != Format::Plain || options.template.is_some())
+    {
+        anyhow::bail!("--output-format-version only works with the plain format");
+    }
+
+    if options.format == Format::SingleFence {
+        let plain = Options {
+            format: Format::Plain,
+            ..options.clone()
+        };
+        let mut runs = output::BacktickRuns::default();
+        render_sections(&mut runs, config, &plain, gathered, limit)?;
+        let fence = output::fence_longer_than(runs.longest);
+
+        writeln!(out, "{}", fence)?;
+        let summary = render_sections(out, config, &plain, gathered, limit)?;
+        if runs.bytes > 0 && !runs.ends_with_newline {
+            writeln!(out)?;
+        }
+        writeln!(out, "{}", fence)?;
+        return Ok(summary);
+    }
+
+    let (prompt, footer) = prompt_and_footer(config, options);
+    let prompt = match options.part {
+        Some((number, count)) if prompt.is_empty() => {
+            Cow::Owned(part_heading(number, count))
+        }
+        Some((number, count)) => {
+            Cow::Owned(format!("{}\n\n{}", part_heading(number, count), prompt))
+        }
+        None => prompt,
+    };
+    if options.format_version {
+        writeln!(
+            out,
+            "{}{}",
+            output::FORMAT_VERSION_PREFIX,
+            output::FORMAT_VERSION
+        )?;
+    }
+    for document in &options.prepend {
+        write_verbatim(out, document)?;
+    }
+    formatter.write_prompt(out, &prompt)?;
+
+    // Select the files first so the tree only lists included ones
+    let mut total_tokens = overhead_tokens(&prompt, footer, options);
+    let mut included = Vec::with_capacity(gathered.len());
+    let mut per_file_tokens = Vec::new();
+    let mut omitted = Vec::new();
+    let mut seen_content: HashMap<[u8; 32], &Path> = HashMap::new();
+
+    for (index, file) in group_by_dir(options, gathered).into_iter().enumerate() {
+        if index >= limit {
+            omitted.push(file.entry.relative_path.as_path());
+            continue;
+        }
+        // With --dedupe a repeated file only points at its first copy. The
+        // hash covers the filtered content that gets written, which is
+        // already in memory, not the bytes on disk
+        let digest: Option<[u8; 32]> = options
+            .dedupe
+            .then(|| Sha256::digest(file.content.as_bytes()).into());
+        let duplicate_of = digest.and_then(|d| seen_content.get(&d).copied());
+        let content = match duplicate_of {
+            Some(original) => {
+                Cow::Owned(format!("(identical to {})", original.display()))
+            }
+            None => truncate(options, &file.content),
+        };
+        let truncated = duplicate_of.is_none() && matches!(content, Cow::Owned(_));
+
+        let file_tokens = tokens::estimate_tokens(&content);
+        total_tokens += file_tokens;
+        per_file_tokens.push((file.entry.relative_path.as_path(), file_tokens));
+        if let (Some(digest), None) = (digest, duplicate_of) {
+            seen_content.insert(digest, &file.entry.relative_path);
+        }
+        included.push(SelectedFile {
+            file,
+            content,
+            is_duplicate: duplicate_of.is_some(),
+            truncated,
+        });
+    }
+
+    if let Some(info) = &options.repo_info {
+        formatter.write_repo_info(out, info)?;
+    }
+    if options.tree || config.summary_block {
+        let paths: Vec<&Path> = included
+            .iter()
+            .map(|selected| selected.file.entry.relative_path.as_path())
+            .collect();
+        let tree = tree::render_tree(&paths);
+        // The summary block lists the tree itself
+        if config.summary_block {
+            let summary = output::Summary {
+                files: included.len(),
+                bytes: included
+                    .iter()
+                    .map(|selected| selected.content.len() as u64)
+                    .sum(),
+                tokens: total_tokens,
+                tree: &tree,
+            };
+            formatter.write_summary(out, &summary)?;
+        } else {
+            formatter.write_tree(out, &tree)?;
+        }
+    }
+
+    let mut manifest = Vec::with_capacity(included.len());
+    let mut current_dir = None;
+    let count = included.len();
+    for (index, selected) in included.into_iter().enumerate() {
+        let file = selected.file;
+        if options.group_by_dir {
+            let dir = parent_dir(&file.entry.relative_path);
+            if current_dir != Some(dir) {
+                formatter.write_group(out, dir)?;
+                current_dir = Some(dir);
+            }
+        }
+        let (commit_hash, commit_time, author, message) = match &file.git {
+            Some(git) => (
+                git.commit_hash.clone(),
+                git.format_time(options.date_format),
+                git.author(),
+                git.summary.clone(),
+            ),
+            None if file.untracked => {
+                missing_git_fields("untracked", file.modified, options.date_format)
+            }
+            None if options.no_git => {
+                missing_git_fields("no git", file.modified, options.date_format)
+            }
+            None => {
+                let (hash, time, author, message) = missing_git_fields(
+                    &config.unknown_commit,
+                    file.modified,
+                    options.date_format,
+                );
+                let time = config.unknown_time.clone().unwrap_or(time);
+                (hash, time, author, message)
+            }
+        };
+
+        let format = config.format_for(&file.entry.relative_path);
+        let line_numbers = format
+            .and_then(|format| format.line_numbers)
+            .unwrap_or(options.line_numbers);
+        // Number the whole file before truncating so numbers stay accurate
+        let content = if line_numbers && !selected.is_duplicate {
+            let numbered = output::number_lines(&file.content);
+            Cow::Owned(truncate(options, &numbered).into_owned())
+        } else {
+            selected.content
+        };
+        let mut notes = Vec::new();
+        notes.extend(file.kind.note());
+        let transcoded_note = file
+            .transcoded_from
+            .map(|encoding| format!("transcoded from {}", encoding));
+        if let Some(note) = &transcoded_note {
+            notes.push(note.as_str());
+        }
+        if file.filtered {
+            notes.push("filtered");
+        }
+        if file.comments_stripped {
+            notes.push("comments stripped");
+        }
+        if selected.truncated {
+            notes.push("truncated");
+        }
+        let chunk_note = file
+            .chunk
+            .map(|(number, count)| format!("chunk {} of {}", number, count));
+        if let Some(note) = &chunk_note {
+            notes.push(note);
+        }
+
+        let short_hash = match &file.git {
+            Some(git) => git.short_hash().to_string(),
+            None => commit_hash.clone(),
+        };
+
+        formatter.write_file(
+            out,
+            &FileSection {
+                path: &file.entry.relative_path,
+                absolute_path: &file.entry.absolute_path,
+                commit_hash: &commit_hash,
+                short_hash: &short_hash,
+                commit_time: &commit_time,
+                author: &author,
+                message: &message,
+                content: &content,
+                notes: &notes,
+                delimiter: format.and_then(|format| format.delimiter.as_deref()),
+                permissions: file.permissions.as_deref(),
+                imports: file.imports.as_deref(),
+                number: options.number_files.then_some((index + 1, count)),
+            },
+        )?;
+
+        manifest.push((&file.entry.relative_path, file.content.len(), short_hash));
+    }
+
+    if options.manifest && !manifest.is_empty() {
+        let entries: Vec<ManifestEntry> = manifest
+            .iter()
+            .map(|(path, bytes, commit)| ManifestEntry {
+                path,
+                bytes: *bytes,
+                commit,
+            })
+            .collect();
+        formatter.write_manifest(out, &entries)?;
+    }
+    for document in &options.append {
+        write_verbatim(out, document)?;
+    }
+    formatter.write_footer(out, footer)?;
+    formatter.finish(out)?;
+
+    Ok(RenderSummary {
+        bytes: 0,
+        total_tokens: 0,
+        file_tokens: per_file_tokens,
+        omitted,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::{Repository, Signature, Time};
+    use std::time::{Duration, UNIX_EPOCH};
+    use tempfile::TempDir;
+
+    fn gathered(path: &str) -> GatheredFile {
+        GatheredFile {
+            entry: FileEntry {
+                absolute_path: Path::new("/repo").join(path),
+                relative_path: path.into(),
+            },
+            content: String::new(),
+            modified: None,
+            git: None,
+            untracked: false,
+            redactions: 0,
+            comments_stripped: false,
+            filtered: false,
+            kind: FileKind::Source,
+            permissions: None,
+            imports: None,
+            transcoded_from: None,
+            chunk: None,
+        }
+    }
+
+    #[test]
+    fn test_split_by_tokens_packs_whole_files() -> Result<()> {
+        let file = |path: &str, content: String| GatheredFile {
+            content,
+            ..gathered(path)
+        };
+        let big: String = (0..40)
+            .map(|i| format!("let line_{} = {};\n", i, i))
+            .collect();
+        let files = vec![
+            file("a.rs", "a".repeat(300)),
+            file("src/b.rs", "b".repeat(300)),
+            file("c.rs", "c".repeat(100)),
+            file("src/nested/big.rs", big.clone()),
+            file("d.rs", "d".repeat(50)),
+        ];
+        let config = Config::default();
+        let options = Options {
+            tree: true,
+            ..Options::default()
+        };
+        let budget = 450;
+        let parts = split_by_tokens(&config, &options, files, budget)?;
+
+        // Every part as written, heading and all, stays within the budget
+        for (index, part) in parts.iter().enumerate() {
+            let options = Options {
+                part: Some((index + 1, parts.len())),
+                ..options.clone()
+            };
+            let tokens = part_tokens(&config, &options, part)?;
+            assert!(tokens <= budget, "part {} has {} tokens", index + 1, tokens);
+        }
+
+        let mut paths: Vec<&str> = parts
+            .iter()
+            .flatten()
+            .map(|file| file.entry.relative_path.to_str().unwrap())
+            .collect();
+        paths.dedup();
+        assert_eq!(
+            paths,
+            vec!["a.rs", "src/b.rs", "c.rs", "src/nested/big.rs", "d.rs"]
+        );
+        let chunks: Vec<&GatheredFile> = parts
+            .iter()
+            .flatten()
+            .filter(|file| file.chunk.is_some())
+            .collect();
+        assert!(chunks.len() > 1);
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.entry.relative_path.ends_with("big.rs")));
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|chunk| chunk.content.as_str())
+                .collect::<String>(),
+            big
+        );
+
+        assert!(split_by_tokens(&config, &options, vec![], 10).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_tokens_counts_the_rendered_output() -> Result<()> {
+        let files: Vec<GatheredFile> = (0..8)
+            .map(|index| GatheredFile {
+                content: format!("fn f{}() {{}}\n", index).repeat(4),
+                ..gathered(&format!("src/file{}.rs", index))
+            })
+            .collect();
+        let config = Config::default();
+        let unlimited = Options {
+            line_numbers: true,
+            tree: true,
+            ..Options::default()
+        };
+        let mut out = Vec::new();
+        render(&mut out, &config, &unlimited, &files)?;
+        let full = tokens::estimate_tokens(&String::from_utf8(out)?);
+
+        // Half the full output: the prompt and a few files fit, not all
+        let max = full / 2;
+        let options = Options {
+            max_tokens: Some(max),
+            ..unlimited
+        };
+        let mut out = Vec::new();
+        let summary = render(&mut out, &config, &options, &files)?;
+        let output = String::from_utf8(out)?;
+
+        assert!(tokens::estimate_tokens(&output) <= max);
+        let written = summary.file_tokens.len();
+        assert!(written > 0 && !summary.omitted.is_empty());
+        assert_eq!(written + summary.omitted.len(), files.len());
+        assert_eq!(
+            summary.omitted[0],
+            Path::new(&format!("src/file{}.rs", written))
+        );
+        assert!(output.contains(&format!("File: src/file{}.rs\n", written - 1)));
+        assert!(!output.contains(&format!("file{}.rs", written)));
+
+        // A budget of the full size keeps every file
+        let options = Options {
+            max_tokens: Some(full),
+            ..options
+        };
+        let summary = render(&mut io::sink(), &config, &options, &files)?;
+        assert!(summary.omitted.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_built_in_prompt_follows_the_date_format() -> Result<()> {
+        let render_with = |config: &Config, date_format| -> Result<String> {
+            let options = Options {
+                date_format,
+                ..Options::default()
+            };
+            let mut out = Vec::new();
+            render(&mut out, config, &options, &[gathered("a.rs")])?;
+            Ok(String::from_utf8(out)?)
+        };
+        let output = render_with(&Config::default(), DateFormat::Relative)?;
+        assert!(output.contains("- Last update: how long ago the last change"));
+
+        // A prompt of the config's own is written as it is
+        let config = Config {
+            llm_prompt: "Review this".to_string(),
+            ..Config::default()
+        };
+        assert!(render_with(&config, DateFormat::Relative)?
+            .starts_with("Review this\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_dedupe_points_repeats_at_the_first_copy() -> Result<()> {
+        let file = |path: &str, content: &str| GatheredFile {
+            content: content.to_string(),
+            ..gathered(path)
+        };
+        let files = [
+            file("a/LICENSE.rs", "// MIT\n"),
+            file("b.rs", "fn b() {}\n"),
+            file("c/LICENSE.rs", "// MIT\n"),
+        ];
+        let options = Options {
+            dedupe: true,
+            no_prompt: true,
+            manifest: false,
+            ..Options::default()
+        };
+        let mut out = Vec::new();
+        render(&mut out, &Config::default(), &options, &files)?;
+        let output = String::from_utf8(out)?;
+
+        let sections: Vec<&str> = output.split("File: ").skip(1).collect();
+        assert_eq!(sections.len(), 3);
+        assert!(sections[0].starts_with("a/LICENSE.rs\n"));
+        assert!(sections[0].contains("---\n// MIT\n"));
+        assert!(sections[1].contains("---\nfn b() {}\n"));
+        assert!(sections[2].starts_with("c/LICENSE.rs\n"));
+        assert!(sections[2].contains("---\n(identical to a/LICENSE.rs)\n"));
+        assert!(!sections[2].contains("// MIT"));
+
+        // Without --dedupe both copies are written out
+        let options = Options {
+            dedupe: false,
+            ..options
+        };
+        let mut out = Vec::new();
+        render(&mut out, &Config::default(), &options, &files)?;
+        let output = String::from_utf8(out)?;
+        assert_eq!(output.matches("// MIT\n").count(), 2);
+        assert!(!output.contains("identical to"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_summary_block_opens_the_output() -> Result<()> {
+        let file = |path: &str, content: &str| GatheredFile {
+            content: content.to_string(),
+            ..gathered(path)
+        };
+        let files = [file("a.rs", "fn a() {}\n"), file("src/b.rs", "fn b() {}\n")];
+        let config = Config {
+            summary_block: true,
+            ..Config::default()
+        };
+        // The tree is only written once, inside the summary
+        let options = Options {
+            no_prompt: true,
+            tree: true,
+            ..Options::default()
+        };
+        let mut out = Vec::new();
+        render(&mut out, &config, &options, &files)?;
+        let output = String::from_utf8(out)?;
+
+        assert!(output.starts_with(
+            "Files analyzed: 2\nTotal size: 20 B\nEstimated tokens: 6\n\n\
+             Directory structure:\n.\n├── a.rs\n└── src\n    └── b.rs\n\n---\nFile: a.rs\n"
+        ));
+        assert_eq!(output.matches("└── src").count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_headers_fall_back_to_the_filesystem_mtime() -> Result<()> {
+        let modified = Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let files = [
+            GatheredFile {
+                untracked: true,
+                modified,
+                ..gathered("new.rs")
+            },
+            GatheredFile {
+                modified,
+                ..gathered("unknown.rs")
+            },
+            gathered("no_mtime.rs"),
+        ];
+        let options = Options {
+            no_prompt: true,
+            date_format: DateFormat::Unix,
+            ..Options::default()
+        };
+        let mut out = Vec::new();
+        render(&mut out, &Config::default(), &options, &files)?;
+        let output = String::from_utf8(out)?;
+
+        assert!(output.contains(
+            "File: new.rs\nLast commit: untracked\n\
+             Last update: 1700000000 (filesystem mtime)\n"
+        ));
+        assert!(output.contains(
+            "File: unknown.rs\nLast commit: unknown\n\
+             Last update: 1700000000 (filesystem mtime)\n"
+        ));
+        assert!(output.contains(
+            "File: no_mtime.rs\nLast commit: unknown\nLast update: unknown\n"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_by_dir_keeps_directories_together() {
+        let files: Vec<GatheredFile> =
+            ["src/main.rs", "a.rs", "src/x/c.rs", "src/b.rs", "z.rs"]
+                .map(gathered)
+                .into();
+        let options = Options {
+            group_by_dir: true,
+            ..Options::default()
+        };
+
+        let paths: Vec<&str> = group_by_dir(&options, &files)
+            .iter()
+            .map(|file| file.entry.relative_path.to_str().unwrap())
+            .collect();
+        assert_eq!(
+            paths,
+            ["src/main.rs", "src/b.rs", "a.rs", "z.rs", "src/x/c.rs"]
+        );
+        assert_eq!(parent_dir(Path::new("a.rs")), Path::new("."));
+    }
+
+    /// Sort `files` by `sort` and return the resulting paths
+    fn sorted(mut files: Vec<GatheredFile>, sort: SortOrder) -> Vec<String> {
+        sort_gathered(&mut files, sort);
+        files
+            .iter()
+            .map(|file| file.entry.relative_path.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_sort_by_path() {
+        let files = ["src/b.rs", "a.rs", "src/a.rs"].map(gathered).into();
+        assert_eq!(
+            sorted(files, SortOrder::Path),
+            ["a.rs", "src/a.rs", "src/b.rs"]
+        );
+    }
+
+    #[test]
+    fn test_sort_by_mtime() {
+        let at = |path: &str, secs: Option<u64>| GatheredFile {
+            modified: secs.map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+            ..gathered(path)
+        };
+        let files = vec![
+            at("old.rs", Some(100)),
+            at("none.rs", None),
+            at("tie_b.rs", Some(200)),
+            at("new.rs", Some(300)),
+            at("tie_a.rs", Some(200)),
+        ];
+        assert_eq!(
+            sorted(files, SortOrder::Mtime),
+            ["new.rs", "tie_a.rs", "tie_b.rs", "old.rs", "none.rs"]
+        );
+    }
+
+    #[test]
+    fn test_sort_by_git_time() {
+        let at = |path: &str, commit_time: Option<i64>| GatheredFile {
+            git: commit_time.map(|commit_time| GitInfo {
+                commit_hash: "0123456789abcdef".to_string(),
+                commit_time,
+                commit_offset_minutes: 0,
+                author_name: "Test User".to_string(),
+                author_email: "test@example.com".to_string(),
+                summary: "Change".to_string(),
+            }),
+            ..gathered(path)
+        };
+        let files = vec![
+            at("untracked.rs", None),
+            at("old.rs", Some(100)),
+            at("tie_b.rs", Some(200)),
+            at("tie_a.rs", Some(200)),
+            at("new.rs", Some(300)),
+        ];
+        assert_eq!(
+            sorted(files, SortOrder::GitTime),
+            ["new.rs", "tie_a.rs", "tie_b.rs", "old.rs", "untracked.rs"]
+        );
+    }
+
+    #[test]
+    fn test_sort_by_size() {
+        let sized = |path: &str, len: usize| GatheredFile {
+            content: "x".repeat(len),
+            ..gathered(path)
+        };
+        let files = vec![
+            sized("small.rs", 1),
+            sized("tie_b.rs", 5),
+            sized("big.rs", 10),
+            sized("tie_a.rs", 5),
+        ];
+        assert_eq!(
+            sorted(files, SortOrder::Size),
+            ["big.rs", "tie_a.rs", "tie_b.rs", "small.rs"]
+        );
+    }
+
+    #[test]
+    fn test_sort_by_priority() -> Result<()> {
+        let mut files: Vec<GatheredFile> =
+            ["a.rs", "src/lib.rs", "src/main.rs", "tests/cli.rs", "z.rs"]
+                .map(gathered)
+                .into();
+        let priority = ["src/main.rs".to_string(), "tests/*".to_string()];
+        sort_by_priority(&mut files, &priority)?;
+
+        let paths: Vec<&str> = files
+            .iter()
+            .map(|file| file.entry.relative_path.to_str().unwrap())
+            .collect();
+        assert_eq!(
+            paths,
+            ["src/main.rs", "tests/cli.rs", "a.rs", "src/lib.rs", "z.rs"]
+        );
+        assert!(sort_by_priority(&mut files, &["[".to_string()]).is_err());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_describe_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let describe =
+            |mode| describe_permissions(&fs::Permissions::from_mode(mode));
+        assert_eq!(describe(0o100644), "0644");
+        assert_eq!(describe(0o100755), "0755 (executable)");
+    }
+
+    #[test]
+    fn test_split_by_top_dir() {
+        let files = ["build.rs", "src/main.rs", "tests/cli.rs", "src/a/b.rs"]
+            .map(gathered)
+            .into();
+        let groups: Vec<(String, Vec<String>)> = split_by_top_dir(files)
+            .into_iter()
+            .map(|(group, files)| {
+                let paths = files
+                    .iter()
+                    .map(|f| f.entry.relative_path.display().to_string())
+                    .collect();
+                (group, paths)
+            })
+            .collect();
+
+        assert_eq!(
+            groups,
+            vec![
+                ("_root".to_string(), vec!["build.rs".to_string()]),
+                (
+                    "src".to_string(),
+                    vec!["src/main.rs".to_string(), "src/a/b.rs".to_string()]
+                ),
+                ("tests".to_string(), vec!["tests/cli.rs".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_head_and_tail_keep_line_numbers() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        Repository::init(temp_dir.path())?;
+        fs::write(temp_dir.path().join("long.rs"), "a\nb\nc\nd\ne\n")?;
+
+        let options = Options {
+            dirs: vec![temp_dir.path().to_string_lossy().into_owned()],
+            no_prompt: true,
+            manifest: false,
+            line_numbers: true,
+            head: Some(1),
+            tail: Some(1),
+            ..Options::default()
+        };
+        let output = generate(&Config::default(), &options)?;
+
+        assert!(output.contains("Note: truncated\n"));
+        assert!(output
+            .ends_with("---\n   1 | a\n... 3 lines omitted ...\n   5 | e\n\n\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_annotates_committed_files() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let repo = Repository::init(temp_dir.path())?;
+        fs::write(temp_dir.path().join("lib.rs"), "pub fn f() {}\n")?;
+        fs::write(temp_dir.path().join("new.rs"), "fn g() {}\n")?;
+
+        let mut index = repo.index()?;
+        index.add_path(Path::new("lib.rs"))?;
+        let tree = repo.find_tree(index.write_tree()?)?;
+        let signature =
+            Signature::new("Test User", "test@example.com", &Time::new(100, 0))?;
+        let commit = repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            "Add lib",
+            &tree,
+            &[],
+        )?;
+
+        let options = Options {
+            dirs: vec![temp_dir.path().to_string_lossy().into_owned()],
+            no_prompt: true,
+            ..Options::default()
+        };
+        let output = generate(&Config::default(), &options)?;
+
+        assert!(output.starts_with(&format!(
+            "---\nFile: lib.rs\nLast commit: {}\nLast update: 1970-01-01T00:01:40+00:00\n\
+             Author: Test User <test@example.com>\nMessage: Add lib\n---\npub fn f() {{}}\n",
+            commit
+        )));
+        assert!(output.contains("File: new.rs\nLast commit: untracked\n"));
+        assert!(output.ends_with(&format!(
+            "---\nManifest: 2 file(s)\n---\nlib.rs  14 B  {}\nnew.rs  10 B  untracked\n\n",
+            &commit.to_string()[..7]
+        )));
+
+        let options = Options {
+            prepend: vec!["# Design".to_string(), "Goals\n".to_string()],
+            append: vec!["Requirements".to_string()],
+            ..options
+        };
+        let output = generate(&Config::default(), &options)?;
+        assert!(output.starts_with("# Design\nGoals\n---\nFile: lib.rs\n"));
+        assert!(output.ends_with("  untracked\n\nRequirements\n"));
+
+        let options = Options {
+            format: Format::Json,
+            ..options
+        };
+        assert!(generate(&Config::default(), &options).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unreadable_files_are_skipped_unless_fail_fast() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        Repository::init(temp_dir.path())?;
+        fs::write(temp_dir.path().join("a.rs"), "fn a() {}\n")?;
+        let entries = || {
+            ["a.rs", "gone.rs"]
+                .map(|name| FileEntry {
+                    absolute_path: temp_dir.path().join(name),
+                    relative_path: name.into(),
+                })
+                .into()
+        };
+        let options = Options {
+            dirs: vec![temp_dir.path().to_string_lossy().into_owned()],
+            ..Options::default()
+        };
+
+        let gathered = gather(&Config::default(), &options, entries())?;
+        assert_eq!(gathered.files.len(), 1);
+        assert_eq!(gathered.unreadable, 1);
+        assert_eq!(gathered.skipped[0].path, PathBuf::from("gone.rs"));
+        assert!(gathered.skipped[0].reason.starts_with("unreadable ("));
+
+        let options = Options {
+            fail_fast: true,
+            ..options
+        };
+        let err = gather(&Config::default(), &options, entries())
+            .err()
+            .unwrap();
+        assert!(err.to_string().starts_with("Failed to read "));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_vanishing_after_the_read_is_unreadable() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        Repository::init(temp_dir.path())?;
+        fs::write(temp_dir.path().join("a.rs"), "fn a() {}\n")?;
+        let entries = || -> Vec<FileEntry> {
+            vec![FileEntry {
+                absolute_path: temp_dir.path().join("a.rs"),
+                relative_path: "a.rs".into(),
+            }]
+        };
+        // The filter runs between the read and the modification time lookup
+        let config = Config {
+            filters: [("rs".to_string(), "rm \"$AMC_FILE\"; cat".to_string())]
+                .into(),
+            ..Config::default()
+        };
+        let options = Options {
+            dirs: vec![temp_dir.path().to_string_lossy().into_owned()],
+            allow_filters: true,
+            ..Options::default()
+        };
+
+        let gathered = gather(&config, &options, entries())?;
+        assert!(gathered.files.is_empty());
+        assert_eq!(gathered.unreadable, 1);
+        assert!(gathered.skipped[0].reason.starts_with("unreadable ("));
+
+        fs::write(temp_dir.path().join("a.rs"), "fn a() {}\n")?;
+        let options = Options {
+            fail_fast: true,
+            ..options
+        };
+        let Err(err) = gather(&config, &options, entries()) else {
+            panic!("a vanished file was gathered under --fail-fast");
+        };
+        assert!(err.to_string().starts_with("Failed to read "));
+        Ok(())
+    }
+
+    /// Records the size of every write instead of keeping the bytes
+    #[derive(Default)]
+    struct WriteSizes {
+        writes: usize,
+        largest: usize,
+        total: usize,
+    }
+
+    impl Write for WriteSizes {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes += 1;
+            self.largest = self.largest.max(buf.len());
+            self.total += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_every_format_streams_file_by_file() -> Result<()> {
+        let files: Vec<GatheredFile> = (0..500)
+            .map(|index| GatheredFile {
+                content: format!("// file {}\n{}\n", index, "x".repeat(1000)),
+                ..gathered(&format!("src/file{:03}.rs", index))
+            })
+            .collect();
+        let config = Config::default();
+
+        for format in Format::value_variants() {
+            let options = Options {
+                format: *format,
+                ..Options::default()
+            };
+            let mut sizes = WriteSizes::default();
+            render(&mut sizes, &config, &options, &files)?;
+            // Over 500 KiB went out, but never more than about one file (or
+            // the prompt) at a time
+            assert!(sizes.total > 500 * 1000, "{:?}", format);
+            assert!(sizes.writes > files.len(), "{:?}", format);
+            assert!(sizes.largest < 4096, "{:?}: {}", format, sizes.largest);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_single_fence_outgrows_inner_fences() -> Result<()> {
+        let render_fenced = |content: &str| -> Result<String> {
+            let files = [GatheredFile {
+                content: content.to_string(),
+                ..gathered("src/main.rs")
+            }];
+            let options = Options {
+                format: Format::SingleFence,
+                manifest: false,
+                ..Options::default()
+            };
+            let config = Config {
+                llm_prompt: "Prompt".to_string(),
+                ..Config::default()
+            };
+            let mut out = Vec::new();
+            render(&mut out, &config, &options, &files)?;
+            Ok(String::from_utf8(out)?)
+        };
+
+        let output = render_fenced("fn main() {}")?;
+        assert!(output.starts_with("```\nPrompt\n---\nFile: src/main.rs\n"));
+        assert!(output.ends_with("---\nfn main() {}\n\n```\n"), "{}", output);
+
+        let output = render_fenced("/// ````text\n/// ````")?;
+        assert!(output.starts_with("`````\nPrompt\n"));
+        assert!(output.ends_with("\n`````\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_fallbacks_come_from_the_config() -> Result<()> {
+        let files = [
+            gathered("a.rs"),
+            GatheredFile {
+                untracked: true,
+                ..gathered("new.rs")
+            },
+        ];
+        let options = Options {
+            no_prompt: true,
+            manifest: false,
+            ..Options::default()
+        };
+        let render_with = |config: &Config| -> Result<String> {
+            let mut out = Vec::new();
+            render(&mut out, config, &options, &files)?;
+            Ok(String::from_utf8(out)?)
+        };
+
+        let text = render_with(&Config::default())?;
+        assert!(text
+            .contains("File: a.rs\nLast commit: unknown\nLast update: unknown\n"));
+
+        let config = Config {
+            unknown_commit: String::new(),
+            unknown_time: Some("n/a".to_string()),
+            ..Config::default()
+        };
+        let text = render_with(&config)?;
+        assert!(text.contains(
+            "File: a.rs\nLast commit: \nLast update: n/a\nAuthor: \nMessage: \n"
+        ));
+        // Untracked files keep their own label
+        assert!(text.contains("File: new.rs\nLast commit: untracked\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_version_line_round_trips() -> Result<()> {
+        let files = [GatheredFile {
+            content: "fn a() {}\n".to_string(),
+            ..gathered("a.rs")
+        }];
+        let options = Options {
+            format_version: true,
+            ..Options::default()
+        };
+        let config = Config::default();
+        let mut out = Vec::new();
+        render(&mut out, &config, &options, &files)?;
+        let text = String::from_utf8(out)?;
+        assert!(text.starts_with("# amc-format: 2\n"));
+
+        let parsed = parse::parse_document(&text, &config.delimiters(), "")?;
+        assert_eq!(parsed.version, Some(output::FORMAT_VERSION));
+        assert_eq!(parsed.prompt, config.llm_prompt.trim_end());
+        assert_eq!(parsed.files[0].content, "fn a() {}\n");
+
+        let options = Options {
+            format: Format::Json,
+            ..options
+        };
+        assert!(render(&mut Vec::new(), &config, &options, &files).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_template_layouts_the_output() -> Result<()> {
+        let files = [
+            GatheredFile {
+                content: "fn a() {}".to_string(),
+                ..gathered("a.rs")
+            },
+            GatheredFile {
+                content: "// {path} stays as written".to_string(),
+                comments_stripped: true,
+                permissions: Some("0644".to_string()),
+                ..gathered("src/b.rs")
+            },
+        ];
+        let render_as = |config: &Config,
+                         options: &Options,
+                         template: Option<&str>|
+         -> Result<String> {
+            let options = Options {
+                template: template.map(str::to_string),
+                ..options.clone()
+            };
+            let mut out = Vec::new();
+            render(&mut out, config, &options, &files)?;
+            Ok(String::from_utf8(out)?)
+        };
+
+        // The bundled template reproduces the plain layout byte for byte
+        let with_footer = Config {
+            footer_prompt: "Review the files above.".to_string(),
+            ..Config::default()
+        };
+        let no_prompt = Options {
+            no_prompt: true,
+            ..Options::default()
+        };
+        let numbered = Options {
+            number_files: true,
+            manifest: false,
+            ..Options::default()
+        };
+        for config in [&Config::default(), &with_footer] {
+            for options in [&Options::default(), &no_prompt, &numbered] {
+                assert_eq!(
+                    render_as(config, options, Some(output::DEFAULT_TEMPLATE))?,
+                    render_as(config, options, None)?
+                );
+            }
+        }
+
+        let config = Config::default();
+        let render_with = |template: Option<&str>| {
+            render_as(&config, &Options::default(), template)
+        };
+
+        let custom = "<docs>\n{files}<doc path=\"{path}\">{content}</doc>\n{/files}</docs>\n";
+        assert_eq!(
+            render_with(Some(custom))?,
+            "<docs>\n<doc path=\"a.rs\">fn a() {}</doc>\n\
+             <doc path=\"src/b.rs\">// {path} stays as written</doc>\n</docs>\n"
+        );
+
+        let err = render_with(Some("{files}{path}")).unwrap_err();
+        assert_eq!(err.to_string(), "Template block {files} is never closed");
+        assert!(render_with(Some("{path}")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_number_files() -> Result<()> {
+        let files = [gathered("a.rs"), gathered("src/b.rs")];
+        let render_as = |format: Format| -> Result<String> {
+            let options = Options {
+                format,
+                number_files: true,
+                no_prompt: true,
+                manifest: false,
+                ..Options::default()
+            };
+            let mut out = Vec::new();
+            render(&mut out, &Config::default(), &options, &files)?;
+            Ok(String::from_utf8(out)?)
+        };
+
+        let plain = render_as(Format::Plain)?;
+        assert!(plain.starts_with("---\n[File 1/2]\nFile: a.rs\n"));
+        assert!(plain.contains("---\n[File 2/2]\nFile: src/b.rs\n"));
+        assert!(
+            render_as(Format::Markdown)?.contains("[File 2/2]\n- File: `src/b.rs`")
+        );
+        assert!(render_as(Format::Xml)?
+            .contains("<file index=\"2\" total=\"2\" path=\"src/b.rs\""));
+
+        let value: serde_json::Value =
+            serde_json::from_str(&render_as(Format::Json)?)?;
+        assert_eq!(value["files"][1]["index"], 2);
+        assert_eq!(value["files"][1]["total"], 2);
+
+        // Off by default
+        let mut out = Vec::new();
+        render(&mut out, &Config::default(), &Options::default(), &files)?;
+        assert!(!String::from_utf8(out)?.contains("[File "));
+        Ok(())
+    }
+
+    #[test]
+    fn test_dirs_must_share_a_repository() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        for repo in ["one", "two"] {
+            fs::create_dir_all(temp_dir.path().join(repo).join("src"))?;
+            Repository::init(temp_dir.path().join(repo))?;
+        }
+        let dir =
+            |path: &str| temp_dir.path().join(path).to_string_lossy().into_owned();
+        let options = Options {
+            dirs: vec![dir("one"), dir("one/src")],
+            ..Options::default()
+        };
+        assert!(gather(&Config::default(), &options, Vec::new()).is_ok());
+
+        let options = Options {
+            dirs: vec![dir("one/src"), dir("two/src")],
+            ..options
+        };
+        let Err(err) = gather(&Config::default(), &options, Vec::new()) else {
+            panic!("dirs from two repositories were accepted");
+        };
+        assert!(err.to_string().contains("is in another Git repository"));
+
+        let options = Options {
+            no_git: true,
+            ..options
+        };
+        assert!(gather(&Config::default(), &options, Vec::new()).is_ok());
+        Ok(())
+    }
+
+    /// Benchmark rather than a check of behavior; run with
+    /// `cargo test --release -- --ignored --nocapture parallel`
+    #[test]
+    #[ignore]
+    fn test_parallel_gather_beats_one_thread() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let repo = Repository::init(temp_dir.path())?;
+        let signature =
+            Signature::new("Test User", "test@example.com", &Time::new(100, 0))?;
+        // Many commits, each touching a few files, so history lookups walk far
+        let mut parent = None;
+        for commit in 0..200 {
+            let mut index = repo.index()?;
+            for file in 0..5 {
+                let path =
+                    format!("dir{}/file{}.rs", commit % 20, commit * 5 + file);
+                fs::create_dir_all(temp_dir.path().join(&path).parent().unwrap())?;
+                fs::write(
+                    temp_dir.path().join(&path),
+                    format!("fn f{}() {{}}\n", commit),
+                )?;
+                index.add_path(Path::new(&path))?;
+            }
+            index.write()?;
+            let tree = repo.find_tree(index.write_tree()?)?;
+            let parents: Vec<&git2::Commit> = parent.iter().collect();
+            let id = repo.commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                &format!("Commit {}", commit),
+                &tree,
+                &parents,
+            )?;
+            parent = Some(repo.find_commit(id)?);
+        }
+
+        let options = Options {
+            dirs: vec![temp_dir.path().to_string_lossy().into_owned()],
+            // Both runs have to look every file up
+            cache: false,
+            ..Options::default()
+        };
+        let timed = |threads: usize| -> Result<(Duration, Vec<Option<String>>)> {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()?;
+            let files = collect_files(&Config::default(), &options)?;
+            let started = std::time::Instant::now();
+            let gathered =
+                pool.install(|| gather(&Config::default(), &options, files))?;
+            let hashes = gathered
+                .files
+                .iter()
+                .map(|file| file.git.as_ref().map(|git| git.commit_hash.clone()))
+                .collect();
+            Ok((started.elapsed(), hashes))
+        };
+        let threads = std::thread::available_parallelism()?.get();
+        let (sequential, expected) = timed(1)?;
+        let (parallel, hashes) = timed(threads)?;
+        println!(
+            "1000 files: {:?} on one thread, {:?} on {}",
+            sequential, parallel, threads
+        );
+
+        assert_eq!(hashes, expected);
+        assert!(expected.iter().all(Option::is_some));
+        if threads > 1 {
+            assert!(parallel < sequential);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_exclude_and_only_tests() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        Repository::init(temp_dir.path())?;
+        fs::create_dir(temp_dir.path().join("tests"))?;
+        let files = [
+            ("main.rs", "fn main() {}\n"),
+            ("lib.rs", "#[cfg(test)]\nmod tests {}\n"),
+            ("tests/cli.rs", "#[test]\nfn cli() {}\n"),
+        ];
+        for (path, content) in files {
+            fs::write(temp_dir.path().join(path), content)?;
+        }
+        let entries = || {
+            files
+                .map(|(path, _)| FileEntry {
+                    absolute_path: temp_dir.path().join(path),
+                    relative_path: path.into(),
+                })
+                .into()
+        };
+        let paths = |options: &Options| -> Result<Vec<String>> {
+            let gathered = gather(&Config::default(), options, entries())?;
+            Ok(gathered
+                .files
+                .iter()
+                .map(|file| file.entry.relative_path.to_string_lossy().into_owned())
+                .collect())
+        };
+        let options = Options {
+            dirs: vec![temp_dir.path().to_string_lossy().into_owned()],
+            ..Options::default()
+        };
+
+        let gathered = gather(&Config::default(), &options, entries())?;
+        let kinds: Vec<FileKind> = gathered.files.iter().map(|f| f.kind).collect();
+        assert_eq!(
+            kinds,
+            [FileKind::SourceWithTests, FileKind::Source, FileKind::Test]
+        );
+
+        let excluded = Options {
+            exclude_tests: true,
+            ..options.clone()
+        };
+        assert_eq!(paths(&excluded)?, ["lib.rs", "main.rs"]);
+        let only = Options {
+            only_tests: true,
+            ..options
+        };
+        assert_eq!(paths(&only)?, ["lib.rs", "tests/cli.rs"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_skips_only_minified_web_assets() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        Repository::init(temp_dir.path())?;
+        let long_line = format!("{}\n", "x".repeat(3000));
+        let names = ["bundle.js", "table.md", "fixture.rs"];
+        for name in names {
+            fs::write(temp_dir.path().join(name), &long_line)?;
+        }
+        let entries: Vec<FileEntry> = names
+            .map(|name| FileEntry {
+                absolute_path: temp_dir.path().join(name),
+                relative_path: name.into(),
+            })
+            .into();
+        let config = Config {
+            extensions: ["js", "md", "rs"].map(String::from).into(),
+            ..Config::default()
+        };
+        let options = Options {
+            dirs: vec![temp_dir.path().to_string_lossy().into_owned()],
+            ..Options::default()
+        };
+
+        let gathered = gather(&config, &options, entries)?;
+        let kept: Vec<&Path> = gathered
+            .files
+            .iter()
+            .map(|file| file.entry.relative_path.as_path())
+            .collect();
+        assert_eq!(kept, [Path::new("fixture.rs"), Path::new("table.md")]);
+        assert_eq!(gathered.skipped.len(), 1);
+        assert_eq!(gathered.skipped[0].path, Path::new("bundle.js"));
+        assert!(gathered.skipped[0].reason.starts_with("looks minified"));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_filters_replace_content_or_fall_back() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        Repository::init(temp_dir.path())?;
+        fs::write(temp_dir.path().join("a.rs"), "fn a() {}\n")?;
+        fs::write(temp_dir.path().join("b.py"), "b = 1\n")?;
+        let entries: Vec<FileEntry> = ["a.rs", "b.py"]
+            .map(|name| FileEntry {
+                absolute_path: temp_dir.path().join(name),
+                relative_path: name.into(),
+            })
+            .into();
+        let config = Config {
+            extensions: vec!["rs".to_string(), "py".to_string()],
+            filters: [("rs", "tr a-z A-Z"), ("py", "exit 1")]
+                .map(|(ext, command)| (ext.to_string(), command.to_string()))
+                .into(),
+            ..Config::default()
+        };
+        let options = Options {
+            dirs: vec![temp_dir.path().to_string_lossy().into_owned()],
+            ..Options::default()
+        };
+
+        // Nothing runs without the opt-in
+        let gathered = gather(&config, &options, entries.clone())?;
+        assert_eq!(gathered.files[0].content, "fn a() {}\n");
+        assert!(!gathered.files[0].filtered);
+
+        let options = Options {
+            allow_filters: true,
+            ..options
+        };
+        let gathered = gather(&config, &options, entries)?;
+        assert_eq!(gathered.files[0].content, "FN A() {}\n");
+        assert!(gathered.files[0].filtered);
+        assert_eq!(gathered.files[1].content, "b = 1\n");
+        assert!(!gathered.files[1].filtered);
+        Ok(())
+    }
+}
@@ -6,118 +6,1217 @@
 //! - The relative file path
 //! - The last Git commit hash that modified the file
 //! - The timestamp of the last update
+//! - The commit author and the first line of the commit message
 //!
 //! # Configuration
 //!
-//! The tool uses a TOML configuration file (default: `.amc.toml`) that specifies:
+//! The tool uses a TOML configuration file (default: the nearest `.amc.toml`
+//! between `--dir` and the repository root; `--config` files ending in
+//! `.yaml`/`.yml` or `.json` are read as YAML or JSON) that specifies:
 //! - `delimiter`: String used to separate file headers from content
+//! - `comment_delimiters`: Write plain headers as comments in the file's
+//!   language, e.g. `// File: src/main.rs`
+//! - `summary_block`: Start the output with the file count, total size,
+//!   estimated tokens and directory tree, laid out like gitingest's summary
 //! - `extensions`: List of file extensions to process
+//! - `exclude_if_contains`: Skip files whose first lines contain one of these
+//!   markers (e.g. `@generated`)
+//! - `llm_prompt` / `llm_prompt_file`: Prompt text written before the files,
+//!   inline or read from a file relative to the config (default: a description
+//!   of the header lines, matching `--date-format`)
+//! - `footer_prompt`: Instructions written after the last file (default: none)
+//! - `max_blank_lines`: Collapse longer runs of blank lines in file content,
+//!   as `--squeeze-blank` does (default: off)
+//! - `header_template`: Plain-format header lines with `{path}`, `{hash}`,
+//!   `{short_hash}`, `{time}`, `{author}`, `{message}` and `{size}`
+//!   placeholders (default: the `File:`/`Last commit:`/... lines)
+//! - `unknown_commit` / `unknown_time`: Header values for files whose history
+//!   can't be resolved (default: "unknown", and the filesystem mtime)
+//! - `[formats."<ext>"]`: Per-extension `delimiter` and `line_numbers`
+//!   overriding the global settings for matching files
+//! - `[filters]`: Per-extension shell command each matching file's content is
+//!   piped through, its output replacing the content; only run with
+//!   `--allow-filters`, as the config may come from an untrusted repository
+//! - `include_globs` / `exclude_globs`: Glob patterns that add or remove files
+//!   independently of their extension; exclusion wins
+//! - `excluded_files`: Exact paths, relative to `--dir`, to leave out
+//! - `priority`: Paths or globs of files to write first, in the listed order,
+//!   ahead of the `--sort` order
+//!
+//! Files matched by a `.amcignore` (gitignore syntax) are skipped as well.
 //!
 //! # Example Usage
 //!
 //! ```bash
 //! $ amc --dir ./src --config .amc.toml
+//! $ amc --dir crate-a/src --dir crate-b/src
 //! ```
 //!
+//! # Subcommands
+//!
+//! - `init [--force]`: Write a commented config file to the `--config` path
+//! - `stats [--top N]`: Files, lines (code, comment and blank) and bytes per
+//!   extension plus the largest files, without emitting any content; comment
+//!   counts are approximate
+//! - `diff <OLD> <NEW> [--full]`: Parse two plain or JSON outputs generated
+//!   with the same config and list added (`A`), removed (`D`) and modified
+//!   (`M`) files with line counts; `--full` adds unified diffs
+//! - `clear-cache`: Delete the per-file Git lookup cache (`amc-cache.json` in
+//!   the Git directory)
+//! - `completions <bash|zsh|fish|powershell|elvish>`: Print a shell completion
+//!   script generated from these arguments
+//!
 //! # Command Line Arguments
 //!
-//! - `-d, --dir`: Directory to scan (default: ".")
-//! - `-c, --config`: Path to config file (default: ".amc.toml")
+//! - `-d, --dir`: Directory to scan, repeatable or comma-separated, all in one
+//!   Git repository; a file instead annotates just that file (default: ".")
+//! - `--files-from`: Read newline-separated paths from a file or `-` (stdin)
+//!   and annotate exactly those instead of walking; paths are shown relative
+//!   to the first `--dir`
+//! - `--max-files`: Abort when more files than this match, guarding against
+//!   scanning the wrong directory; `0` disables the limit (default: 1000)
+//! - `--exclude-file`: Leave out one file by its exact path relative to
+//!   `--dir`; repeatable, added to the config's `excluded_files`
+//! - `--repo-root[=DIR]`: Show paths relative to DIR, or to the Git top-level
+//!   without a value, instead of relative to `--dir`; globs still match
+//!   against paths under `--dir`
+//! - `--paths`: Show paths relative to the scanned directory (`scan`), the
+//!   current directory (`cwd`, with `../` for files outside it) or the Git
+//!   top-level (`repo`, like `--repo-root`) (default: "scan")
+//! - `-c, --config`: Path to config file, falling back to the `AMC_CONFIG`
+//!   environment variable (default: the nearest `.amc.toml` found walking up
+//!   from `--dir` to the Git root)
+//! - `-o, --output`: File to write the result to, `-` for stdout (default: "-")
+//! - `--output-dir`: Write one file per top-level subdirectory into this
+//!   directory instead, e.g. `src.txt`, `tests.txt`, plus `_root.txt` for
+//!   files directly in `--dir`
+//! - `--summary`: Write a JSON description of the run to a sidecar file:
+//!   file count, bytes, estimated tokens, the config used, and every
+//!   included or skipped path, the latter with the reason
+//! - `--compress`: Compress the output with `gzip` or `zstd`, adding the
+//!   matching extension to `--output` if it is missing
+//! - `--clipboard`: Copy the result to the system clipboard (and to `--output`
+//!   when it names a file); on Linux a background `amc` process keeps serving
+//!   it until something else is copied
+//! - `--post-hook`: Shell command run after each output file is written, with
+//!   the quoted path substituted for `{output}` and set as `AMC_OUTPUT`; a
+//!   non-zero exit status fails the run
+//! - `--allow-filters`: Run the config's `[filters]` commands, which are
+//!   ignored by default
+//! - `-f, --format`: Output layout, `plain`, `markdown`, `json`, `xml` or
+//!   `single-fence`, plain wrapped in one code block (default: "plain")
+//! - `--template-file`: Lay the output out from a template instead of a
+//!   built-in format; `templates/default.txt` reproduces the plain layout
+//! - `--output-format-version`: Start plain output with `# amc-format: N`, the
+//!   layout version that `parse::parse_document` checks
+//! - `--no-git`: Work on directories outside a Git repository, writing the
+//!   file's modification time instead of commit metadata
+//! - `--rev`: Revision to resolve each file's last commit from (default: "HEAD")
+//! - `--since`: Only include files whose last commit is newer than a date
+//!   (`2024-05-01`, `2 weeks ago`) or descends from a revision
+//! - `--since-include-untracked`: With `--since`, keep files that have no Git
+//!   history instead of leaving them out
+//! - `--sort`: Order files by `path`, `mtime`, `git-time` or `size` (default:
+//!   "path", or "git-time" together with `--max-tokens`)
+//! - `--date-format`: Commit time rendering, `unix`, `rfc3339` or `relative`
+//!   (default: "rfc3339")
+//! - `--skip-untracked`: Leave out files Git doesn't know about instead of
+//!   marking them as "untracked"
+//! - `--no-gitattributes`: Keep files marked `linguist-generated` or
+//!   `export-ignore` in `.gitattributes`, which are skipped by default
+//! - `--no-gitignore`: Ignore `.gitignore`, `.git/info/exclude` and the
+//!   global excludes file, e.g. to include a `dist/` being debugged;
+//!   `.amcignore` still applies
+//! - `--no-global-gitignore`: Only ignore the global excludes file
+//! - `--include-minified`: Keep `.js`, `.css` and other web assets that look
+//!   minified (a line over 2000 characters, or under 3% whitespace), which are
+//!   skipped by default
+//! - `--include-hidden`: Walk dotfiles and dot-directories such as `.env` or
+//!   `.github/`, which are skipped by default; `.git` is always left out
+//! - `--exclude-tests`: Leave out test files, recognized by their path
+//!   (`tests/`, `*_test.rs`, `*.test.ts`, `test_*.py`, ...)
+//! - `--only-tests`: Keep only test files and Rust files with a `#[cfg(test)]`
+//!   module
+//! - `--include-lockfiles`: Keep dependency lockfiles such as `Cargo.lock`,
+//!   `package-lock.json` or `poetry.lock`, which are skipped by default
+//! - `--follow-symlinks`: Follow symlinked files and directories; by default
+//!   symlinks are skipped. Loops are detected and every file is included once.
+//! - `--include-submodules`: Walk the work trees of Git submodules, which are
+//!   skipped by default, and annotate their files from each submodule's history
+//! - `--max-depth`: Descend at most this many levels, measured from each
+//!   `--dir`; `1` keeps only the files directly inside it
+//! - `--encoding`: Decode files that aren't valid UTF-8 (and have no byte
+//!   order mark) with this encoding instead of the detected one
+//! - `--max-file-size`: Skip files larger than the given size (e.g. `200k`, `1M`)
+//! - `--tree`: Print a directory tree of the included files after the prompt
+//! - `--repo-info`: Write branch, HEAD, commit count and dirty status first
+//! - `--show-perms`: Add a `Permissions:` header line with the file's mode
+//!   bits, flagging executables ("n/a" on Windows)
+//! - `--annotate-imports`: Add an `Imports:` header line listing the file's
+//!   Rust `use`, Python `import` or JavaScript/TypeScript `import`/`require`
+//! - `--number-files`: Start each file header with its position in the output,
+//!   e.g. `[File 3/27]`
+//! - `--group-by-dir`: Keep each directory's files together, each group under
+//!   a `### directory: <dir>` heading (plain, markdown and single-fence)
+//! - `--line-numbers`: Prefix each content line with its line number
+//! - `--strip-comments`: Remove comments from Rust, C-like (JS/TS, Go, Java,
+//!   C/C++), Python and shell files, leaving string literals intact; affected
+//!   headers carry a "comments stripped" note
+//! - `--squeeze-blank`: Collapse runs of blank lines in file content to the
+//!   config's `max_blank_lines` (default: 1)
+//! - `--head` / `--tail`: Keep only the first and/or last N lines of files
+//!   longer than that, with a `... K lines omitted ...` marker in between
+//! - `--prompt-file`: Read the LLM prompt from a file, overriding the config
+//! - `--footer-file`: Read the closing instructions from a file, overriding
+//!   `footer_prompt`
+//! - `--no-prompt`: Leave the LLM prompt and footer out of the output
+//! - `--prepend` / `--append`: Copy supporting documents verbatim before the
+//!   prompt or after the last file; repeatable, plain and markdown only
+//! - `--no-manifest`: Leave out the manifest listing every written file with
+//!   its size and short commit hash after the last file
+//! - `--list`: Print the files that would be included, with sizes, and exit
+//! - `--concurrency`: Worker threads for reading files (default: number of CPUs)
+//! - `--dedupe`: Emit identical files once; later copies say "identical to"
+//!   the first one
+//! - `--tokens`: Print estimated token counts per file and in total to stderr
+//! - `--max-tokens`: Stop including files once the estimated tokens of the
+//!   whole output, headers and manifest included, would exceed the budget,
+//!   preferring the most recently committed files
+//! - `--split-tokens`: Write the output as `NAME.partNN.EXT` files of at most N
+//!   estimated tokens each, every part starting with "Part X of Y" and the
+//!   prompt; only a file too large for one part is cut into chunks
+//! - `--redact`: Replace likely secrets (AWS keys, JWTs, `password=` values,
+//!   private keys, high-entropy strings) with `***REDACTED***`
+//! - `--no-cache`: Neither read nor update the per-file Git lookup cache, which
+//!   is otherwise reused while HEAD (or `--rev`) stays the same
+//! - `--fail-fast`: Abort on the first unreadable file; by default it is
+//!   skipped with a warning and listed as excluded in `--summary`
+//! - `-v, --verbose`: Log more to stderr; `-v` adds progress details, `-vv`
+//!   per-file debugging (default: warnings only)
+//! - `-q, --quiet`: Only report errors, hiding warnings, the progress counter
+//!   and the closing summary line
 //!
-use clap::Parser;
-use git2::Repository;
-use log::{info, LevelFilter};
+use all_my_circuits::config::{Config, ConfigFormat, CONFIG_FILE_NAME};
+use all_my_circuits::git::{
+    is_git_repository, repository_root, DateFormat, GitHistory, RepoInfo,
+};
+use all_my_circuits::output::Format;
+use all_my_circuits::{
+    cache, compress, diff, encoding, hook, parse, size, stats, FileEntry, Gathered,
+    GatheredFile, Options, RenderSummary, SortOrder,
+};
+use anyhow::Context;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use log::{info, warn, LevelFilter};
+use serde_json::json;
 use simple_logger::SimpleLogger;
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
-
-mod config;
-mod walk;
-
-use config::Config;
-use walk::FileWalker;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
-    /// Directory to scan
-    #[arg(short, long, default_value = ".")]
-    dir: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Directory to scan, may be repeated or comma-separated. A file is
+    /// annotated on its own, whatever its extension or ignore rules
+    #[arg(short, long, default_value = ".", value_delimiter = ',')]
+    dir: Vec<String>,
+
+    /// Annotate exactly the files listed in FILE (`-` for stdin), one per
+    /// line, instead of walking --dir
+    #[arg(long, value_name = "FILE")]
+    files_from: Option<String>,
+
+    /// Abort when more than N files match, 0 disables the limit
+    #[arg(long, value_name = "N", default_value_t = 1000)]
+    max_files: usize,
+
+    /// Leave out this file, given relative to --dir (repeatable)
+    #[arg(long, value_name = "PATH")]
+    exclude_file: Vec<String>,
+
+    /// Show paths relative to DIR, or to the Git top-level when no DIR is
+    /// given, instead of relative to --dir
+    #[arg(long, value_name = "DIR", conflicts_with = "paths")]
+    repo_root: Option<Option<PathBuf>>,
+
+    /// What file paths in the output are relative to
+    #[arg(long, value_enum, value_name = "BASE", default_value_t = PathDisplay::Scan)]
+    paths: PathDisplay,
+
+    /// Config file path [default: nearest .amc.toml up to the Git root]
+    #[arg(short, long, env = "AMC_CONFIG")]
+    config: Option<String>,
+
+    /// Output file path, `-` writes to stdout
+    #[arg(short, long, default_value = "-")]
+    output: String,
+
+    /// Write one file per top-level directory of --dir into DIR instead,
+    /// named after the directory (files directly in --dir go to _root)
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["output", "clipboard"])]
+    output_dir: Option<PathBuf>,
+
+    /// Also write a JSON summary of the run (files, sizes, tokens, skipped
+    /// files and why) to FILE
+    #[arg(long, value_name = "FILE", conflicts_with = "output_dir")]
+    summary: Option<PathBuf>,
+
+    /// Compress the output file, appending .gz or .zst to its name if missing
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    compress: Option<compress::Compression>,
+
+    /// Copy the output to the system clipboard instead of stdout
+    #[arg(long)]
+    clipboard: bool,
+
+    /// Shell command to run after each output file is written, e.g.
+    /// 'llm < {output}'. It runs through `sh -c` (`cmd /C` on Windows) with
+    /// the full privileges of amc, so only pass commands you trust. {output}
+    /// becomes the already quoted path (don't add quotes around it); the
+    /// path is also in $AMC_OUTPUT. A non-zero exit status fails the run.
+    #[arg(long, value_name = "COMMAND")]
+    post_hook: Option<String>,
+
+    /// Run the [filters] commands of the config. They are ignored otherwise,
+    /// as a config discovered in a cloned repository could run anything.
+    #[arg(long)]
+    allow_filters: bool,
+
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = Format::Plain)]
+    format: Format,
+
+    /// Lay the output out from a template instead of a built-in format:
+    /// {prompt} and {delimiter}, then a {files}...{/files} block written per
+    /// file with {path}, {hash}, {time}, {author}, {content} and more
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["format", "output_format_version"])]
+    template_file: Option<PathBuf>,
+
+    /// Start plain output with a `# amc-format: N` line naming the layout
+    /// version, for tools that parse it back
+    #[arg(long)]
+    output_format_version: bool,
+
+    /// Allow directories outside a Git repository and leave out commit
+    /// metadata; headers show the file's modification time instead
+    #[arg(long, conflicts_with_all = ["rev", "since", "repo_info"])]
+    no_git: bool,
+
+    /// Revision (branch, tag, commit) to look up file history from [default: HEAD]
+    #[arg(long, value_name = "REV")]
+    rev: Option<String>,
+
+    /// Only include files last changed after this date (2024-05-01,
+    /// "2 weeks ago") or in commits descending from this revision
+    #[arg(long, value_name = "DATE|REV")]
+    since: Option<String>,
+
+    /// With --since, also include files without Git history
+    #[arg(long, requires = "since")]
+    since_include_untracked: bool,
+
+    /// File order: path, mtime, git-time (newest first) or size (largest first)
+    /// [default: path, or git-time with --max-tokens]
+    #[arg(long, value_enum)]
+    sort: Option<SortOrder>,
+
+    /// How to render commit timestamps
+    #[arg(long, value_enum, default_value_t = DateFormat::Rfc3339)]
+    date_format: DateFormat,
+
+    /// Leave out files that are not tracked by Git
+    #[arg(long)]
+    skip_untracked: bool,
+
+    /// Keep files marked linguist-generated or export-ignore in .gitattributes
+    #[arg(long)]
+    no_gitattributes: bool,
+
+    /// Include files ignored by .gitignore, .git/info/exclude and the global
+    /// excludes file
+    #[arg(long)]
+    no_gitignore: bool,
+
+    /// Include files ignored only by the global Git excludes file
+    #[arg(long)]
+    no_global_gitignore: bool,
+
+    /// Keep files that look minified (very long lines, hardly any whitespace)
+    #[arg(long)]
+    include_minified: bool,
+
+    /// Leave out test files: anything under tests/, test/ or __tests__/ and
+    /// names like *_test.rs, *.test.ts or test_*.py
+    #[arg(long, conflicts_with = "only_tests")]
+    exclude_tests: bool,
+
+    /// Keep only test files and Rust files with a #[cfg(test)] module
+    #[arg(long)]
+    only_tests: bool,
+
+    /// Keep dependency lockfiles (Cargo.lock, package-lock.json, yarn.lock,
+    /// poetry.lock, ...), which are left out by default
+    #[arg(long)]
+    include_lockfiles: bool,
+
+    /// Follow symlinks while walking (by default they are skipped)
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// Include hidden files and directories like .env or .github/ (skipped
+    /// by default so dotfiles with secrets stay out)
+    #[arg(long)]
+    include_hidden: bool,
+
+    /// Include the files of Git submodules, annotated with each submodule's
+    /// own commits (skipped by default)
+    #[arg(long)]
+    include_submodules: bool,
+
+    /// Descend at most N directory levels below --dir (1: only its own files)
+    #[arg(long, value_name = "N")]
+    max_depth: Option<usize>,
+
+    /// Decode files that aren't valid UTF-8 with this encoding (e.g. latin1,
+    /// utf-16le, shift_jis) instead of guessing
+    #[arg(long, value_name = "LABEL", value_parser = encoding::parse_encoding)]
+    encoding: Option<&'static encoding_rs::Encoding>,
+
+    /// Skip files larger than this size (accepts suffixes like 200k, 1M)
+    #[arg(long, value_name = "SIZE", value_parser = size::parse_size)]
+    max_file_size: Option<u64>,
 
-    /// Config file path
-    #[arg(short, long, default_value = ".amc.toml")]
-    config: String,
+    /// Print a directory tree of the included files before their contents
+    #[arg(long)]
+    tree: bool,
 
-    /// Enable verbose logging
-    #[arg(short, long)]
-    verbose: bool,
+    /// Start with the branch, HEAD, commit count and working tree status
+    #[arg(long)]
+    repo_info: bool,
+
+    /// Add each file's mode bits (e.g. 0755 (executable)) to its header
+    #[arg(long)]
+    show_perms: bool,
+
+    /// List each file's imports (Rust use, Python import, JS/TS import and
+    /// require) in its header
+    #[arg(long)]
+    annotate_imports: bool,
+
+    /// Start each file header with its position, e.g. [File 3/27], so files
+    /// can be referred to by number
+    #[arg(long)]
+    number_files: bool,
+
+    /// Write each directory's files together under a "### directory:" heading
+    #[arg(long)]
+    group_by_dir: bool,
+
+    /// Prefix every line of file content with its line number
+    #[arg(long)]
+    line_numbers: bool,
+
+    /// Remove comments from Rust, C-like, Python and shell files
+    #[arg(long)]
+    strip_comments: bool,
+
+    /// Collapse runs of blank lines (to the config's max_blank_lines, or 1)
+    #[arg(long)]
+    squeeze_blank: bool,
+
+    /// Keep only the first N lines of longer files (combines with --tail)
+    #[arg(long, value_name = "N")]
+    head: Option<usize>,
+
+    /// Keep only the last N lines of longer files (combines with --head)
+    #[arg(long, value_name = "N")]
+    tail: Option<usize>,
+
+    /// Read the LLM prompt from this file instead of the config
+    #[arg(long, value_name = "FILE")]
+    prompt_file: Option<String>,
+
+    /// Read the closing instructions written after the last file from FILE
+    #[arg(long, value_name = "FILE")]
+    footer_file: Option<String>,
+
+    /// Don't write the LLM prompt at the top of the output (nor the footer)
+    #[arg(long)]
+    no_prompt: bool,
+
+    /// Copy FILE verbatim before the prompt, repeatable (plain/markdown only)
+    #[arg(long, value_name = "FILE")]
+    prepend: Vec<PathBuf>,
+
+    /// Copy FILE verbatim after the last file, repeatable (plain/markdown only)
+    #[arg(long, value_name = "FILE")]
+    append: Vec<PathBuf>,
+
+    /// Leave out the manifest of written files after the last file
+    #[arg(long)]
+    no_manifest: bool,
+
+    /// List the files that would be included, with sizes, and exit
+    #[arg(long)]
+    list: bool,
+
+    /// Number of worker threads for reading files [default: number of CPUs]
+    #[arg(long, value_name = "N")]
+    concurrency: Option<usize>,
+
+    /// Write identical files once, later copies only reference the first
+    #[arg(long)]
+    dedupe: bool,
+
+    /// Print estimated token counts to stderr
+    #[arg(long)]
+    tokens: bool,
+
+    /// Stop including files once the estimated token count would exceed N
+    #[arg(long, value_name = "N")]
+    max_tokens: Option<usize>,
+
+    /// Split the output into numbered parts of at most N estimated tokens
+    /// each, written next to --output as NAME.part01.EXT, NAME.part02.EXT, ...
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["max_tokens", "output_dir", "clipboard", "summary"]
+    )]
+    split_tokens: Option<usize>,
+
+    /// Replace likely secrets (API keys, tokens, passwords, private keys)
+    /// with ***REDACTED***
+    #[arg(long)]
+    redact: bool,
+
+    /// Abort when a file can't be read instead of skipping it with a warning
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// Don't read or update the cache of per-file Git lookups
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Log more details to stderr, -vv for debug output
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Only report errors
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+}
+
+impl Cli {
+    /// The library options corresponding to these flags
+    fn options(&self) -> Options {
+        Options {
+            dirs: self.dir.clone(),
+            file_list: None,
+            max_files: self.max_files,
+            exclude_files: self.exclude_file.clone(),
+            config_file: None,
+            repo_root: None,
+            format: self.format,
+            rev: self.rev.clone(),
+            since: self.since.clone(),
+            since_include_untracked: self.since_include_untracked,
+            sort: self.sort,
+            date_format: self.date_format,
+            skip_untracked: self.skip_untracked,
+            git_attributes: !self.no_gitattributes,
+            gitignore: !self.no_gitignore,
+            global_gitignore: !self.no_gitignore && !self.no_global_gitignore,
+            skip_minified: !self.include_minified,
+            exclude_tests: self.exclude_tests,
+            only_tests: self.only_tests,
+            follow_symlinks: self.follow_symlinks,
+            include_hidden: self.include_hidden,
+            exclude_lockfiles: !self.include_lockfiles,
+            include_submodules: self.include_submodules,
+            max_depth: self.max_depth,
+            max_file_size: self.max_file_size,
+            tree: self.tree,
+            line_numbers: self.line_numbers,
+            strip_comments: self.strip_comments,
+            max_blank_lines: self.squeeze_blank.then_some(1),
+            head: self.head,
+            tail: self.tail,
+            no_prompt: self.no_prompt,
+            prepend: Vec::new(),
+            append: Vec::new(),
+            manifest: !self.no_manifest,
+            concurrency: self.concurrency,
+            dedupe: self.dedupe,
+            max_tokens: self.max_tokens,
+            redact: self.redact,
+            progress: !self.quiet,
+            fail_fast: self.fail_fast,
+            part: None,
+            show_perms: self.show_perms,
+            group_by_dir: self.group_by_dir,
+            cache: !self.no_cache,
+            encoding: self.encoding,
+            no_git: self.no_git,
+            annotate_imports: self.annotate_imports,
+            allow_filters: self.allow_filters,
+            number_files: self.number_files,
+            format_version: self.output_format_version,
+            template: None,
+            repo_info: None,
+        }
+    }
+}
+
+/// Base directory for displayed paths, selected with `--paths`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum PathDisplay {
+    /// The scanned --dir
+    Scan,
+    /// The current working directory
+    Cwd,
+    /// The Git repository root
+    Repo,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Write a commented config file to the --config path
+    Init {
+        /// Overwrite an existing config file
+        #[arg(long)]
+        force: bool,
+    },
+    /// Summarize matching files per extension without emitting content
+    Stats {
+        /// How many of the largest files to list
+        #[arg(long, value_name = "N", default_value_t = 10)]
+        top: usize,
+    },
+    /// Compare two generated outputs and list added, removed and modified files
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+        /// Also print a unified diff for every changed file
+        #[arg(long)]
+        full: bool,
+    },
+    /// Delete the cache of per-file Git lookups kept in the Git directory
+    ClearCache,
+    /// Print a shell completion script to stdout
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
 }
 
 fn main() -> anyhow::Result<()> {
+    #[cfg(target_os = "linux")]
+    if std::env::var_os(CLIPBOARD_DAEMON_ENV).is_some() {
+        return serve_clipboard();
+    }
+    let started = Instant::now();
     let cli = Cli::parse();
 
-    // Check if the directory is a Git repository
-    if !is_git_repository(&cli.dir) {
+    if let Some(Command::Init { force }) = cli.command {
+        let path = cli.config.as_deref().unwrap_or(CONFIG_FILE_NAME);
+        return handle_init(path, force);
+    }
+
+    if let Some(Command::Completions { shell }) = cli.command {
+        let mut command = Cli::command();
+        clap_complete::generate(shell, &mut command, "amc", &mut io::stdout());
+        return Ok(());
+    }
+
+    if let Some(Command::Diff { old, new, full }) = &cli.command {
+        setup_logging(cli.verbose, cli.quiet);
+        let (_, config) = load_config(&cli)?;
+        return handle_diff(&config, old, new, *full);
+    }
+
+    // Check if every directory is in a Git repository
+    if let Some(dir) = cli
+        .dir
+        .iter()
+        .find(|dir| !cli.no_git && !is_git_repository(dir))
+    {
         return Err(anyhow::anyhow!(
             "The specified directory '{}' is not a Git repository or within one. \
-            This tool only works with Git-managed directories.",
-            cli.dir
+            Pass --no-git to annotate it without commit metadata.",
+            dir
         ));
     }
 
-    setup_logging(cli.verbose);
+    setup_logging(cli.verbose, cli.quiet);
+
+    if let Some(Command::ClearCache) = cli.command {
+        let path = GitHistory::discover(&cli.dir[0])?.cache_path();
+        if cache::clear(&path)? {
+            println!("Removed {}", path.display());
+        } else {
+            println!("No cache at {}", path.display());
+        }
+        return Ok(());
+    }
+
+    let (config_path, mut config) = load_config(&cli)?;
 
-    // Load config from the specified file
-    let config = Config::load(&cli.config)?;
-    info!("Loaded configuration from: {}", cli.config);
+    if let Some(prompt_file) = &cli.prompt_file {
+        config.llm_prompt = fs::read_to_string(prompt_file).with_context(|| {
+            format!("Failed to read prompt file: {}", prompt_file)
+        })?;
+    }
+    if let Some(footer_file) = &cli.footer_file {
+        config.footer_prompt =
+            fs::read_to_string(footer_file).with_context(|| {
+                format!("Failed to read footer file: {}", footer_file)
+            })?;
+    }
 
-    let walker = FileWalker::new(config.extensions);
-    let files = walker.walk(&cli.dir)?;
+    if !config.filters.is_empty() && !cli.allow_filters {
+        warn!("Ignoring the [filters] of the config; pass --allow-filters to run them");
+    }
 
-    println!("{}", config.llm_prompt);
+    let mut options = cli.options();
+    options.config_file = config_path;
+    if cli.repo_info {
+        options.repo_info = Some(RepoInfo::collect(&cli.dir[0])?);
+    }
+    options.repo_root = match (&cli.repo_root, cli.paths) {
+        (Some(Some(root)), _) => Some(root.clone()),
+        (Some(None), _) | (None, PathDisplay::Repo) => {
+            Some(repository_root(&cli.dir[0]).with_context(|| {
+                format!("{} is not in a Git work tree", cli.dir[0])
+            })?)
+        }
+        (None, PathDisplay::Cwd) => Some(std::env::current_dir()?),
+        (None, PathDisplay::Scan) => None,
+    };
+    if let Some(source) = &cli.files_from {
+        let list = if source == "-" {
+            io::read_to_string(io::stdin())?
+        } else {
+            fs::read_to_string(source)
+                .with_context(|| format!("Failed to read file list: {}", source))?
+        };
+        options.file_list = Some(list);
+    }
+    if let Some(path) = &cli.template_file {
+        options.template = Some(fs::read_to_string(path).with_context(|| {
+            format!("Failed to read template file: {}", path.display())
+        })?);
+    }
+    options.prepend = read_documents(&cli.prepend)?;
+    options.append = read_documents(&cli.append)?;
+    if config.max_blank_lines.is_some() {
+        options.max_blank_lines = config.max_blank_lines;
+    }
 
-    for file in files {
-        info!("Processing file: {}", file.absolute_path.display());
-        let content = fs::read_to_string(&file.absolute_path)?;
+    let files = all_my_circuits::collect_files(&config, &options)?;
+
+    if cli.list {
+        return list_files(&files);
+    }
+
+    if let Some(Command::Stats { top }) = cli.command {
+        print!("{}", stats::Stats::collect(&files, top)?);
+        return Ok(());
+    }
+
+    let gathered = all_my_circuits::gather(&config, &options, files)?;
+    if let Some(limit) = cli.max_file_size.filter(|_| gathered.oversized > 0) {
+        warn!(
+            "Skipped {} file(s) larger than {}",
+            gathered.oversized,
+            size::format_size(limit)
+        );
+    }
+    if gathered.unreadable > 0 {
+        warn!("Skipped {} unreadable file(s)", gathered.unreadable);
+    }
+
+    if cli.redact {
+        let redacted: Vec<&GatheredFile> = gathered
+            .files
+            .iter()
+            .filter(|file| file.redactions > 0)
+            .collect();
+        if !redacted.is_empty() {
+            let total: usize = redacted.iter().map(|file| file.redactions).sum();
+            warn!(
+                "Redacted {} likely secret(s) in {} file(s)",
+                total,
+                redacted.len()
+            );
+        }
+    }
+
+    let mut totals = RunTotals::default();
+    if let Some(output_dir) = &cli.output_dir {
+        write_split(
+            &cli,
+            &config,
+            &options,
+            gathered.files,
+            output_dir,
+            &mut totals,
+        )?;
+        totals.report(&cli, started);
+        return Ok(());
+    }
+    if let Some(budget) = cli.split_tokens {
+        write_parts(&cli, &config, &options, gathered.files, budget, &mut totals)?;
+        totals.report(&cli, started);
+        return Ok(());
+    }
+
+    let render = |out: &mut dyn Write| {
+        all_my_circuits::render(out, &config, &options, &gathered.files)
+    };
+
+    let output_path = match cli.compress {
+        Some(compression) if cli.output != "-" => {
+            compression.output_path(&cli.output)
+        }
+        _ => cli.output.clone(),
+    };
+    if cli.compress.is_some() && output_path == "-" && io::stdout().is_terminal() {
+        anyhow::bail!(
+            "Refusing to write compressed output to a terminal; use --output"
+        );
+    }
+
+    // The clipboard always receives plain text; only the file is compressed.
+    // It needs the whole output at once, so this is the only mode that
+    // buffers everything instead of streaming.
+    let summary = if cli.clipboard {
+        let mut buffer = Vec::new();
+        let summary = render(&mut buffer)?;
+        if output_path != "-" {
+            compress::with_encoder(
+                open_output(&output_path)?,
+                cli.compress,
+                |out| Ok(out.write_all(&buffer)?),
+            )?;
+        }
+        copy_to_clipboard(buffer)?;
+        summary
+    } else {
+        compress::with_encoder(open_output(&output_path)?, cli.compress, |out| {
+            render(out)
+        })?
+    };
+    report_summary(&cli, &summary);
+    totals.add(&summary);
+    if let Some(path) = &cli.summary {
+        write_run_summary(path, &cli, &options, &gathered, &summary)?;
+    }
+    run_post_hook(&cli, &output_path)?;
+    totals.report(&cli, started);
+
+    Ok(())
+}
+
+/// Find and load the config, returning the path it came from if any
+fn load_config(cli: &Cli) -> anyhow::Result<(Option<PathBuf>, Config)> {
+    // An explicit --config wins, otherwise look upwards from the scan dir
+    let config_path = match &cli.config {
+        Some(path) => Some(PathBuf::from(path)),
+        None => {
+            Config::discover(&cli.dir[0], repository_root(&cli.dir[0]).as_deref())
+        }
+    };
+    let config = match &config_path {
+        Some(path) => {
+            let config = Config::load(path)?;
+            info!("Loaded configuration from: {}", path.display());
+            config
+        }
+        None => {
+            info!("No {} found, using default configuration", CONFIG_FILE_NAME);
+            Config::default()
+        }
+    };
+    Ok((config_path, config))
+}
+
+/// Compare two generated outputs file by file
+fn handle_diff(
+    config: &Config,
+    old: &Path,
+    new: &Path,
+    full: bool,
+) -> anyhow::Result<()> {
+    let parse = |path: &Path| {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        parse::parse_output(&text, &config.delimiters(), &config.footer_prompt)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    };
+    let (old_files, new_files) = (parse(old)?, parse(new)?);
+
+    let changes = diff::diff_outputs(&old_files, &new_files);
+    print!("{}", diff::ChangeSummary(&changes));
+
+    if full {
+        let content = |files: &[parse::ParsedFile], path: &Path| {
+            files
+                .iter()
+                .find(|file| file.path == path)
+                .map(|file| file.content.clone())
+                .unwrap_or_default()
+        };
+        for change in &changes {
+            println!();
+            print!(
+                "{}",
+                diff::unified_diff(
+                    &change.path,
+                    &content(&old_files, &change.path),
+                    &content(&new_files, &change.path)
+                )
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Describe the run for `--summary`: what was written and what was left out
+fn write_run_summary(
+    path: &Path,
+    cli: &Cli,
+    options: &Options,
+    gathered: &Gathered,
+    summary: &RenderSummary,
+) -> anyhow::Result<()> {
+    let sizes: HashMap<&Path, usize> = gathered
+        .files
+        .iter()
+        .map(|file| (file.entry.relative_path.as_path(), file.content.len()))
+        .collect();
+    let included: Vec<_> = summary
+        .file_tokens
+        .iter()
+        .map(|(path, tokens)| {
+            json!({ "path": path, "bytes": sizes[path], "tokens": tokens })
+        })
+        .collect();
+    let skipped = gathered
+        .skipped
+        .iter()
+        .map(|file| json!({ "path": file.path, "reason": file.reason }));
+    let omitted = summary
+        .omitted
+        .iter()
+        .map(|path| json!({ "path": path, "reason": "over --max-tokens budget" }));
+
+    let run = json!({
+        "output": cli.output,
+        "format": cli.format.to_possible_value().map(|v| v.get_name().to_string()),
+        "config": options.config_file,
+        "files": included.len(),
+        "total_bytes": summary
+            .file_tokens
+            .iter()
+            .map(|(path, _)| sizes[path])
+            .sum::<usize>(),
+        "estimated_tokens": summary.total_tokens,
+        "included": included,
+        "excluded": skipped.chain(omitted).collect::<Vec<_>>(),
+    });
+
+    let mut text = serde_json::to_string_pretty(&run)?;
+    text.push('\n');
+    fs::write(path, text)
+        .with_context(|| format!("Failed to write summary: {}", path.display()))
+}
+
+/// Write one output file per top-level directory into `output_dir`
+fn write_split(
+    cli: &Cli,
+    config: &Config,
+    options: &Options,
+    files: Vec<GatheredFile>,
+    output_dir: &Path,
+    totals: &mut RunTotals,
+) -> anyhow::Result<()> {
+    fs::create_dir_all(output_dir).with_context(|| {
+        format!(
+            "Failed to create output directory: {}",
+            output_dir.display()
+        )
+    })?;
 
-        // Get git information
-        let (commit_hash, commit_time) = get_git_info(&file.absolute_path)
-            .unwrap_or(("unknown".to_string(), "unknown".to_string()));
+    for (group, files) in all_my_circuits::split_by_top_dir(files) {
+        let name = format!("{}.{}", group, cli.format.extension());
+        let mut path = output_dir.join(name).to_string_lossy().into_owned();
+        if let Some(compression) = cli.compress {
+            path = compression.output_path(&path);
+        }
 
-        info!("Git info - commit: {}, time: {}", commit_hash, commit_time);
+        let summary =
+            compress::with_encoder(open_output(&path)?, cli.compress, |out| {
+                all_my_circuits::render(out, config, options, &files)
+            })?;
+        info!("Wrote {} file(s) to {}", files.len(), path);
+        report_summary(cli, &summary);
+        totals.add(&summary);
+        run_post_hook(cli, &path)?;
+    }
+    Ok(())
+}
+
+/// Write the files as numbered parts of at most `budget` tokens next to
+/// `--output`
+fn write_parts(
+    cli: &Cli,
+    config: &Config,
+    options: &Options,
+    files: Vec<GatheredFile>,
+    budget: usize,
+    totals: &mut RunTotals,
+) -> anyhow::Result<()> {
+    if cli.output == "-" {
+        anyhow::bail!("--split-tokens writes several files and needs --output");
+    }
+
+    let parts = all_my_circuits::split_by_tokens(config, options, files, budget)?;
+    let count = parts.len();
+    for (index, files) in parts.iter().enumerate() {
+        let mut path = part_path(&cli.output, index + 1);
+        if let Some(compression) = cli.compress {
+            path = compression.output_path(&path);
+        }
+        let options = Options {
+            part: Some((index + 1, count)),
+            ..options.clone()
+        };
+
+        let summary =
+            compress::with_encoder(open_output(&path)?, cli.compress, |out| {
+                all_my_circuits::render(out, config, &options, files)
+            })?;
+        info!("Wrote {} file(s) to {}", files.len(), path);
+        report_summary(cli, &summary);
+        totals.add(&summary);
+        run_post_hook(cli, &path)?;
+    }
+    Ok(())
+}
+
+/// `out.txt` becomes `out.part01.txt`, `out` becomes `out.part01`
+fn part_path(output: &str, number: usize) -> String {
+    let path = Path::new(output);
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(format!(".part{:02}", number));
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+/// Run `--post-hook`, if given, for the file just written to `output`
+fn run_post_hook(cli: &Cli, output: &str) -> anyhow::Result<()> {
+    match &cli.post_hook {
+        Some(command) => hook::run(command, output),
+        None => Ok(()),
+    }
+}
+
+/// Token counts requested with `--tokens` and the partial-output warning
+fn report_summary(cli: &Cli, summary: &RenderSummary) {
+    if cli.tokens {
+        for (path, tokens) in &summary.file_tokens {
+            eprintln!("{:>8}  {}", tokens, path.display());
+        }
+        eprintln!(
+            "{:>8}  total (including prompt, headers and footer)",
+            summary.total_tokens
+        );
+    }
+
+    if !summary.omitted.is_empty() {
+        let paths: Vec<String> = summary
+            .omitted
+            .iter()
+            .map(|path| format!("  {}", path.display()))
+            .collect();
+        warn!(
+            "Token budget reached, output is partial. {} file(s) omitted:\n{}",
+            summary.omitted.len(),
+            paths.join("\n")
+        );
+    }
+}
 
-        // Print file annotation
-        println!("{}", config.delimiter);
-        println!("File: {}", file.relative_path.display());
-        println!("Last commit: {}", commit_hash);
-        println!("Last update: {}", commit_time);
-        println!("{}", config.delimiter);
+/// What every output file of a run added up to
+#[derive(Default)]
+struct RunTotals {
+    files: usize,
+    bytes: usize,
+    tokens: usize,
+}
+
+impl RunTotals {
+    fn add(&mut self, summary: &RenderSummary) {
+        self.files += summary.file_tokens.len();
+        self.bytes += summary.bytes;
+        self.tokens += summary.total_tokens;
+    }
 
-        // Print file content
-        println!("{}\n", content);
+    /// The closing line on stderr, unless `--quiet`
+    fn report(&self, cli: &Cli, started: Instant) {
+        if !cli.quiet {
+            eprintln!(
+                "Wrote {} file(s), {}, ~{} tokens in {:.2}s",
+                self.files,
+                size::format_size(self.bytes as u64),
+                self.tokens,
+                started.elapsed().as_secs_f64()
+            );
+        }
     }
+}
+
+/// Read `--prepend`/`--append` documents in the order they were given
+fn read_documents(paths: &[PathBuf]) -> anyhow::Result<Vec<String>> {
+    paths
+        .iter()
+        .map(|path| {
+            fs::read_to_string(path).with_context(|| {
+                format!("Failed to read document: {}", path.display())
+            })
+        })
+        .collect()
+}
 
+/// Print each walked file with its size, without reading any content
+fn list_files(files: &[FileEntry]) -> anyhow::Result<()> {
+    let mut total = 0;
+    for file in files {
+        let len = fs::metadata(&file.absolute_path)?.len();
+        total += len;
+        println!(
+            "{:>10}  {}",
+            size::format_size(len),
+            file.relative_path.display()
+        );
+    }
+    println!(
+        "{} file(s), {} total",
+        files.len(),
+        size::format_size(total)
+    );
     Ok(())
 }
 
-fn setup_logging(verbose: bool) {
-    if verbose {
-        SimpleLogger::new()
-            .with_level(LevelFilter::Info)
-            .without_timestamps()
-            .init()
-            .unwrap();
+fn handle_init(path: &str, force: bool) -> anyhow::Result<()> {
+    if ConfigFormat::from_path(Path::new(path)) != ConfigFormat::Toml {
+        anyhow::bail!(
+            "amc init only writes TOML; pick a path ending in .toml or convert \
+             the template by hand"
+        );
+    }
+    if Path::new(path).exists() && !force {
+        return Err(anyhow::anyhow!(
+            "Config file '{}' already exists, use --force to overwrite it",
+            path
+        ));
     }
+    Config::write_default(path)?;
+    eprintln!("Wrote default configuration to {}", path);
+    Ok(())
+}
+
+/// Set in the environment of the background process that serves the
+/// clipboard on Linux
+#[cfg(target_os = "linux")]
+const CLIPBOARD_DAEMON_ENV: &str = "AMC_CLIPBOARD_DAEMON";
+
+/// Place the rendered output on the system clipboard.
+///
+/// On Linux (X11 and Wayland) the clipboard is served by the process that set
+/// it and empties when that process exits, so amc hands the text to a copy of
+/// itself running in the background, which keeps serving it until something
+/// else is copied.
+fn copy_to_clipboard(buffer: Vec<u8>) -> anyhow::Result<()> {
+    let text = String::from_utf8(buffer).context("Output is not valid UTF-8")?;
+    let clipboard = arboard::Clipboard::new()
+        .context("No system clipboard available (is a display server running?)");
+    #[cfg(target_os = "linux")]
+    {
+        // Only checked here, so a missing display is reported up front
+        drop(clipboard?);
+        let mut daemon = std::process::Command::new(std::env::current_exe()?)
+            .env(CLIPBOARD_DAEMON_ENV, "1")
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+            .context("Failed to start the clipboard process")?;
+        // Dropping stdin closes it, so the daemon sees the end of the text
+        daemon
+            .stdin
+            .take()
+            .context("Failed to start the clipboard process")?
+            .write_all(text.as_bytes())
+            .context("Failed to copy output to clipboard")?;
+    }
+    #[cfg(not(target_os = "linux"))]
+    clipboard?
+        .set_text(text.as_str())
+        .context("Failed to copy output to clipboard")?;
+    eprintln!(
+        "Copied {} bytes ({} chars) to clipboard",
+        text.len(),
+        text.chars().count()
+    );
+    Ok(())
 }
 
-fn get_git_info(path: &PathBuf) -> anyhow::Result<(String, String)> {
-    let repo = Repository::discover(path)?;
-    let head = repo.head()?;
-    let commit = head.peel_to_commit()?;
+/// Run as the clipboard daemon: put stdin on the clipboard and keep serving it
+/// until another program takes the clipboard over
+#[cfg(target_os = "linux")]
+fn serve_clipboard() -> anyhow::Result<()> {
+    use arboard::SetExtLinux;
 
-    Ok((commit.id().to_string(), commit.time().seconds().to_string()))
+    let text = io::read_to_string(io::stdin())?;
+    arboard::Clipboard::new()?.set().wait().text(text)?;
+    Ok(())
+}
+
+/// Open the output destination, treating `-` as stdout
+fn open_output(path: &str) -> anyhow::Result<Box<dyn Write>> {
+    if path == "-" {
+        // Block-buffered: stdout is otherwise flushed at every line
+        return Ok(Box::new(BufWriter::new(io::stdout().lock())));
+    }
+    let file = fs::File::create(path)
+        .with_context(|| format!("Failed to create output file: {}", path))?;
+    info!("Writing output to: {}", path);
+    Ok(Box::new(BufWriter::new(file)))
 }
 
-fn is_git_repository(path: &str) -> bool {
-    Repository::discover(path).is_ok()
+/// Warnings by default, each `-v` one level more, `--quiet` errors only
+fn setup_logging(verbose: u8, quiet: bool) {
+    let level = match (quiet, verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Warn,
+        (false, 1) => LevelFilter::Info,
+        (false, _) => LevelFilter::Debug,
+    };
+    SimpleLogger::new()
+        .with_level(level)
+        .without_timestamps()
+        .init()
+        .unwrap();
 }
@@ -0,0 +1,90 @@
+//! Heuristic detection of minified files (bundled JS, compressed CSS), which
+//! match ordinary extensions but are useless as context. Only web assets are
+//! checked; a long line in a Markdown table or a Rust test fixture is kept.
+
+use std::path::Path;
+
+/// Extensions of the files a bundler or compressor emits
+const MINIFIABLE_EXTENSIONS: &[&str] = &["js", "mjs", "cjs", "css", "map"];
+
+/// A line this long is not something a person wrote
+const MAX_LINE_LENGTH: usize = 2000;
+
+/// Below this share of whitespace characters the content is considered
+/// minified...
+const MIN_WHITESPACE_RATIO: f64 = 0.03;
+
+/// ...as long as there is enough of it for the ratio to mean anything
+const MIN_RATIO_LENGTH: usize = 1000;
+
+/// Whether `path` is the kind of file that gets minified
+pub fn is_minifiable(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            MINIFIABLE_EXTENSIONS
+                .iter()
+                .any(|minifiable| ext.eq_ignore_ascii_case(minifiable))
+        })
+}
+
+/// Why `content` looks minified, or `None` if it looks hand-written
+pub fn minified_reason(content: &str) -> Option<String> {
+    let longest = content
+        .lines()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0);
+    if longest > MAX_LINE_LENGTH {
+        return Some(format!("a line of {} characters", longest));
+    }
+
+    let total = content.chars().count();
+    if total >= MIN_RATIO_LENGTH {
+        let whitespace = content.chars().filter(|c| c.is_whitespace()).count();
+        let ratio = whitespace as f64 / total as f64;
+        if ratio < MIN_WHITESPACE_RATIO {
+            return Some(format!("{:.1}% whitespace", ratio * 100.0));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_long_line_is_minified() {
+        let bundle = format!("!function(){{{}}}();\n", "var a=1;".repeat(500));
+        assert_eq!(
+            minified_reason(&bundle).as_deref(),
+            Some("a line of 4016 characters")
+        );
+    }
+
+    #[test]
+    fn test_dense_content_is_minified() {
+        // Short lines, but almost no whitespace
+        let css = ".a{color:red;margin:0;padding:0;border:0}\n".repeat(30);
+        assert!(minified_reason(&css).unwrap().ends_with("% whitespace"));
+    }
+
+    #[test]
+    fn test_regular_code_is_not_minified() {
+        let code = "function add(a, b) {\n    return a + b;\n}\n".repeat(100);
+        assert_eq!(minified_reason(&code), None);
+        assert_eq!(minified_reason("x=1"), None);
+        assert_eq!(minified_reason(""), None);
+    }
+
+    #[test]
+    fn test_only_web_assets_are_minifiable() {
+        for path in ["app.js", "lib.mjs", "lib.cjs", "site.CSS", "app.js.map"] {
+            assert!(is_minifiable(Path::new(path)), "{}", path);
+        }
+        for path in ["README.md", "src/fixtures.rs", "data.json", "Makefile"] {
+            assert!(!is_minifiable(Path::new(path)), "{}", path);
+        }
+    }
+}
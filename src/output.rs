@@ -0,0 +1,1583 @@
+use crate::config::Config;
+use crate::git::RepoInfo;
+use crate::size;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Version of the plain layout announced by `--output-format-version`,
+/// bumped whenever the way sections are written changes. Version 2 added the
+/// optional `[File N/M]` line of `--number-files` after the opening delimiter.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// Start of the first line of a versioned plain output, followed by the
+/// version
+pub const FORMAT_VERSION_PREFIX: &str = "# amc-format: ";
+
+/// Output layout selected with `--format`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Delimiter-separated sections with `File:`/`Last commit:` headers
+    #[default]
+    Plain,
+    /// Fenced code blocks with a metadata bullet list above each file
+    Markdown,
+    /// A single JSON document with the prompt, delimiter and every file
+    Json,
+    /// A `<context>` element with `<instructions>` and one `<file>` per file
+    Xml,
+    /// The plain layout wrapped in one code fence for chat tools that mangle
+    /// several blocks
+    SingleFence,
+}
+
+impl Format {
+    /// File extension for output written in this format, without the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Plain => "txt",
+            Format::Markdown => "md",
+            Format::Json => "json",
+            Format::Xml => "xml",
+            Format::SingleFence => "md",
+        }
+    }
+
+    /// `comment_delimiters` and `header_template` only affect the plain
+    /// layout, whose headers are then written as comments in each file's
+    /// language and from the template respectively
+    pub fn formatter(self, config: &Config) -> Box<dyn Formatter> {
+        match self {
+            Format::Plain => Box::new(PlainFormatter {
+                delimiter: config.delimiter.clone(),
+                comment_delimiters: config.comment_delimiters,
+                header_template: config.header_template.clone(),
+            }),
+            Format::Markdown => Box::new(MarkdownFormatter),
+            Format::Json => Box::new(JsonFormatter {
+                delimiter: config.delimiter.clone(),
+                files_written: 0,
+                files_started: false,
+                files_closed: false,
+            }),
+            Format::Xml => Box::new(XmlFormatter),
+            // `render` wraps the plain layout in the fence, which can only be
+            // picked once the whole output has been seen
+            Format::SingleFence => Box::new(PlainFormatter {
+                delimiter: config.delimiter.clone(),
+                comment_delimiters: config.comment_delimiters,
+                header_template: config.header_template.clone(),
+            }),
+        }
+    }
+}
+
+/// A single annotated file ready to be written
+pub struct FileSection<'a> {
+    pub path: &'a Path,
+    pub absolute_path: &'a Path,
+    pub commit_hash: &'a str,
+    /// Abbreviated `commit_hash`, or the same label without history
+    pub short_hash: &'a str,
+    pub commit_time: &'a str,
+    pub author: &'a str,
+    pub message: &'a str,
+    pub content: &'a str,
+    /// How `content` differs from the file on disk, e.g. "comments stripped"
+    pub notes: &'a [&'a str],
+    /// Replaces the formatter's delimiter for this file (`[formats]` config)
+    pub delimiter: Option<&'a str>,
+    /// Mode bits with `--show-perms`, e.g. "0755 (executable)"
+    pub permissions: Option<&'a str>,
+    /// Modules the file imports, with `--annotate-imports`
+    pub imports: Option<&'a [String]>,
+    /// 1-based position among the written files and their count, with
+    /// `--number-files`
+    pub number: Option<(usize, usize)>,
+}
+
+/// `[File 3/27]`, the label `--number-files` starts headers with
+pub fn file_number_label((index, total): (usize, usize)) -> String {
+    format!("[File {}/{}]", index, total)
+}
+
+/// One row of the manifest written after the last file
+pub struct ManifestEntry<'a> {
+    pub path: &'a Path,
+    /// Size of the content as written
+    pub bytes: usize,
+    /// Short hash of the last commit, or "untracked"/"unknown"
+    pub commit: &'a str,
+}
+
+/// Totals and directory structure of the written files, the header
+/// `summary_block = true` puts before them in the layout gitingest popularized
+pub struct Summary<'a> {
+    pub files: usize,
+    /// Size of the content as written
+    pub bytes: u64,
+    /// Estimate for the prompt, footer and file content, leaving out the
+    /// headers, which can't be measured before this block is written
+    pub tokens: usize,
+    /// Rendered by [`crate::tree::render_tree`]
+    pub tree: &'a str,
+}
+
+impl fmt::Display for Summary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Files analyzed: {}", self.files)?;
+        writeln!(f, "Total size: {}", size::format_size(self.bytes))?;
+        writeln!(f, "Estimated tokens: {}", self.tokens)?;
+        writeln!(f)?;
+        writeln!(f, "Directory structure:")?;
+        write!(f, "{}", self.tree)
+    }
+}
+
+/// Writes the prompt and the per-file sections in a particular layout
+pub trait Formatter {
+    fn write_prompt(&mut self, out: &mut dyn Write, prompt: &str)
+        -> io::Result<()>;
+
+    /// Directory overview written between the prompt and the first file
+    fn write_tree(&mut self, out: &mut dyn Write, tree: &str) -> io::Result<()> {
+        writeln!(out, "{}", tree)
+    }
+
+    /// Repository state written right after the prompt
+    fn write_repo_info(
+        &mut self,
+        out: &mut dyn Write,
+        info: &RepoInfo,
+    ) -> io::Result<()> {
+        writeln!(out, "{}", info)
+    }
+
+    /// Summary block written in place of the tree with `summary_block`
+    fn write_summary(
+        &mut self,
+        out: &mut dyn Write,
+        summary: &Summary,
+    ) -> io::Result<()> {
+        writeln!(out, "{}", summary)
+    }
+
+    /// Heading before the files of one directory with `--group-by-dir`
+    fn write_group(
+        &mut self,
+        out: &mut dyn Write,
+        directory: &Path,
+    ) -> io::Result<()> {
+        writeln!(out, "### directory: {}\n", directory.display())
+    }
+
+    fn write_file(
+        &mut self,
+        out: &mut dyn Write,
+        file: &FileSection,
+    ) -> io::Result<()>;
+
+    /// Index of every written file, after the last one
+    fn write_manifest(
+        &mut self,
+        out: &mut dyn Write,
+        entries: &[ManifestEntry],
+    ) -> io::Result<()>;
+
+    /// Closing instructions written after the last file; empty means none
+    fn write_footer(
+        &mut self,
+        out: &mut dyn Write,
+        footer: &str,
+    ) -> io::Result<()> {
+        if footer.is_empty() {
+            return Ok(());
+        }
+        writeln!(out, "{}", footer)
+    }
+
+    /// Called once after the last file has been written
+    fn finish(&mut self, _out: &mut dyn Write) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub struct PlainFormatter {
+    delimiter: String,
+    comment_delimiters: bool,
+    header_template: String,
+}
+
+impl Formatter for PlainFormatter {
+    fn write_prompt(
+        &mut self,
+        out: &mut dyn Write,
+        prompt: &str,
+    ) -> io::Result<()> {
+        if prompt.is_empty() {
+            return Ok(());
+        }
+        writeln!(out, "{}", prompt)
+    }
+
+    fn write_file(
+        &mut self,
+        out: &mut dyn Write,
+        file: &FileSection,
+    ) -> io::Result<()> {
+        // e.g. `// File: src/main.rs`, falling back to bare header lines
+        let comment = file
+            .path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(comment_prefix_for_extension)
+            .filter(|_| self.comment_delimiters)
+            .map(|prefix| format!("{} ", prefix))
+            .unwrap_or_default();
+        let delimiter = file.delimiter.unwrap_or(&self.delimiter);
+
+        let path = file.path.display().to_string();
+        let size = size::format_size(file.content.len() as u64);
+        let header = render_template(
+            &self.header_template,
+            &[
+                ("path", &path),
+                ("hash", file.commit_hash),
+                ("short_hash", file.short_hash),
+                ("time", file.commit_time),
+                ("author", file.author),
+                ("message", file.message),
+                ("size", &size),
+            ],
+        );
+
+        writeln!(out, "{}{}", comment, delimiter)?;
+        if let Some(number) = file.number {
+            writeln!(out, "{}{}", comment, file_number_label(number))?;
+        }
+        for line in header.lines() {
+            writeln!(out, "{}{}", comment, line)?;
+        }
+        if let Some(permissions) = file.permissions {
+            writeln!(out, "{}Permissions: {}", comment, permissions)?;
+        }
+        if let Some(imports) = file.imports {
+            writeln!(out, "{}Imports: {}", comment, imports.join(", "))?;
+        }
+        if !file.notes.is_empty() {
+            writeln!(out, "{}Note: {}", comment, file.notes.join("; "))?;
+        }
+        writeln!(out, "{}{}", comment, delimiter)?;
+        writeln!(out, "{}\n", file.content)
+    }
+
+    fn write_manifest(
+        &mut self,
+        out: &mut dyn Write,
+        entries: &[ManifestEntry],
+    ) -> io::Result<()> {
+        let path_width = entries
+            .iter()
+            .map(|entry| entry.path.to_string_lossy().len())
+            .max()
+            .unwrap_or(0);
+        let bytes_width = entries
+            .iter()
+            .map(|entry| entry.bytes.to_string().len())
+            .max()
+            .unwrap_or(0);
+
+        writeln!(out, "{}", self.delimiter)?;
+        writeln!(out, "Manifest: {} file(s)", entries.len())?;
+        writeln!(out, "{}", self.delimiter)?;
+        for entry in entries {
+            writeln!(
+                out,
+                "{:<path_width$}  {:>bytes_width$} B  {}",
+                entry.path.to_string_lossy(),
+                entry.bytes,
+                entry.commit
+            )?;
+        }
+        writeln!(out)
+    }
+
+    fn write_footer(
+        &mut self,
+        out: &mut dyn Write,
+        footer: &str,
+    ) -> io::Result<()> {
+        if footer.is_empty() {
+            return Ok(());
+        }
+        writeln!(out, "{}", self.delimiter)?;
+        writeln!(out, "{}", footer)
+    }
+}
+
+pub struct MarkdownFormatter;
+
+impl Formatter for MarkdownFormatter {
+    fn write_prompt(
+        &mut self,
+        out: &mut dyn Write,
+        prompt: &str,
+    ) -> io::Result<()> {
+        if prompt.is_empty() {
+            return Ok(());
+        }
+        writeln!(out, "{}\n", prompt)
+    }
+
+    fn write_file(
+        &mut self,
+        out: &mut dyn Write,
+        file: &FileSection,
+    ) -> io::Result<()> {
+        let fence = fence_for(file.content);
+        let language = detect_language(file.path, file.content).unwrap_or("");
+
+        if let Some(number) = file.number {
+            writeln!(out, "{}", file_number_label(number))?;
+        }
+        writeln!(out, "- File: `{}`", file.path.display())?;
+        writeln!(out, "- Last commit: `{}`", file.commit_hash)?;
+        writeln!(out, "- Last update: {}", file.commit_time)?;
+        writeln!(out, "- Author: {}", file.author)?;
+        writeln!(out, "- Message: {}", file.message)?;
+        if let Some(permissions) = file.permissions {
+            writeln!(out, "- Permissions: {}", permissions)?;
+        }
+        if let Some(imports) = file.imports {
+            let imports: Vec<String> = imports
+                .iter()
+                .map(|import| format!("`{}`", import))
+                .collect();
+            writeln!(out, "- Imports: {}", imports.join(", "))?;
+        }
+        if !file.notes.is_empty() {
+            writeln!(out, "- Note: {}", file.notes.join("; "))?;
+        }
+        writeln!(out)?;
+        writeln!(out, "{}{}", fence, language)?;
+        write!(out, "{}", file.content)?;
+        if !file.content.ends_with('\n') {
+            writeln!(out)?;
+        }
+        writeln!(out, "{}\n", fence)
+    }
+
+    fn write_manifest(
+        &mut self,
+        out: &mut dyn Write,
+        entries: &[ManifestEntry],
+    ) -> io::Result<()> {
+        writeln!(out, "## Manifest\n")?;
+        writeln!(out, "| File | Bytes | Commit |")?;
+        writeln!(out, "| --- | ---: | --- |")?;
+        for entry in entries {
+            writeln!(
+                out,
+                "| `{}` | {} | `{}` |",
+                entry.path.display(),
+                entry.bytes,
+                entry.commit
+            )?;
+        }
+        writeln!(out)
+    }
+}
+
+/// Streams `{"llm_prompt", "delimiter", "files": [...]}` one file at a time,
+/// followed by `"manifest"` and `"footer_prompt"` when there are any
+pub struct JsonFormatter {
+    delimiter: String,
+    files_written: usize,
+    files_started: bool,
+    files_closed: bool,
+}
+
+impl JsonFormatter {
+    fn start_files(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        if !self.files_started {
+            self.files_started = true;
+            write!(out, ",\"files\":[")?;
+        }
+        Ok(())
+    }
+
+    /// End the files array, before the manifest, footer or closing brace
+    fn close_files(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        self.start_files(out)?;
+        if !self.files_closed {
+            self.files_closed = true;
+            write!(out, "]")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct JsonFile<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    index: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total: Option<usize>,
+    relative_path: &'a Path,
+    absolute_path: &'a Path,
+    commit_hash: &'a str,
+    commit_time: &'a str,
+    author: &'a str,
+    message: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    permissions: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    imports: Option<&'a [String]>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    notes: &'a [&'a str],
+    content: &'a str,
+}
+
+#[derive(Serialize)]
+struct JsonManifestEntry<'a> {
+    relative_path: &'a Path,
+    bytes: usize,
+    commit: &'a str,
+}
+
+impl Formatter for JsonFormatter {
+    fn write_prompt(
+        &mut self,
+        out: &mut dyn Write,
+        prompt: &str,
+    ) -> io::Result<()> {
+        write!(out, "{{\"llm_prompt\":")?;
+        serde_json::to_writer(&mut *out, prompt)?;
+        write!(out, ",\"delimiter\":")?;
+        serde_json::to_writer(&mut *out, &self.delimiter)?;
+        Ok(())
+    }
+
+    fn write_tree(&mut self, out: &mut dyn Write, tree: &str) -> io::Result<()> {
+        write!(out, ",\"tree\":")?;
+        serde_json::to_writer(&mut *out, tree)?;
+        Ok(())
+    }
+
+    fn write_repo_info(
+        &mut self,
+        out: &mut dyn Write,
+        info: &RepoInfo,
+    ) -> io::Result<()> {
+        write!(out, ",\"repo_info\":")?;
+        serde_json::to_writer(&mut *out, info)?;
+        Ok(())
+    }
+
+    fn write_summary(
+        &mut self,
+        out: &mut dyn Write,
+        summary: &Summary,
+    ) -> io::Result<()> {
+        write!(out, ",\"summary\":")?;
+        let summary = serde_json::json!({
+            "files": summary.files,
+            "bytes": summary.bytes,
+            "estimated_tokens": summary.tokens,
+            "tree": summary.tree,
+        });
+        serde_json::to_writer(&mut *out, &summary)?;
+        Ok(())
+    }
+
+    fn write_group(
+        &mut self,
+        _out: &mut dyn Write,
+        _directory: &Path,
+    ) -> io::Result<()> {
+        // Every file already carries its relative path
+        Ok(())
+    }
+
+    fn write_file(
+        &mut self,
+        out: &mut dyn Write,
+        file: &FileSection,
+    ) -> io::Result<()> {
+        self.start_files(out)?;
+        if self.files_written > 0 {
+            write!(out, ",")?;
+        }
+        serde_json::to_writer(
+            &mut *out,
+            &JsonFile {
+                index: file.number.map(|(index, _)| index),
+                total: file.number.map(|(_, total)| total),
+                relative_path: file.path,
+                absolute_path: file.absolute_path,
+                commit_hash: file.commit_hash,
+                commit_time: file.commit_time,
+                author: file.author,
+                message: file.message,
+                permissions: file.permissions,
+                imports: file.imports,
+                notes: file.notes,
+                content: file.content,
+            },
+        )?;
+        self.files_written += 1;
+        Ok(())
+    }
+
+    fn write_manifest(
+        &mut self,
+        out: &mut dyn Write,
+        entries: &[ManifestEntry],
+    ) -> io::Result<()> {
+        self.close_files(out)?;
+        write!(out, ",\"manifest\":[")?;
+        for (index, entry) in entries.iter().enumerate() {
+            if index > 0 {
+                write!(out, ",")?;
+            }
+            serde_json::to_writer(
+                &mut *out,
+                &JsonManifestEntry {
+                    relative_path: entry.path,
+                    bytes: entry.bytes,
+                    commit: entry.commit,
+                },
+            )?;
+        }
+        write!(out, "]")
+    }
+
+    fn write_footer(
+        &mut self,
+        out: &mut dyn Write,
+        footer: &str,
+    ) -> io::Result<()> {
+        self.close_files(out)?;
+        if footer.is_empty() {
+            return Ok(());
+        }
+        write!(out, ",\"footer_prompt\":")?;
+        serde_json::to_writer(&mut *out, footer)?;
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        self.close_files(out)?;
+        writeln!(out, "}}")
+    }
+}
+
+/// Wraps the run in `<context>` with each file's content in a CDATA section
+pub struct XmlFormatter;
+
+impl Formatter for XmlFormatter {
+    fn write_prompt(
+        &mut self,
+        out: &mut dyn Write,
+        prompt: &str,
+    ) -> io::Result<()> {
+        writeln!(out, "<context>")?;
+        if !prompt.is_empty() {
+            writeln!(out, "<instructions>{}</instructions>", xml_escape(prompt))?;
+        }
+        Ok(())
+    }
+
+    fn write_tree(&mut self, out: &mut dyn Write, tree: &str) -> io::Result<()> {
+        writeln!(out, "<tree>{}</tree>", cdata(tree))
+    }
+
+    fn write_summary(
+        &mut self,
+        out: &mut dyn Write,
+        summary: &Summary,
+    ) -> io::Result<()> {
+        writeln!(
+            out,
+            "<summary files=\"{}\" bytes=\"{}\" estimated_tokens=\"{}\">",
+            summary.files, summary.bytes, summary.tokens
+        )?;
+        writeln!(out, "<tree>{}</tree>", cdata(summary.tree))?;
+        writeln!(out, "</summary>")
+    }
+
+    fn write_repo_info(
+        &mut self,
+        out: &mut dyn Write,
+        info: &RepoInfo,
+    ) -> io::Result<()> {
+        let attribute = |name: &str, value: Option<&str>| {
+            value
+                .map(|value| format!(" {}=\"{}\"", name, xml_escape(value)))
+                .unwrap_or_default()
+        };
+        writeln!(
+            out,
+            "<repository{}{} commits=\"{}\" uncommitted_changes=\"{}\"/>",
+            attribute("branch", info.branch.as_deref()),
+            attribute("head", info.head.as_deref()),
+            info.commits,
+            info.uncommitted_changes
+        )
+    }
+
+    fn write_group(
+        &mut self,
+        _out: &mut dyn Write,
+        _directory: &Path,
+    ) -> io::Result<()> {
+        // Every file already carries its path attribute
+        Ok(())
+    }
+
+    fn write_file(
+        &mut self,
+        out: &mut dyn Write,
+        file: &FileSection,
+    ) -> io::Result<()> {
+        let language = detect_language(file.path, file.content)
+            .map(|language| format!(" language=\"{}\"", language))
+            .unwrap_or_default();
+        let notes = if file.notes.is_empty() {
+            String::new()
+        } else {
+            format!(" notes=\"{}\"", xml_escape(&file.notes.join("; ")))
+        };
+        let permissions = file
+            .permissions
+            .map(|mode| format!(" permissions=\"{}\"", xml_escape(mode)))
+            .unwrap_or_default();
+        let imports = file
+            .imports
+            .map(|imports| {
+                format!(" imports=\"{}\"", xml_escape(&imports.join(", ")))
+            })
+            .unwrap_or_default();
+        let number = file
+            .number
+            .map(|(index, total)| {
+                format!(" index=\"{}\" total=\"{}\"", index, total)
+            })
+            .unwrap_or_default();
+        writeln!(
+            out,
+            "<file{} path=\"{}\"{} commit=\"{}\" updated=\"{}\" author=\"{}\" \
+             message=\"{}\"{}{}{}>{}</file>",
+            number,
+            xml_escape(&file.path.to_string_lossy()),
+            language,
+            xml_escape(file.commit_hash),
+            xml_escape(file.commit_time),
+            xml_escape(file.author),
+            xml_escape(file.message),
+            permissions,
+            imports,
+            notes,
+            cdata(file.content)
+        )
+    }
+
+    fn write_manifest(
+        &mut self,
+        out: &mut dyn Write,
+        entries: &[ManifestEntry],
+    ) -> io::Result<()> {
+        writeln!(out, "<manifest>")?;
+        for entry in entries {
+            writeln!(
+                out,
+                "<entry path=\"{}\" bytes=\"{}\" commit=\"{}\"/>",
+                xml_escape(&entry.path.to_string_lossy()),
+                entry.bytes,
+                xml_escape(entry.commit)
+            )?;
+        }
+        writeln!(out, "</manifest>")
+    }
+
+    fn write_footer(
+        &mut self,
+        out: &mut dyn Write,
+        footer: &str,
+    ) -> io::Result<()> {
+        if footer.is_empty() {
+            return Ok(());
+        }
+        writeln!(
+            out,
+            "<closing_instructions>{}</closing_instructions>",
+            xml_escape(footer)
+        )
+    }
+
+    fn finish(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "</context>")
+    }
+}
+
+/// Opens the block of a template written once per file
+pub const FILES_START: &str = "{files}";
+
+/// Closes the per-file block opened by [`FILES_START`]
+pub const FILES_END: &str = "{/files}";
+
+/// The plain layout as a `--template-file`, to start custom ones from. It
+/// has no trailing newline, as that would be written after the footer.
+pub const DEFAULT_TEMPLATE: &str = include_str!("../templates/default.txt");
+
+/// Lays the output out from a user template using [`render_template`]
+/// placeholders. Text before `{files}` is written with `{prompt}` and
+/// `{delimiter}`, the block up to `{/files}` once per file with `{path}`,
+/// `{hash}`, `{short_hash}`, `{time}`, `{author}`, `{message}`, `{size}`,
+/// `{number}`, `{permissions}`, `{imports}`, `{notes}` and `{content}`, and
+/// the rest with `{manifest}`, `{footer}` and `{delimiter}`. `{if name}...{/if}`
+/// writes its text only when `name` is non-empty.
+pub struct TemplateFormatter {
+    delimiter: String,
+    head: Vec<Segment>,
+    file: Vec<Segment>,
+    tail: Vec<Segment>,
+    /// Writes `{manifest}` the way the plain layout does
+    plain: PlainFormatter,
+    manifest: String,
+}
+
+/// Part of a template between `{if}` blocks
+enum Segment {
+    Text(String),
+    /// Text written only when the named value is non-empty
+    If(String, String),
+}
+
+impl TemplateFormatter {
+    pub fn new(template: &str, config: &Config) -> anyhow::Result<Self> {
+        let Some((head, rest)) = template.split_once(FILES_START) else {
+            anyhow::bail!("Template has no {} block", FILES_START);
+        };
+        let Some((file, tail)) = rest.split_once(FILES_END) else {
+            anyhow::bail!("Template block {} is never closed", FILES_START);
+        };
+        if file.contains(FILES_START) || tail.contains(FILES_START) {
+            anyhow::bail!("Template has more than one {} block", FILES_START);
+        }
+        Ok(Self {
+            delimiter: config.delimiter.clone(),
+            head: segments(head)?,
+            file: segments(file)?,
+            tail: segments(tail)?,
+            plain: PlainFormatter {
+                delimiter: config.delimiter.clone(),
+                comment_delimiters: config.comment_delimiters,
+                header_template: config.header_template.clone(),
+            },
+            manifest: String::new(),
+        })
+    }
+}
+
+/// Split `text` at its `{if name}...{/if}` blocks, which don't nest
+fn segments(mut text: &str) -> anyhow::Result<Vec<Segment>> {
+    let mut segments = Vec::new();
+    while let Some(start) = text.find("{if ") {
+        segments.push(Segment::Text(text[..start].to_string()));
+        let rest = &text[start + "{if ".len()..];
+        let Some((name, rest)) = rest.split_once('}') else {
+            anyhow::bail!("Template has an unterminated {{if");
+        };
+        let Some((body, rest)) = rest.split_once("{/if}") else {
+            anyhow::bail!("Template block {{if {}}} is never closed", name);
+        };
+        if body.contains("{if ") {
+            anyhow::bail!("Template {{if}} blocks can't be nested");
+        }
+        segments.push(Segment::If(name.trim().to_string(), body.to_string()));
+        text = rest;
+    }
+    segments.push(Segment::Text(text.to_string()));
+    Ok(segments)
+}
+
+/// Render `segments`, dropping `{if}` blocks whose value is empty or unknown
+fn render_segments(segments: &[Segment], values: &[(&str, &str)]) -> String {
+    let mut template = String::new();
+    for segment in segments {
+        match segment {
+            Segment::Text(text) => template.push_str(text),
+            Segment::If(name, body) => {
+                let set = values
+                    .iter()
+                    .any(|(key, value)| key == name && !value.is_empty());
+                if set {
+                    template.push_str(body);
+                }
+            }
+        }
+    }
+    render_template(&template, values)
+}
+
+impl Formatter for TemplateFormatter {
+    fn write_prompt(
+        &mut self,
+        out: &mut dyn Write,
+        prompt: &str,
+    ) -> io::Result<()> {
+        let head = render_segments(
+            &self.head,
+            &[("prompt", prompt), ("delimiter", &self.delimiter)],
+        );
+        write!(out, "{}", head)
+    }
+
+    fn write_file(
+        &mut self,
+        out: &mut dyn Write,
+        file: &FileSection,
+    ) -> io::Result<()> {
+        let path = file.path.display().to_string();
+        let size = size::format_size(file.content.len() as u64);
+        let number = file.number.map(file_number_label).unwrap_or_default();
+        let imports = file.imports.map(|imports| imports.join(", "));
+        let notes = file.notes.join("; ");
+        let section = render_segments(
+            &self.file,
+            &[
+                ("path", &path),
+                ("hash", file.commit_hash),
+                ("short_hash", file.short_hash),
+                ("time", file.commit_time),
+                ("author", file.author),
+                ("message", file.message),
+                ("size", &size),
+                ("number", &number),
+                ("permissions", file.permissions.unwrap_or_default()),
+                ("imports", imports.as_deref().unwrap_or_default()),
+                ("notes", &notes),
+                ("delimiter", &self.delimiter),
+                ("content", file.content),
+            ],
+        );
+        write!(out, "{}", section)
+    }
+
+    fn write_manifest(
+        &mut self,
+        _out: &mut dyn Write,
+        entries: &[ManifestEntry],
+    ) -> io::Result<()> {
+        let mut manifest = Vec::new();
+        self.plain.write_manifest(&mut manifest, entries)?;
+        self.manifest = String::from_utf8_lossy(&manifest).into_owned();
+        Ok(())
+    }
+
+    fn write_footer(
+        &mut self,
+        out: &mut dyn Write,
+        footer: &str,
+    ) -> io::Result<()> {
+        let tail = render_segments(
+            &self.tail,
+            &[
+                ("manifest", &self.manifest),
+                ("footer", footer),
+                ("delimiter", &self.delimiter),
+            ],
+        );
+        write!(out, "{}", tail)
+    }
+}
+
+/// Escape text for use in XML character data and attribute values
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Wrap text in a CDATA section, splitting it wherever the text itself
+/// contains the `]]>` terminator
+fn cdata(text: &str) -> String {
+    format!("<![CDATA[{}]]>", text.replace("]]>", "]]]]><![CDATA[>"))
+}
+
+/// Replace each `{name}` in `template` with its value from `values`, leaving
+/// unknown placeholders as they are
+pub fn render_template(template: &str, values: &[(&str, &str)]) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let placeholder = &rest[start..];
+        let value = placeholder.find('}').and_then(|end| {
+            let name = &placeholder[1..end];
+            values
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| (*value, end + 1))
+        });
+        match value {
+            Some((value, len)) => {
+                rendered.push_str(value);
+                rest = &placeholder[len..];
+            }
+            None => {
+                rendered.push('{');
+                rest = &placeholder[1..];
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Prefix every line with a right-aligned line number, e.g. `  42 | ...`.
+/// Line endings are kept as they are, `\r\n` included.
+pub fn number_lines(content: &str) -> String {
+    let line_count = content.split_inclusive('\n').count();
+    let width = line_count.to_string().len().max(4);
+
+    let mut numbered =
+        String::with_capacity(content.len() + line_count * (width + 3));
+    for (index, line) in content.split_inclusive('\n').enumerate() {
+        numbered.push_str(&format!("{:>width$} | {}", index + 1, line));
+    }
+    numbered
+}
+
+/// Collapse runs of more than `max` blank (whitespace-only) lines down to
+/// `max`, keeping the remaining lines and their endings untouched
+pub fn squeeze_blank_lines(content: &str, max: usize) -> Cow<'_, str> {
+    let mut squeezed = String::with_capacity(content.len());
+    let mut blank_run = 0;
+    let mut changed = false;
+    for line in content.split_inclusive('\n') {
+        if line.trim().is_empty() {
+            blank_run += 1;
+            if blank_run > max {
+                changed = true;
+                continue;
+            }
+        } else {
+            blank_run = 0;
+        }
+        squeezed.push_str(line);
+    }
+
+    if changed {
+        Cow::Owned(squeezed)
+    } else {
+        Cow::Borrowed(content)
+    }
+}
+
+/// Keep the first `head` and last `tail` lines of `content`, replacing the
+/// ones in between with a `... N lines omitted ...` marker. Content with no
+/// more than `head + tail` lines is returned unchanged.
+pub fn truncate_lines(content: &str, head: usize, tail: usize) -> Cow<'_, str> {
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    if lines.len() <= head + tail {
+        return Cow::Borrowed(content);
+    }
+
+    let omitted = lines.len() - head - tail;
+    let mut truncated: String = lines[..head].concat();
+    truncated.push_str(&format!("... {} lines omitted ...", omitted));
+    if tail > 0 || content.ends_with('\n') {
+        truncated.push('\n');
+    }
+    truncated.push_str(&lines[lines.len() - tail..].concat());
+    Cow::Owned(truncated)
+}
+
+/// Pick a backtick fence longer than any backtick run inside the content
+pub fn fence_for(content: &str) -> String {
+    let longest_run = content
+        .split(|c| c != '`')
+        .map(|run| run.len())
+        .max()
+        .unwrap_or(0);
+    fence_longer_than(longest_run)
+}
+
+/// A backtick fence longer than `run` and at least three backticks long
+pub fn fence_longer_than(run: usize) -> String {
+    "`".repeat(run.max(2) + 1)
+}
+
+/// Sink that discards everything written to it but remembers the longest
+/// backtick run, so a fence can be picked without holding the output
+#[derive(Debug, Default)]
+pub struct BacktickRuns {
+    current: usize,
+    pub longest: usize,
+    /// Whether the last byte written was a newline
+    pub ends_with_newline: bool,
+    pub bytes: usize,
+}
+
+impl Write for BacktickRuns {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            if byte == b'`' {
+                self.current += 1;
+                self.longest = self.longest.max(self.current);
+            } else {
+                self.current = 0;
+            }
+        }
+        if let Some(&last) = buf.last() {
+            self.ends_with_newline = last == b'\n';
+        }
+        self.bytes += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Map a file extension to the info string used on a Markdown code fence
+pub fn language_for_extension(ext: &str) -> Option<&'static str> {
+    let language = match ext {
+        "rs" => "rust",
+        "py" => "python",
+        "js" | "mjs" | "cjs" => "javascript",
+        "ts" => "typescript",
+        "tsx" => "tsx",
+        "jsx" => "jsx",
+        "go" => "go",
+        "c" | "h" => "c",
+        "cpp" | "cc" | "cxx" | "hpp" => "cpp",
+        "java" => "java",
+        "kt" => "kotlin",
+        "rb" => "ruby",
+        "sh" | "bash" => "bash",
+        "toml" => "toml",
+        "yaml" | "yml" => "yaml",
+        "json" => "json",
+        "md" => "markdown",
+        "html" => "html",
+        "css" => "css",
+        "sql" => "sql",
+        _ => return None,
+    };
+    Some(language)
+}
+
+/// Guess a file's language from its extension, well-known file names such as
+/// `Dockerfile`, or a `#!` line, in that order
+pub fn detect_language(path: &Path, content: &str) -> Option<&'static str> {
+    if let Some(language) = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(language_for_extension)
+    {
+        return Some(language);
+    }
+
+    let file_name = path.file_name().and_then(|name| name.to_str())?;
+    let by_name = match file_name {
+        "Dockerfile" | "Containerfile" => Some("dockerfile"),
+        "Makefile" | "GNUmakefile" | "makefile" => Some("makefile"),
+        "CMakeLists.txt" => Some("cmake"),
+        "Gemfile" | "Rakefile" => Some("ruby"),
+        "Jenkinsfile" => Some("groovy"),
+        _ if file_name.starts_with("Dockerfile.") => Some("dockerfile"),
+        _ => None,
+    };
+    by_name.or_else(|| shebang_language(content))
+}
+
+/// Language named by a `#!` interpreter line, e.g. `#!/usr/bin/env python3`
+fn shebang_language(content: &str) -> Option<&'static str> {
+    let line = content.lines().next()?.strip_prefix("#!")?;
+    let mut words = line.split_whitespace();
+    let mut interpreter = words.next()?.rsplit('/').next()?;
+    if interpreter == "env" {
+        interpreter = words.find(|word| !word.starts_with('-'))?;
+    }
+
+    // python3.12 -> python
+    let name =
+        interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    let language = match name {
+        "python" => "python",
+        "sh" | "bash" | "zsh" | "dash" | "ksh" => "bash",
+        "node" | "nodejs" => "javascript",
+        "deno" | "ts-node" => "typescript",
+        "ruby" => "ruby",
+        "perl" => "perl",
+        "php" => "php",
+        "lua" => "lua",
+        _ => return None,
+    };
+    Some(language)
+}
+
+/// Map a file extension to the language's line comment marker
+pub fn comment_prefix_for_extension(ext: &str) -> Option<&'static str> {
+    let prefix = match ext {
+        "rs" | "c" | "h" | "cpp" | "cc" | "cxx" | "hpp" | "java" | "kt" | "go"
+        | "js" | "mjs" | "cjs" | "ts" | "tsx" | "jsx" | "swift" | "scala"
+        | "cs" | "php" | "dart" | "zig" => "//",
+        "py" | "rb" | "sh" | "bash" | "zsh" | "toml" | "yaml" | "yml" | "pl"
+        | "r" | "ex" | "exs" | "nix" | "cmake" => "#",
+        "sql" | "lua" | "hs" | "elm" => "--",
+        "lisp" | "clj" | "el" | "scm" => ";;",
+        "erl" | "tex" => "%",
+        "vim" => "\"",
+        _ => return None,
+    };
+    Some(prefix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(format: Format, content: &str) -> String {
+        let mut out = Vec::new();
+        let mut formatter = format.formatter(&Config::default());
+        let section = FileSection {
+            path: Path::new("src/main.rs"),
+            absolute_path: Path::new("/repo/src/main.rs"),
+            commit_hash: "abc123",
+            short_hash: "abc123",
+            commit_time: "1700000000",
+            author: "Jane Doe <jane@example.com>",
+            message: "Initial commit",
+            content,
+            notes: &[],
+            delimiter: None,
+            permissions: None,
+            imports: None,
+            number: None,
+        };
+        formatter.write_prompt(&mut out, "Prompt").unwrap();
+        formatter.write_file(&mut out, &section).unwrap();
+        formatter.finish(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_plain_format() {
+        let output = render(Format::Plain, "fn main() {}");
+        assert_eq!(
+            output,
+            "Prompt\n---\nFile: src/main.rs\nLast commit: abc123\n\
+             Last update: 1700000000\nAuthor: Jane Doe <jane@example.com>\n\
+             Message: Initial commit\n---\nfn main() {}\n\n"
+        );
+    }
+
+    #[test]
+    fn test_footer_after_last_file() {
+        let footer = |format: Format| {
+            let mut out = Vec::new();
+            let mut formatter = format.formatter(&Config::default());
+            formatter.write_prompt(&mut out, "").unwrap();
+            formatter.write_footer(&mut out, "Now review it.").unwrap();
+            formatter.finish(&mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(footer(Format::Plain), "---\nNow review it.\n");
+
+        let value: serde_json::Value =
+            serde_json::from_str(&footer(Format::Json)).unwrap();
+        assert_eq!(value["footer_prompt"], "Now review it.");
+        assert_eq!(value["files"], serde_json::json!([]));
+
+        // No footer leaves the output untouched
+        let mut out = Vec::new();
+        Format::Plain
+            .formatter(&Config::default())
+            .write_footer(&mut out, "")
+            .unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_comment_delimiters() {
+        let imports = ["os".to_string(), ".models".to_string()];
+        let write = |path: &str| {
+            let mut out = Vec::new();
+            let section = FileSection {
+                path: Path::new(path),
+                absolute_path: Path::new(path),
+                commit_hash: "abc123",
+                short_hash: "abc123",
+                commit_time: "1700000000",
+                author: "Jane Doe <jane@example.com>",
+                message: "Initial commit",
+                content: "x = 1",
+                notes: &["comments stripped"],
+                delimiter: None,
+                permissions: Some("0755 (executable)"),
+                imports: Some(&imports),
+                number: None,
+            };
+            let config = Config {
+                comment_delimiters: true,
+                ..Config::default()
+            };
+            Format::Plain
+                .formatter(&config)
+                .write_file(&mut out, &section)
+                .unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let python = write("app.py");
+        assert!(
+            python.starts_with("# ---\n# File: app.py\n# Last commit: abc123\n")
+        );
+        assert!(python.ends_with(
+            "# Permissions: 0755 (executable)\n# Imports: os, .models\n\
+             # Note: comments stripped\n\
+             # ---\nx = 1\n\n"
+        ));
+        assert!(write("main.rs").starts_with("// ---\n// File: main.rs\n"));
+
+        // Unknown extensions keep the plain delimiter
+        assert!(write("notes.txt").starts_with("---\nFile: notes.txt\n"));
+    }
+
+    #[test]
+    fn test_manifest() {
+        let manifest = |format: Format| {
+            let mut out = Vec::new();
+            let entries = [
+                ManifestEntry {
+                    path: Path::new("src/main.rs"),
+                    bytes: 1234,
+                    commit: "abc1234",
+                },
+                ManifestEntry {
+                    path: Path::new("new.rs"),
+                    bytes: 5,
+                    commit: "untracked",
+                },
+            ];
+            let mut formatter = format.formatter(&Config::default());
+            formatter.write_prompt(&mut out, "").unwrap();
+            formatter.write_manifest(&mut out, &entries).unwrap();
+            formatter.finish(&mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(
+            manifest(Format::Plain),
+            "---\nManifest: 2 file(s)\n---\n\
+             src/main.rs  1234 B  abc1234\n\
+             new.rs          5 B  untracked\n\n"
+        );
+        assert!(manifest(Format::Markdown)
+            .contains("| `src/main.rs` | 1234 | `abc1234` |\n"));
+
+        let value: serde_json::Value =
+            serde_json::from_str(&manifest(Format::Json)).unwrap();
+        assert_eq!(value["manifest"][1]["relative_path"], "new.rs");
+        assert_eq!(value["manifest"][0]["bytes"], 1234);
+    }
+
+    #[test]
+    fn test_empty_prompt_is_omitted() {
+        let mut out = Vec::new();
+        let mut formatter = Format::Plain.formatter(&Config::default());
+        formatter.write_prompt(&mut out, "").unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_markdown_format() {
+        let output = render(Format::Markdown, "fn main() {}\n");
+        assert!(output.contains("- File: `src/main.rs`\n"));
+        assert!(output.contains("- Last commit: `abc123`\n"));
+        assert!(output.contains("```rust\nfn main() {}\n```\n"));
+    }
+
+    #[test]
+    fn test_backtick_runs_span_writes() -> io::Result<()> {
+        let mut runs = BacktickRuns::default();
+        write!(runs, "a ``")?;
+        write!(runs, "`` b `")?;
+        assert_eq!(runs.longest, 4);
+        assert!(!runs.ends_with_newline);
+        writeln!(runs)?;
+        assert!(runs.ends_with_newline);
+        assert_eq!(runs.bytes, 11);
+        assert_eq!(fence_longer_than(runs.longest), "`````");
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_format_escapes_content() {
+        let content = "let s = \"quoted\";\nnext line";
+        let output = render(Format::Json, content);
+        let value: serde_json::Value = serde_json::from_str(&output).unwrap();
+
+        assert_eq!(value["llm_prompt"], "Prompt");
+        assert_eq!(value["delimiter"], "---");
+        assert_eq!(value["files"][0]["relative_path"], "src/main.rs");
+        assert_eq!(value["files"][0]["absolute_path"], "/repo/src/main.rs");
+        assert_eq!(value["files"][0]["commit_hash"], "abc123");
+        assert_eq!(value["files"][0]["author"], "Jane Doe <jane@example.com>");
+        assert_eq!(value["files"][0]["message"], "Initial commit");
+        assert_eq!(value["files"][0]["content"], content);
+    }
+
+    #[test]
+    fn test_summary_block_in_every_format() {
+        let summary = Summary {
+            files: 2,
+            bytes: 2048,
+            tokens: 512,
+            tree: ".\n├── a.rs\n└── b.rs\n",
+        };
+        assert_eq!(
+            summary.to_string(),
+            "Files analyzed: 2\nTotal size: 2.0 KiB\nEstimated tokens: 512\n\n\
+             Directory structure:\n.\n├── a.rs\n└── b.rs\n"
+        );
+
+        let mut out = Vec::new();
+        let mut formatter = Format::Json.formatter(&Config::default());
+        formatter.write_prompt(&mut out, "Prompt").unwrap();
+        formatter.write_summary(&mut out, &summary).unwrap();
+        formatter.finish(&mut out).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(value["summary"]["files"], 2);
+        assert_eq!(value["summary"]["bytes"], 2048);
+        assert_eq!(value["summary"]["estimated_tokens"], 512);
+        assert_eq!(value["summary"]["tree"], summary.tree);
+
+        let mut out = Vec::new();
+        let mut formatter = Format::Xml.formatter(&Config::default());
+        formatter.write_summary(&mut out, &summary).unwrap();
+        let xml = String::from_utf8(out).unwrap();
+        assert!(xml.starts_with(
+            "<summary files=\"2\" bytes=\"2048\" estimated_tokens=\"512\">\n<tree>"
+        ));
+        assert!(xml.ends_with("</tree>\n</summary>\n"));
+    }
+
+    #[test]
+    fn test_json_format_with_tree_and_no_files() {
+        let mut out = Vec::new();
+        let mut formatter = Format::Json.formatter(&Config::default());
+        let info = RepoInfo {
+            branch: Some("main".to_string()),
+            head: Some("abc1234".to_string()),
+            commits: 3,
+            uncommitted_changes: 0,
+        };
+        formatter.write_prompt(&mut out, "Prompt").unwrap();
+        formatter.write_repo_info(&mut out, &info).unwrap();
+        formatter.write_tree(&mut out, ".\n└── a.rs\n").unwrap();
+        formatter.finish(&mut out).unwrap();
+
+        let value: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(value["tree"], ".\n└── a.rs\n");
+        assert_eq!(value["repo_info"]["branch"], "main");
+        assert_eq!(value["repo_info"]["commits"], 3);
+        assert_eq!(value["files"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_xml_format() {
+        let output = render(Format::Xml, "if a < b { x[y[0]]>z }");
+        assert_eq!(
+            output,
+            "<context>\n<instructions>Prompt</instructions>\n\
+             <file path=\"src/main.rs\" language=\"rust\" commit=\"abc123\" updated=\"1700000000\" \
+             author=\"Jane Doe &lt;jane@example.com&gt;\" message=\"Initial commit\">\
+             <![CDATA[if a < b { x[y[0]]]]><![CDATA[>z }]]></file>\n</context>\n"
+        );
+    }
+
+    #[test]
+    fn test_detect_language() {
+        let detect =
+            |path: &str, content: &str| detect_language(Path::new(path), content);
+
+        assert_eq!(detect("src/main.rs", "#!/bin/sh"), Some("rust"));
+        assert_eq!(detect("Dockerfile", "FROM rust"), Some("dockerfile"));
+        assert_eq!(detect("docker/Dockerfile.dev", ""), Some("dockerfile"));
+        assert_eq!(detect("Makefile", "all:"), Some("makefile"));
+        assert_eq!(
+            detect("bin/tool", "#!/usr/bin/env python3\nprint()"),
+            Some("python")
+        );
+        assert_eq!(detect("bin/run", "#!/bin/bash -e\n"), Some("bash"));
+        assert_eq!(
+            detect("bin/srv", "#!/usr/bin/env -S node --harmony"),
+            Some("javascript")
+        );
+        assert_eq!(detect("bin/x", "#!/usr/bin/python3.12"), Some("python"));
+        assert_eq!(detect("notes", "just text"), None);
+        assert_eq!(detect("bin/odd", "#!/opt/unknown-shell"), None);
+    }
+
+    #[test]
+    fn test_render_template() {
+        let values = [("path", "src/main.rs"), ("short_hash", "abc1234")];
+        assert_eq!(
+            render_template("// {path} @ {short_hash}", &values),
+            "// src/main.rs @ abc1234"
+        );
+        assert_eq!(
+            render_template("{unknown} {path} {", &values),
+            "{unknown} src/main.rs {"
+        );
+    }
+
+    #[test]
+    fn test_imports_in_structured_formats() {
+        let imports = ["std::fs".to_string(), "crate::walk".to_string()];
+        let write = |format: Format| {
+            let mut out = Vec::new();
+            let section = FileSection {
+                path: Path::new("src/lib.rs"),
+                absolute_path: Path::new("/repo/src/lib.rs"),
+                commit_hash: "abc123",
+                short_hash: "abc123",
+                commit_time: "1700000000",
+                author: "Jane Doe <jane@example.com>",
+                message: "Initial commit",
+                content: "use std::fs;",
+                notes: &[],
+                delimiter: None,
+                permissions: None,
+                imports: Some(&imports),
+                number: None,
+            };
+            let mut formatter = format.formatter(&Config::default());
+            formatter.write_file(&mut out, &section).unwrap();
+            formatter.finish(&mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert!(write(Format::Markdown)
+            .contains("- Imports: `std::fs`, `crate::walk`\n"));
+        assert!(write(Format::Xml).contains(" imports=\"std::fs, crate::walk\""));
+        let value: serde_json::Value =
+            serde_json::from_str(&format!("{{\"x\":0{}", write(Format::Json)))
+                .unwrap();
+        assert_eq!(
+            value["files"][0]["imports"],
+            serde_json::json!(["std::fs", "crate::walk"])
+        );
+    }
+
+    #[test]
+    fn test_custom_header_template() {
+        let mut out = Vec::new();
+        let config = Config {
+            header_template: "{path} ({size}, {short_hash})".to_string(),
+            ..Config::default()
+        };
+        let section = FileSection {
+            path: Path::new("src/main.rs"),
+            absolute_path: Path::new("/repo/src/main.rs"),
+            commit_hash: "abc1234def",
+            short_hash: "abc1234",
+            commit_time: "1700000000",
+            author: "Jane Doe <jane@example.com>",
+            message: "Initial commit",
+            content: "fn main() {}",
+            notes: &[],
+            delimiter: None,
+            permissions: None,
+            imports: None,
+            number: None,
+        };
+        Format::Plain
+            .formatter(&config)
+            .write_file(&mut out, &section)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "---\nsrc/main.rs (12 B, abc1234)\n---\nfn main() {}\n\n"
+        );
+    }
+
+    #[test]
+    fn test_number_lines() {
+        assert_eq!(number_lines("a\nb\n"), "   1 | a\n   2 | b\n");
+        assert_eq!(number_lines("a"), "   1 | a");
+        assert_eq!(number_lines(""), "");
+        assert_eq!(
+            number_lines("a\r\nb\r\n\r\n"),
+            "   1 | a\r\n   2 | b\r\n   3 | \r\n"
+        );
+        assert_eq!(number_lines("a\r\nb"), "   1 | a\r\n   2 | b");
+
+        let many = "x\n".repeat(12345);
+        assert!(number_lines(&many).ends_with("12345 | x\n"));
+    }
+
+    #[test]
+    fn test_squeeze_blank_lines() {
+        assert_eq!(squeeze_blank_lines("a\n\n\n\nb\n", 1), "a\n\nb\n");
+        assert_eq!(squeeze_blank_lines("a\n\n  \n\t\nb", 2), "a\n\n  \nb");
+        assert_eq!(squeeze_blank_lines("a\n\n\nb\n", 0), "a\nb\n");
+
+        // CRLF endings are kept on the surviving lines
+        assert_eq!(
+            squeeze_blank_lines("a\r\n\r\n\r\n\r\nb\r\n", 1),
+            "a\r\n\r\nb\r\n"
+        );
+
+        // Trailing blank runs are squeezed too; a final line without a
+        // newline stays as it is
+        assert_eq!(squeeze_blank_lines("a\n\n\n\n", 1), "a\n\n");
+        assert_eq!(squeeze_blank_lines("a\n\n", 0), "a\n");
+        assert!(matches!(
+            squeeze_blank_lines("a\n\nb", 1),
+            Cow::Borrowed("a\n\nb")
+        ));
+    }
+
+    #[test]
+    fn test_truncate_lines() {
+        let content = "1\n2\n3\n4\n5\n";
+        assert_eq!(
+            truncate_lines(content, 1, 2),
+            "1\n... 2 lines omitted ...\n4\n5\n"
+        );
+        assert_eq!(
+            truncate_lines(content, 2, 0),
+            "1\n2\n... 3 lines omitted ...\n"
+        );
+        assert_eq!(
+            truncate_lines("1\n2\n3", 0, 1),
+            "... 2 lines omitted ...\n3"
+        );
+        assert!(matches!(truncate_lines(content, 3, 2), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_fence_longer_than_content_backticks() {
+        assert_eq!(fence_for("no backticks"), "```");
+        assert_eq!(fence_for("```rust\n```"), "````");
+    }
+}
@@ -0,0 +1,421 @@
+//! Reading amc's own output back into per-file sections, for `amc diff` and
+//! other tools. Plain output (optionally with comment delimiters) and JSON
+//! are supported; plain headers must keep the `File:` line of the default
+//! `header_template`. A leading `# amc-format: N` line, written by
+//! `--output-format-version`, is checked against [`FORMAT_VERSION`].
+
+use crate::output::{FORMAT_VERSION, FORMAT_VERSION_PREFIX};
+use anyhow::{bail, Context, Result};
+use std::path::PathBuf;
+
+/// One file section recovered from a generated output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedFile {
+    pub path: PathBuf,
+    /// Value of the `Last commit` field, if the header had one
+    pub commit: Option<String>,
+    pub content: String,
+}
+
+/// A generated output split back into its parts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedOutput {
+    /// Layout version from the `# amc-format: N` line, `None` without one
+    pub version: Option<u32>,
+    /// Everything before the first file: the prompt, plus the tree and
+    /// repository info when they were written
+    pub prompt: String,
+    pub files: Vec<ParsedFile>,
+}
+
+/// Split a generated output into its file sections. `delimiters` and
+/// `footer` must be the config values it was generated with, as
+/// [`Config::delimiters`](crate::config::Config::delimiters) lists them: the
+/// global delimiter first, then those of `[formats]` overrides. The prompt and
+/// the manifest are skipped.
+pub fn parse_output(
+    text: &str,
+    delimiters: &[&str],
+    footer: &str,
+) -> Result<Vec<ParsedFile>> {
+    Ok(parse_document(text, delimiters, footer)?.files)
+}
+
+/// Like [`parse_output`], also returning the prompt and the format version.
+/// Fails for a version newer than this build writes.
+pub fn parse_document(
+    text: &str,
+    delimiters: &[&str],
+    footer: &str,
+) -> Result<ParsedOutput> {
+    let (version, text) = split_version(text)?;
+    let (prompt, files) = if text.trim_start().starts_with('{') {
+        parse_json(text)?
+    } else {
+        parse_plain(text, delimiters, footer.trim_end())?
+    };
+    Ok(ParsedOutput {
+        version,
+        prompt,
+        files,
+    })
+}
+
+/// Take the `# amc-format: N` line off the front of `text`, if there is one
+fn split_version(text: &str) -> Result<(Option<u32>, &str)> {
+    let Some(rest) = text.strip_prefix(FORMAT_VERSION_PREFIX) else {
+        return Ok((None, text));
+    };
+    let (line, rest) = rest.split_once('\n').unwrap_or((rest, ""));
+    let version: u32 = line
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid format version line: {:?}", line))?;
+    if version > FORMAT_VERSION {
+        bail!(
+            "Output uses amc-format {}, but this amc only reads up to {}",
+            version,
+            FORMAT_VERSION
+        );
+    }
+    Ok((Some(version), rest))
+}
+
+fn parse_json(text: &str) -> Result<(String, Vec<ParsedFile>)> {
+    let value: serde_json::Value =
+        serde_json::from_str(text).context("Failed to parse JSON output")?;
+    let Some(files) = value["files"].as_array() else {
+        bail!("JSON output has no \"files\" array");
+    };
+    let prompt = value["llm_prompt"].as_str().unwrap_or_default().to_string();
+
+    let files = files
+        .iter()
+        .map(|file| {
+            let field = |name: &str| file[name].as_str().map(str::to_string);
+            Ok(ParsedFile {
+                path: field("relative_path")
+                    .context("File entry without \"relative_path\"")?
+                    .into(),
+                commit: field("commit_hash"),
+                content: field("content").unwrap_or_default(),
+            })
+        })
+        .collect::<Result<_>>()?;
+    Ok((prompt, files))
+}
+
+fn parse_plain(
+    text: &str,
+    delimiters: &[&str],
+    footer: &str,
+) -> Result<(String, Vec<ParsedFile>)> {
+    // The manifest and footer always use the global delimiter
+    let delimiter = delimiters.first().copied().unwrap_or("---");
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let mut files = Vec::new();
+    let mut index = (0..lines.len())
+        .find(|&i| section_prefix(&lines, i, delimiters).is_some())
+        .unwrap_or(lines.len());
+    let prompt = lines[..index].concat();
+    // The prompt is written with one newline of its own
+    let prompt = prompt.strip_suffix('\n').unwrap_or(&prompt).to_string();
+
+    while let Some((prefix, opening)) = section_prefix(&lines, index, delimiters) {
+        let closing = format!("{}{}", prefix, opening);
+        let header_end = (index + 1..lines.len())
+            .find(|&i| trim_newline(lines[i]) == closing)
+            .with_context(|| {
+                format!("Unterminated header at line {}", index + 1)
+            })?;
+
+        let mut path = None;
+        let mut commit = None;
+        for line in &lines[index + 1..header_end] {
+            let field = trim_newline(line).strip_prefix(prefix).unwrap_or_default();
+            if let Some(value) = field.strip_prefix("File: ") {
+                path = Some(PathBuf::from(value));
+            } else if let Some(value) = field.strip_prefix("Last commit: ") {
+                commit = Some(value.to_string());
+            }
+        }
+        let Some(path) = path else {
+            bail!("Header without a File: line at line {}", index + 1);
+        };
+
+        let next = (header_end + 1..lines.len())
+            .find(|&i| {
+                section_prefix(&lines, i, delimiters).is_some()
+                    || is_manifest(&lines, i, delimiter)
+            })
+            .unwrap_or(lines.len());
+        // A `--group-by-dir` heading before the next section isn't content
+        let mut end = next;
+        if end >= header_end + 3
+            && lines[end - 1] == "\n"
+            && lines[end - 2].starts_with("### directory: ")
+        {
+            end -= 2;
+        }
+        let mut section = lines[header_end + 1..end].concat();
+        if next == lines.len() && !footer.is_empty() {
+            let written_footer = format!("{}\n{}\n", delimiter, footer);
+            if let Some(stripped) = section.strip_suffix(&written_footer) {
+                section.truncate(stripped.len());
+            }
+        }
+        // Every section is written as `{content}\n\n`
+        let content = section
+            .strip_suffix("\n\n")
+            .or_else(|| section.strip_suffix('\n'))
+            .unwrap_or(&section)
+            .to_string();
+
+        files.push(ParsedFile {
+            path,
+            commit,
+            content,
+        });
+        index = next;
+    }
+
+    Ok((prompt, files))
+}
+
+/// If line `index` opens a file section (one of `delimiters` followed by
+/// `File: ...`, possibly behind a comment prefix like `// ` and a
+/// `--number-files` label), the prefix and the delimiter
+fn section_prefix<'a, 'd>(
+    lines: &[&'a str],
+    index: usize,
+    delimiters: &[&'d str],
+) -> Option<(&'a str, &'d str)> {
+    let line = trim_newline(lines.get(index)?);
+    delimiters.iter().find_map(|&delimiter| {
+        let prefix = line.strip_suffix(delimiter)?;
+        let field = |index: usize| {
+            lines
+                .get(index)
+                .and_then(|line| trim_newline(line).strip_prefix(prefix))
+        };
+        let mut next = field(index + 1)?;
+        if is_number_label(next) {
+            next = field(index + 2)?;
+        }
+        next.starts_with("File: ").then_some((prefix, delimiter))
+    })
+}
+
+/// `[File 3/27]`, as written by `output::file_number_label`
+fn is_number_label(line: &str) -> bool {
+    line.strip_prefix("[File ")
+        .and_then(|rest| rest.strip_suffix(']'))
+        .and_then(|numbers| numbers.split_once('/'))
+        .is_some_and(|(index, total)| {
+            index.parse::<usize>().is_ok() && total.parse::<usize>().is_ok()
+        })
+}
+
+fn is_manifest(lines: &[&str], index: usize, delimiter: &str) -> bool {
+    trim_newline(lines[index]) == delimiter
+        && lines
+            .get(index + 1)
+            .is_some_and(|next| next.starts_with("Manifest: "))
+}
+
+fn trim_newline(line: &str) -> &str {
+    line.trim_end_matches(['\n', '\r'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, FormatOverride};
+    use crate::output::{FileSection, Format, ManifestEntry};
+    use std::path::Path;
+
+    /// Render files the way `render` does, to parse them back
+    fn generate(config: &Config, files: &[(&str, &str)], numbered: bool) -> String {
+        let mut out = Vec::new();
+        let mut formatter = Format::Plain.formatter(config);
+        formatter
+            .write_prompt(&mut out, "Prompt\n---\nnot a file")
+            .unwrap();
+        for (index, (path, content)) in files.iter().enumerate() {
+            formatter
+                .write_file(
+                    &mut out,
+                    &FileSection {
+                        path: Path::new(path),
+                        absolute_path: Path::new(path),
+                        commit_hash: "abc123",
+                        short_hash: "abc123",
+                        commit_time: "1700000000",
+                        author: "Jane Doe <jane@example.com>",
+                        message: "Initial commit",
+                        content,
+                        notes: &[],
+                        delimiter: config
+                            .format_for(Path::new(path))
+                            .and_then(|format| format.delimiter.as_deref()),
+                        permissions: None,
+                        imports: None,
+                        number: numbered.then_some((index + 1, files.len())),
+                    },
+                )
+                .unwrap();
+        }
+        let entries = [ManifestEntry {
+            path: Path::new("a.rs"),
+            bytes: 1,
+            commit: "abc123",
+        }];
+        formatter.write_manifest(&mut out, &entries).unwrap();
+        formatter.write_footer(&mut out, "Footer").unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_round_trips_plain_output() -> Result<()> {
+        let config = Config::default();
+        let files = [
+            ("a.rs", "fn a() {}\n"),
+            ("notes.md", "---\ntitle: front matter\n---\nno newline"),
+        ];
+        let parsed = parse_output(&generate(&config, &files, false), &["---"], "")?;
+
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].path, PathBuf::from("a.rs"));
+        assert_eq!(parsed[0].commit.as_deref(), Some("abc123"));
+        assert_eq!(parsed[0].content, "fn a() {}\n");
+        assert_eq!(parsed[1].content, files[1].1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trips_numbered_files() -> Result<()> {
+        let files = [("a.rs", "fn a() {}\n"), ("src/b.rs", "fn b() {}\n")];
+        for comment_delimiters in [false, true] {
+            let config = Config {
+                comment_delimiters,
+                ..Config::default()
+            };
+            let text = generate(&config, &files, true);
+            assert!(text.contains("[File 2/2]"));
+
+            let parsed = parse_output(&text, &["---"], "")?;
+            assert_eq!(parsed.len(), 2);
+            assert_eq!(parsed[1].path, PathBuf::from("src/b.rs"));
+            assert_eq!(parsed[1].commit.as_deref(), Some("abc123"));
+            assert_eq!(parsed[1].content, "fn b() {}\n");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trips_per_extension_delimiters() -> Result<()> {
+        let config = Config {
+            formats: [(
+                "md".to_string(),
+                FormatOverride {
+                    delimiter: Some("~~~".to_string()),
+                    line_numbers: None,
+                },
+            )]
+            .into(),
+            ..Config::default()
+        };
+        let files = [
+            ("a.rs", "fn a() {}\n"),
+            ("README.md", "# Title\n---\ntext\n"),
+            ("b.rs", "fn b() {}\n"),
+        ];
+        let text = generate(&config, &files, false);
+        assert!(text.contains("~~~\nFile: README.md\n"));
+
+        let parsed = parse_output(&text, &config.delimiters(), "")?;
+        let paths: Vec<&Path> =
+            parsed.iter().map(|file| file.path.as_path()).collect();
+        assert_eq!(
+            paths,
+            [Path::new("a.rs"), Path::new("README.md"), Path::new("b.rs")]
+        );
+        assert_eq!(parsed[0].content, "fn a() {}\n");
+        assert_eq!(parsed[1].content, files[1].1);
+        assert_eq!(parsed[1].commit.as_deref(), Some("abc123"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_comment_delimiters_and_footer() -> Result<()> {
+        let config = Config {
+            comment_delimiters: true,
+            ..Config::default()
+        };
+        let text = generate(&config, &[("app.py", "x = 1\n")], false);
+        let parsed = parse_output(&text, &["---"], "")?;
+        assert_eq!(parsed[0].path, PathBuf::from("app.py"));
+        assert_eq!(parsed[0].content, "x = 1\n");
+
+        let text = "---\nFile: a.rs\n---\nfn a() {}\n\n\n---\nFooter\n";
+        let parsed = parse_output(text, &["---"], "Footer\n")?;
+        assert_eq!(parsed[0].content, "fn a() {}\n");
+
+        let text = "### directory: .\n\n---\nFile: a.rs\n---\nfn a() {}\n\n\n\
+                    ### directory: src\n\n---\nFile: src/b.rs\n---\nb\n\n\n";
+        let parsed = parse_output(text, &["---"], "")?;
+        assert_eq!(parsed[0].content, "fn a() {}\n");
+        assert_eq!(parsed[1].content, "b\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parses_json_output() -> Result<()> {
+        let text = r#"{"llm_prompt":"","delimiter":"---","files":[
+            {"relative_path":"a.rs","commit_hash":"abc","content":"fn a() {}"}]}"#;
+        let parsed = parse_output(text, &["---"], "")?;
+        assert_eq!(
+            parsed,
+            vec![ParsedFile {
+                path: "a.rs".into(),
+                commit: Some("abc".to_string()),
+                content: "fn a() {}".to_string(),
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_versioned_output() -> Result<()> {
+        let text = format!(
+            "{}{}\n{}",
+            FORMAT_VERSION_PREFIX,
+            FORMAT_VERSION,
+            generate(&Config::default(), &[("a.rs", "fn a() {}\n")], false)
+        );
+        let parsed = parse_document(&text, &["---"], "")?;
+        assert_eq!(parsed.version, Some(FORMAT_VERSION));
+        assert_eq!(parsed.prompt, "Prompt\n---\nnot a file");
+        assert_eq!(parsed.files.len(), 1);
+        assert_eq!(parsed.files[0].content, "fn a() {}\n");
+
+        let unversioned = parse_document("Prompt\n", &["---"], "")?;
+        assert_eq!(unversioned.version, None);
+        assert_eq!(unversioned.prompt, "Prompt");
+
+        let newer = format!("{}{}\n", FORMAT_VERSION_PREFIX, FORMAT_VERSION + 1);
+        assert!(parse_document(&newer, &["---"], "").is_err());
+        let garbled = format!("{}one\n", FORMAT_VERSION_PREFIX);
+        assert!(parse_document(&garbled, &["---"], "").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_output() {
+        assert!(parse_output("---\nFile: a.rs\nno end", &["---"], "").is_err());
+        assert_eq!(
+            parse_output("just a prompt\n", &["---"], "").unwrap(),
+            vec![]
+        );
+    }
+}
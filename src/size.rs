@@ -0,0 +1,80 @@
+//! Parsing and formatting of human-friendly byte sizes such as `200k` or `1M`.
+
+const UNITS: &[(&str, u64)] = &[
+    ("g", 1024 * 1024 * 1024),
+    ("m", 1024 * 1024),
+    ("k", 1024),
+    ("b", 1),
+];
+
+/// Parse a byte count with an optional binary suffix (`k`, `m`, `g`, with or
+/// without a trailing `b`/`ib`), case-insensitive
+pub fn parse_size(input: &str) -> Result<u64, String> {
+    let lower = input.trim().to_ascii_lowercase();
+    let trimmed = lower
+        .strip_suffix("ib")
+        .or_else(|| lower.strip_suffix('b').filter(|rest| !rest.is_empty()))
+        .unwrap_or(&lower);
+
+    let (number, multiplier) = UNITS
+        .iter()
+        .find_map(|(suffix, multiplier)| {
+            trimmed
+                .strip_suffix(suffix)
+                .map(|number| (number, *multiplier))
+        })
+        .unwrap_or((trimmed, 1));
+
+    let value: u64 = number.trim().parse().map_err(|_| {
+        format!("invalid size '{}', expected e.g. 512, 200k, 1M", input)
+    })?;
+
+    value
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size '{}' is too large", input))
+}
+
+/// Format a byte count with the largest binary unit that keeps it readable
+pub fn format_size(bytes: u64) -> String {
+    match bytes {
+        b if b >= 1024 * 1024 * 1024 => {
+            format!("{:.1} GiB", b as f64 / (1024.0 * 1024.0 * 1024.0))
+        }
+        b if b >= 1024 * 1024 => format!("{:.1} MiB", b as f64 / (1024.0 * 1024.0)),
+        b if b >= 1024 => format!("{:.1} KiB", b as f64 / 1024.0),
+        b => format!("{} B", b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_plain_bytes() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("512b"), Ok(512));
+    }
+
+    #[test]
+    fn test_parse_suffixes() {
+        assert_eq!(parse_size("200k"), Ok(200 * 1024));
+        assert_eq!(parse_size("1M"), Ok(1024 * 1024));
+        assert_eq!(parse_size("2MiB"), Ok(2 * 1024 * 1024));
+        assert_eq!(parse_size("1gb"), Ok(1024 * 1024 * 1024));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("lots").is_err());
+        assert!(parse_size("1.5M").is_err());
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(100), "100 B");
+        assert_eq!(format_size(200 * 1024), "200.0 KiB");
+        assert_eq!(format_size(3 * 1024 * 1024 / 2), "1.5 MiB");
+    }
+}
@@ -0,0 +1,201 @@
+//! Repository overview for the `stats` subcommand. Comment line counts are
+//! approximate, based on the same detection as `--strip-comments`.
+
+use crate::output::detect_language;
+use crate::size::format_size;
+use crate::strip::count_comment_lines;
+use crate::walk::FileEntry;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+/// Totals for one group of files
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Totals {
+    pub files: usize,
+    pub lines: usize,
+    pub blank: usize,
+    /// Lines holding only comments, as far as `--strip-comments` can tell;
+    /// zero for languages it doesn't support
+    pub comments: usize,
+    pub bytes: u64,
+}
+
+impl Totals {
+    fn add(&mut self, file: &Totals) {
+        self.files += file.files;
+        self.lines += file.lines;
+        self.blank += file.blank;
+        self.comments += file.comments;
+        self.bytes += file.bytes;
+    }
+
+    /// Lines that are neither blank nor comment-only
+    pub fn code(&self) -> usize {
+        self.lines - self.blank - self.comments
+    }
+}
+
+/// Aggregated statistics over the walked files
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub total: Totals,
+    /// Keyed by file extension, `(none)` for files without one
+    pub by_extension: BTreeMap<String, Totals>,
+    /// Largest files first
+    pub largest: Vec<(PathBuf, u64)>,
+    top: usize,
+}
+
+impl Stats {
+    /// Read every file once and aggregate, keeping the `top` largest files
+    pub fn collect(files: &[FileEntry], top: usize) -> Result<Self> {
+        let mut stats = Stats {
+            top,
+            ..Default::default()
+        };
+
+        for file in files {
+            let content = fs::read(&file.absolute_path)?;
+            let text = String::from_utf8_lossy(&content);
+            let totals = Totals {
+                files: 1,
+                lines: count_lines(&content),
+                blank: text.lines().filter(|line| line.trim().is_empty()).count(),
+                comments: detect_language(&file.relative_path, &text)
+                    .and_then(|language| count_comment_lines(language, &text))
+                    .unwrap_or(0),
+                bytes: content.len() as u64,
+            };
+            let extension = file
+                .relative_path
+                .extension()
+                .map(|ext| ext.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "(none)".to_string());
+
+            stats.total.add(&totals);
+            stats
+                .by_extension
+                .entry(extension)
+                .or_default()
+                .add(&totals);
+            stats
+                .largest
+                .push((file.relative_path.clone(), totals.bytes));
+        }
+
+        stats
+            .largest
+            .sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        stats.largest.truncate(top);
+        Ok(stats)
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<12} {:>8} {:>10} {:>10} {:>10} {:>10} {:>12}",
+            "Extension", "Files", "Lines", "Code", "Comments", "Blank", "Size"
+        )?;
+        for (extension, totals) in &self.by_extension {
+            write_row(f, extension, totals)?;
+        }
+        write_row(f, "Total", &self.total)?;
+
+        if self.top > 0 && !self.largest.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Largest files:")?;
+            for (path, bytes) in &self.largest {
+                writeln!(f, "{:>12}  {}", format_size(*bytes), path.display())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_row(
+    f: &mut fmt::Formatter<'_>,
+    label: &str,
+    totals: &Totals,
+) -> fmt::Result {
+    writeln!(
+        f,
+        "{:<12} {:>8} {:>10} {:>10} {:>10} {:>10} {:>12}",
+        label,
+        totals.files,
+        totals.lines,
+        totals.code(),
+        totals.comments,
+        totals.blank,
+        format_size(totals.bytes)
+    )
+}
+
+/// Number of lines, counting a final line without a trailing newline
+fn count_lines(content: &[u8]) -> usize {
+    let newlines = content.iter().filter(|&&b| b == b'\n').count();
+    if content.last().is_some_and(|&b| b != b'\n') {
+        newlines + 1
+    } else {
+        newlines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn entry(dir: &TempDir, path: &str, content: &str) -> FileEntry {
+        let absolute_path = dir.path().join(path);
+        fs::write(&absolute_path, content).unwrap();
+        FileEntry {
+            absolute_path,
+            relative_path: PathBuf::from(path),
+        }
+    }
+
+    #[test]
+    fn test_count_lines() {
+        assert_eq!(count_lines(b""), 0);
+        assert_eq!(count_lines(b"a"), 1);
+        assert_eq!(count_lines(b"a\nb\n"), 2);
+        assert_eq!(count_lines(b"a\nb"), 2);
+    }
+
+    #[test]
+    fn test_collect_groups_by_extension() -> Result<()> {
+        let dir = TempDir::new()?;
+        let files = vec![
+            entry(&dir, "a.rs", "fn a() {}\n"),
+            entry(&dir, "b.rs", "// b\nfn b() {}\n\n"),
+            entry(&dir, "Makefile", "all:\n"),
+        ];
+
+        let stats = Stats::collect(&files, 1)?;
+
+        assert_eq!(
+            stats.total,
+            Totals {
+                files: 3,
+                lines: 5,
+                blank: 1,
+                comments: 1,
+                bytes: 31
+            }
+        );
+        assert_eq!(stats.by_extension["rs"].files, 2);
+        assert_eq!(stats.by_extension["rs"].code(), 2);
+        assert_eq!(stats.by_extension["(none)"].lines, 1);
+        assert_eq!(stats.largest, vec![(PathBuf::from("b.rs"), 16)]);
+
+        let table = stats.to_string();
+        assert!(table.contains("Largest files:"));
+        assert!(table.lines().any(|line| line.starts_with("Total")));
+        Ok(())
+    }
+}
@@ -0,0 +1,400 @@
+//! Conservative comment removal for the `--strip-comments` option. Each
+//! supported language describes its comment and string syntax, and a single
+//! scanner copies strings verbatim so comment markers inside them survive.
+
+/// Comment and literal syntax of one language family
+struct Syntax {
+    line_comment: Option<&'static str>,
+    block_comment: Option<(&'static str, &'static str)>,
+    /// Rust block comments nest
+    nested_blocks: bool,
+    /// Quotes starting a string literal in which `\` escapes
+    quotes: &'static [char],
+    /// Quotes starting a literal without escapes (Go raw strings, shell `'`)
+    raw_quotes: &'static [char],
+    /// Rust raw strings such as `r#"..."#`, and `'c'` char literals that must
+    /// be told apart from lifetimes
+    rust_literals: bool,
+    /// Python `"""` and `'''` strings
+    triple_quotes: bool,
+    /// Shell: `#` only starts a comment at the beginning of a word
+    word_comments: bool,
+}
+
+const RUST: Syntax = Syntax {
+    line_comment: Some("//"),
+    block_comment: Some(("/*", "*/")),
+    nested_blocks: true,
+    quotes: &['"'],
+    raw_quotes: &[],
+    rust_literals: true,
+    triple_quotes: false,
+    word_comments: false,
+};
+
+const C_LIKE: Syntax = Syntax {
+    line_comment: Some("//"),
+    block_comment: Some(("/*", "*/")),
+    nested_blocks: false,
+    quotes: &['"', '\'', '`'],
+    raw_quotes: &[],
+    rust_literals: false,
+    triple_quotes: false,
+    word_comments: false,
+};
+
+/// Only block comments: `//` is no comment in CSS and shows up in `url()`
+const CSS: Syntax = Syntax {
+    line_comment: None,
+    quotes: &['"', '\''],
+    ..C_LIKE
+};
+
+const GO: Syntax = Syntax {
+    quotes: &['"', '\''],
+    raw_quotes: &['`'],
+    ..C_LIKE
+};
+
+const PYTHON: Syntax = Syntax {
+    line_comment: Some("#"),
+    block_comment: None,
+    nested_blocks: false,
+    quotes: &['"', '\''],
+    raw_quotes: &[],
+    rust_literals: false,
+    triple_quotes: true,
+    word_comments: false,
+};
+
+const SHELL: Syntax = Syntax {
+    line_comment: Some("#"),
+    block_comment: None,
+    nested_blocks: false,
+    quotes: &['"'],
+    raw_quotes: &['\''],
+    rust_literals: false,
+    triple_quotes: false,
+    word_comments: true,
+};
+
+fn syntax_for(language: &str) -> Option<&'static Syntax> {
+    let syntax = match language {
+        "rust" => &RUST,
+        "c" | "cpp" | "java" | "kotlin" | "javascript" | "typescript" | "tsx"
+        | "jsx" => &C_LIKE,
+        "css" => &CSS,
+        "go" => &GO,
+        "python" => &PYTHON,
+        "bash" => &SHELL,
+        _ => return None,
+    };
+    Some(syntax)
+}
+
+/// Remove line and block comments from `content` written in `language` (as
+/// named by [`crate::output::detect_language`]). Lines left holding only a
+/// removed comment are dropped. Returns `None` for unsupported languages.
+pub fn strip_comments(language: &str, content: &str) -> Option<String> {
+    syntax_for(language).map(|syntax| Scanner::new(syntax, content).run())
+}
+
+/// Approximate number of lines holding nothing but comments: non-blank lines
+/// that disappear when comments are stripped. `None` for unsupported
+/// languages.
+pub fn count_comment_lines(language: &str, content: &str) -> Option<usize> {
+    let non_blank =
+        |text: &str| text.lines().filter(|l| !l.trim().is_empty()).count();
+    let stripped = strip_comments(language, content)?;
+    Some(non_blank(content).saturating_sub(non_blank(&stripped)))
+}
+
+struct Scanner<'a> {
+    syntax: &'static Syntax,
+    content: &'a str,
+    pos: usize,
+    out: String,
+    /// Where the current output line starts, and whether a comment was
+    /// removed from it
+    line_start: usize,
+    line_had_comment: bool,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(syntax: &'static Syntax, content: &'a str) -> Self {
+        Self {
+            syntax,
+            content,
+            pos: 0,
+            out: String::with_capacity(content.len()),
+            line_start: 0,
+            line_had_comment: false,
+        }
+    }
+
+    fn run(mut self) -> String {
+        // Keep a `#!` interpreter line even where `#` starts comments
+        if self.content.starts_with("#!") {
+            let end = self
+                .content
+                .find('\n')
+                .map_or(self.content.len(), |i| i + 1);
+            self.copy_to(end);
+            self.line_start = self.out.len();
+        }
+
+        while let Some(c) = self.rest().chars().next() {
+            let rest = self.rest();
+            let syntax = self.syntax;
+
+            if c == '\n' {
+                self.end_line();
+            } else if syntax.triple_quotes
+                && (rest.starts_with("\"\"\"") || rest.starts_with("'''"))
+            {
+                let quote = &rest[..3];
+                let end = rest[3..].find(quote).map_or(rest.len(), |i| i + 6);
+                self.copy_to(self.pos + end);
+            } else if syntax.rust_literals
+                && self.at_word_start()
+                && raw_string_len(rest).is_some()
+            {
+                let len = raw_string_len(rest).unwrap_or(rest.len());
+                self.copy_to(self.pos + len);
+            } else if syntax.rust_literals && c == '\'' {
+                let len = char_literal_len(rest).unwrap_or(1);
+                self.copy_to(self.pos + len);
+            } else if let Some((open, close)) = syntax
+                .block_comment
+                .filter(|(open, _)| rest.starts_with(open))
+            {
+                self.skip_block(open, close);
+            } else if syntax
+                .line_comment
+                .is_some_and(|marker| rest.starts_with(marker))
+                && (!syntax.word_comments || self.at_shell_word_start())
+            {
+                let end = rest.find('\n').map_or(rest.len(), |i| i);
+                self.pos += end;
+                self.trim_trailing_space();
+                self.line_had_comment = true;
+            } else if syntax.quotes.contains(&c) {
+                let len = string_len(rest, c, true);
+                self.copy_to(self.pos + len);
+            } else if syntax.raw_quotes.contains(&c) {
+                let len = string_len(rest, c, false);
+                self.copy_to(self.pos + len);
+            } else {
+                self.copy_to(self.pos + c.len_utf8());
+            }
+        }
+
+        self.drop_line_if_emptied();
+        self.out
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.content[self.pos..]
+    }
+
+    fn copy_to(&mut self, end: usize) {
+        let end = end.min(self.content.len());
+        self.out.push_str(&self.content[self.pos..end]);
+        // Multi-line literals start a fresh output line after their last
+        // newline, so they're never mistaken for emptied lines
+        if let Some(newline) = self.content[self.pos..end].rfind('\n') {
+            self.line_start = self.out.len() - (end - self.pos - newline - 1);
+            self.line_had_comment = false;
+        }
+        self.pos = end;
+    }
+
+    fn end_line(&mut self) {
+        self.pos += 1;
+        if !self.drop_line_if_emptied() {
+            self.out.push('\n');
+        }
+        self.line_start = self.out.len();
+        self.line_had_comment = false;
+    }
+
+    /// Remove the current output line if only a comment was on it
+    fn drop_line_if_emptied(&mut self) -> bool {
+        let emptied =
+            self.line_had_comment && self.out[self.line_start..].trim().is_empty();
+        if emptied {
+            self.out.truncate(self.line_start);
+        }
+        emptied
+    }
+
+    fn skip_block(&mut self, open: &str, close: &str) {
+        let mut depth = 0;
+        while !self.rest().is_empty() {
+            let rest = self.rest();
+            if rest.starts_with(open) && (depth == 0 || self.syntax.nested_blocks) {
+                depth += 1;
+                self.pos += open.len();
+            } else if rest.starts_with(close) {
+                depth -= 1;
+                self.pos += close.len();
+                if depth == 0 {
+                    break;
+                }
+            } else {
+                self.pos += rest.chars().next().map_or(1, char::len_utf8);
+            }
+        }
+        self.line_had_comment = true;
+
+        // `a/* x */b` must not glue `a` and `b` together
+        let before = self.out.chars().next_back();
+        let after = self.rest().chars().next();
+        if before.is_some_and(|c| !c.is_whitespace())
+            && after.is_some_and(|c| !c.is_whitespace())
+        {
+            self.out.push(' ');
+        } else {
+            self.trim_trailing_space();
+        }
+    }
+
+    fn trim_trailing_space(&mut self) {
+        let trimmed = self.out.trim_end_matches([' ', '\t']).len();
+        self.out.truncate(trimmed.max(self.line_start));
+    }
+
+    fn at_word_start(&self) -> bool {
+        !self.content[..self.pos]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_alphanumeric() || c == '_')
+    }
+
+    fn at_shell_word_start(&self) -> bool {
+        self.content[..self.pos]
+            .chars()
+            .next_back()
+            .is_none_or(|c| c.is_whitespace() || c == ';')
+    }
+}
+
+/// Length of a literal starting with `quote`, up to and including the
+/// closing quote (or the rest of the text when unterminated)
+fn string_len(text: &str, quote: char, escapes: bool) -> usize {
+    let mut chars = text.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        if escapes && c == '\\' {
+            chars.next();
+        } else if c == quote {
+            return i + c.len_utf8();
+        }
+    }
+    text.len()
+}
+
+/// Length of a Rust raw string like `r"..."`, `r#"..."#` or `br#"..."#`
+fn raw_string_len(text: &str) -> Option<usize> {
+    let after_prefix = text.strip_prefix('b').unwrap_or(text).strip_prefix('r')?;
+    let hashes = after_prefix.len() - after_prefix.trim_start_matches('#').len();
+    let body = after_prefix[hashes..].strip_prefix('"')?;
+    let terminator = format!("\"{}", "#".repeat(hashes));
+    let prefix_len = text.len() - body.len();
+    Some(
+        body.find(&terminator)
+            .map_or(text.len(), |i| prefix_len + i + terminator.len()),
+    )
+}
+
+/// Length of a Rust char literal such as `'a'`, `'\n'` or `'\u{1F600}'`;
+/// `None` for lifetimes like `'a`
+fn char_literal_len(text: &str) -> Option<usize> {
+    let mut chars = text.char_indices().skip(1);
+    match chars.next()? {
+        (_, '\\') => text[1..].find('\'').map(|i| i + 2).filter(|&len| len > 3),
+        (_, c) => match chars.next()? {
+            (i, '\'') if c != '\'' => Some(i + 1),
+            _ => None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip(language: &str, content: &str) -> String {
+        strip_comments(language, content).unwrap()
+    }
+
+    #[test]
+    fn test_rust_comments_and_literals() {
+        let code = "//! Crate docs\n\
+                    /// Adds\n\
+                    fn add<'a>(a: &'a str) -> char { // trailing\n    \
+                    let url = \"http://x\"; /* block /* nested */ still */\n    \
+                    let raw = r#\"a // b \"quoted\"\"#;\n    \
+                    '/' // slash\n\
+                    }\n";
+        assert_eq!(
+            strip("rust", code),
+            "fn add<'a>(a: &'a str) -> char {\n    \
+             let url = \"http://x\";\n    \
+             let raw = r#\"a // b \"quoted\"\"#;\n    \
+             '/'\n\
+             }\n"
+        );
+        assert_eq!(strip("rust", "let x = a/* c */+b;"), "let x = a +b;");
+        assert_eq!(strip("rust", "let c = '\\'';// q"), "let c = '\\'';");
+    }
+
+    #[test]
+    fn test_python_comments_and_strings() {
+        let code = "#!/usr/bin/env python3\n\
+                    # comment\n\
+                    x = \"# not a comment\"  # but this is\n\
+                    doc = \"\"\"\n# inside docstring\n\"\"\"\n";
+        assert_eq!(
+            strip("python", code),
+            "#!/usr/bin/env python3\n\
+             x = \"# not a comment\"\n\
+             doc = \"\"\"\n# inside docstring\n\"\"\"\n"
+        );
+    }
+
+    #[test]
+    fn test_javascript_and_shell() {
+        let js = "/**\n * Docs\n */\nconst s = `a ${b} // c`; // d\nconst t = 'it\\'s'; /* e */\n";
+        assert_eq!(
+            strip("javascript", js),
+            "const s = `a ${b} // c`;\nconst t = 'it\\'s';\n"
+        );
+
+        let sh = "echo $# \"#x\" 'y#' # comment\n# whole line\nls\n";
+        assert_eq!(strip("bash", sh), "echo $# \"#x\" 'y#'\nls\n");
+    }
+
+    #[test]
+    fn test_css_has_only_block_comments() {
+        let css = "/* Header */\n\
+                   a { background: url(http://example.com/x.png); color: red; }\n\
+                   b::after { content: \"/* kept */\"; } /* trailing */\n";
+        assert_eq!(
+            strip("css", css),
+            "a { background: url(http://example.com/x.png); color: red; }\n\
+             b::after { content: \"/* kept */\"; }\n"
+        );
+    }
+
+    #[test]
+    fn test_unsupported_language() {
+        assert_eq!(strip_comments("markdown", "# Title"), None);
+        assert_eq!(count_comment_lines("markdown", "# Title"), None);
+    }
+
+    #[test]
+    fn test_count_comment_lines() {
+        let code = "// one\nfn f() {} // trailing\n\n/*\n * two\n */\nlet s = \"// no\";\n";
+        assert_eq!(count_comment_lines("rust", code), Some(4));
+    }
+}
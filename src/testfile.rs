@@ -0,0 +1,116 @@
+//! Heuristic classification of test files by path conventions and, for Rust,
+//! inline `#[cfg(test)]` modules.
+
+use std::path::Path;
+
+/// Directory names whose files are tests
+const TEST_DIRS: &[&str] = &["tests", "test", "__tests__"];
+
+/// File name suffixes that mark a test in Rust, Go, Python and JS/TS projects
+const TEST_SUFFIXES: &[&str] = &[
+    "_test.rs",
+    "_tests.rs",
+    "_test.go",
+    "_test.py",
+    ".test.js",
+    ".test.jsx",
+    ".test.ts",
+    ".test.tsx",
+    ".spec.js",
+    ".spec.jsx",
+    ".spec.ts",
+    ".spec.tsx",
+];
+
+/// What a file is to a reviewer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileKind {
+    Source,
+    /// Source with an inline test module, like most Rust files
+    SourceWithTests,
+    /// Lives where tests live or is named like one
+    Test,
+}
+
+impl FileKind {
+    /// Header note for the kind, `None` for plain source
+    pub fn note(self) -> Option<&'static str> {
+        match self {
+            FileKind::Source => None,
+            FileKind::SourceWithTests => Some("contains tests"),
+            FileKind::Test => Some("test"),
+        }
+    }
+}
+
+/// Classify the file at `path` (relative to the repository root, or to the
+/// scanned directory) with `content`. Only the path decides whether it is a
+/// test; the content only tells source files with inline tests apart.
+pub fn classify(path: &Path, content: &str) -> FileKind {
+    let in_test_dir = path.parent().is_some_and(|parent| {
+        parent.components().any(|component| {
+            TEST_DIRS.iter().any(|dir| component.as_os_str() == *dir)
+        })
+    });
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let test_name = TEST_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+        || (name.starts_with("test_") && name.ends_with(".py"));
+    if in_test_dir || test_name {
+        return FileKind::Test;
+    }
+
+    let rust = path.extension().is_some_and(|ext| ext == "rs");
+    if rust && content.contains("#[cfg(test)]") {
+        FileKind::SourceWithTests
+    } else {
+        FileKind::Source
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paths_mark_tests() {
+        for path in [
+            "tests/cli.rs",
+            "crates/core/tests/parse.rs",
+            "web/__tests__/app.jsx",
+            "src/walk_test.rs",
+            "pkg/server_test.go",
+            "app/test_models.py",
+            "app/models_test.py",
+            "src/button.test.tsx",
+            "src/api.spec.ts",
+        ] {
+            assert_eq!(classify(Path::new(path), ""), FileKind::Test, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_sources_and_inline_tests() {
+        for path in ["src/main.rs", "tests.rs", "src/testing.py", "latest/x.ts"] {
+            assert_eq!(classify(Path::new(path), ""), FileKind::Source, "{}", path);
+        }
+
+        let inline = "fn a() {}\n\n#[cfg(test)]\nmod tests {}\n";
+        assert_eq!(
+            classify(Path::new("src/lib.rs"), inline),
+            FileKind::SourceWithTests
+        );
+        // The attribute means nothing outside Rust, and a test stays a test
+        assert_eq!(classify(Path::new("notes.md"), inline), FileKind::Source);
+        assert_eq!(classify(Path::new("tests/a.rs"), inline), FileKind::Test);
+    }
+
+    #[test]
+    fn test_notes() {
+        assert_eq!(FileKind::Source.note(), None);
+        assert_eq!(FileKind::SourceWithTests.note(), Some("contains tests"));
+        assert_eq!(FileKind::Test.note(), Some("test"));
+    }
+}
@@ -0,0 +1,36 @@
+//! Rough token-count estimation for sizing LLM context.
+//!
+//! The heuristic is intentionally simple so it can later be swapped for a
+//! real BPE tokenizer without touching the callers.
+
+/// Average number of bytes per token for typical source code
+const BYTES_PER_TOKEN: usize = 4;
+
+/// Estimate the number of tokens `text` will occupy in a model's context
+pub fn estimate_tokens(text: &str) -> usize {
+    estimate_tokens_from_bytes(text.len())
+}
+
+/// [`estimate_tokens`] for text of which only the length is known, such as
+/// output already streamed away
+pub fn estimate_tokens_from_bytes(bytes: usize) -> usize {
+    bytes.div_ceil(BYTES_PER_TOKEN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_empty() {
+        assert_eq!(estimate_tokens(""), 0);
+    }
+
+    #[test]
+    fn test_estimate_rounds_up() {
+        assert_eq!(estimate_tokens("abcd"), 1);
+        assert_eq!(estimate_tokens("abcde"), 2);
+        assert_eq!(estimate_tokens("fn main() {}"), 3);
+        assert_eq!(estimate_tokens_from_bytes(12), 3);
+    }
+}
@@ -0,0 +1,65 @@
+//! ASCII directory tree of the files included in the output.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Default)]
+struct Node {
+    children: BTreeMap<String, Node>,
+}
+
+/// Render paths as a `tree`-style listing rooted at `.`
+pub fn render_tree<P: AsRef<Path>>(paths: &[P]) -> String {
+    let mut root = Node::default();
+    for path in paths {
+        let mut node = &mut root;
+        for component in path.as_ref().components() {
+            let name = component.as_os_str().to_string_lossy().into_owned();
+            node = node.children.entry(name).or_default();
+        }
+    }
+
+    let mut output = String::from(".\n");
+    render_children(&root, "", &mut output);
+    output
+}
+
+fn render_children(node: &Node, prefix: &str, output: &mut String) {
+    let count = node.children.len();
+    for (index, (name, child)) in node.children.iter().enumerate() {
+        let last = index + 1 == count;
+        output.push_str(prefix);
+        output.push_str(if last { "└── " } else { "├── " });
+        output.push_str(name);
+        output.push('\n');
+
+        let child_prefix =
+            format!("{}{}", prefix, if last { "    " } else { "│   " });
+        render_children(child, &child_prefix, output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_tree() {
+        let paths = ["src/main.rs", "src/walk.rs", "Cargo.toml", "src/bin/amc.rs"];
+        let expected = "\
+.
+├── Cargo.toml
+└── src
+    ├── bin
+    │   └── amc.rs
+    ├── main.rs
+    └── walk.rs
+";
+        assert_eq!(render_tree(&paths), expected);
+    }
+
+    #[test]
+    fn test_render_empty_tree() {
+        assert_eq!(render_tree::<&str>(&[]), ".\n");
+    }
+}
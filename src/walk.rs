@@ -1,15 +1,73 @@
+use crate::git::{self, GitAttributes};
 use anyhow::{Context, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use ignore::WalkBuilder;
-use log::{debug, info};
+use log::{debug, info, warn};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
 use std::path::{Path, PathBuf};
 
-const EXCLUDED_FILES: &[&str] = &[".amc.toml"];
+/// Ignore file for amc-only exclusions, using `.gitignore` syntax
+pub const IGNORE_FILE_NAME: &str = ".amcignore";
+
+/// amc's own files, never part of the output in any directory
+const EXCLUDED_FILES: &[&str] = &[".amc.toml", IGNORE_FILE_NAME];
+
+/// Dependency lockfiles, generated and huge, left out unless
+/// [`FileWalker::with_exclude_lockfiles`] turns that off or an include glob
+/// names them
+pub const LOCKFILES: &[&str] = &[
+    "Cargo.lock",
+    "package-lock.json",
+    "npm-shrinkwrap.json",
+    "yarn.lock",
+    "pnpm-lock.yaml",
+    "bun.lockb",
+    "poetry.lock",
+    "Pipfile.lock",
+    "uv.lock",
+    "pdm.lock",
+    "Gemfile.lock",
+    "composer.lock",
+    "go.sum",
+    "flake.lock",
+    "mix.lock",
+    "pubspec.lock",
+    "Podfile.lock",
+    "packages.lock.json",
+    "gradle.lockfile",
+];
+
+/// How many leading lines are searched for `exclude_if_contains` markers
+pub const MARKER_SCAN_LINES: usize = 10;
 
 pub struct FileWalker {
     extensions: Vec<String>,
+    include_globs: GlobSet,
+    exclude_globs: GlobSet,
+    /// Canonical paths of specific files to leave out, e.g. the config in use
+    excluded_paths: Vec<PathBuf>,
+    /// Exact paths, relative to the scan root, to leave out
+    excluded_files: Vec<PathBuf>,
+    git_attributes: bool,
+    /// Honor `.gitignore` files and `.git/info/exclude`
+    git_ignore: bool,
+    /// Honor the user's global Git excludes file
+    global_git_ignore: bool,
+    follow_symlinks: bool,
+    /// Descend into dotfiles and dot-directories (`.git` never)
+    include_hidden: bool,
+    /// Leave out `LOCKFILES`
+    exclude_lockfiles: bool,
+    /// Descend into the work trees of Git submodules
+    include_submodules: bool,
+    /// Directory relative paths are shown against, instead of the scan root
+    path_base: Option<PathBuf>,
+    max_depth: Option<usize>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FileEntry {
     pub absolute_path: PathBuf,
     pub relative_path: PathBuf,
@@ -22,9 +80,120 @@ impl FileWalker {
                 .into_iter()
                 .map(|ext| ext.trim_start_matches('.').to_string())
                 .collect(),
+            include_globs: GlobSet::empty(),
+            exclude_globs: GlobSet::empty(),
+            excluded_paths: Vec::new(),
+            excluded_files: Vec::new(),
+            git_attributes: true,
+            git_ignore: true,
+            global_git_ignore: true,
+            follow_symlinks: false,
+            include_hidden: false,
+            exclude_lockfiles: true,
+            include_submodules: false,
+            path_base: None,
+            max_depth: None,
         }
     }
 
+    /// Leave out the dependency lockfiles in [`LOCKFILES`]. On by default;
+    /// `include_globs` naming a lockfile still bring it back.
+    pub fn with_exclude_lockfiles(mut self, enabled: bool) -> Self {
+        self.exclude_lockfiles = enabled;
+        self
+    }
+
+    /// Descend into symlinked directories and include symlinked files.
+    /// Off by default; loops are detected and files reached through several
+    /// links are returned once by [`FileWalker::walk_many`].
+    pub fn with_follow_symlinks(mut self, enabled: bool) -> Self {
+        self.follow_symlinks = enabled;
+        self
+    }
+
+    /// Only descend this many levels below the scanned directory; `1` keeps
+    /// just the files directly inside it
+    pub fn with_max_depth(mut self, depth: Option<usize>) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Show relative paths against `base` (such as the repository root)
+    /// rather than against each scanned directory. Globs and excluded files
+    /// still match paths relative to the scanned directory.
+    pub fn with_path_base<P: Into<PathBuf>>(mut self, base: P) -> Self {
+        self.path_base = Some(base.into());
+        self
+    }
+
+    /// Whether Git ignore rules apply: `local` covers `.gitignore` files and
+    /// `.git/info/exclude`, `global` the user's `core.excludesFile`. Both are
+    /// on by default; `.amcignore` applies either way.
+    pub fn with_gitignore(mut self, local: bool, global: bool) -> Self {
+        self.git_ignore = local;
+        self.global_git_ignore = global;
+        self
+    }
+
+    /// Include hidden files and directories such as `.env` or `.github/`.
+    /// Off by default so dotfiles holding secrets never reach the output;
+    /// `.gitignore` and `.amcignore` are read either way, and `.git` is
+    /// always skipped.
+    pub fn with_include_hidden(mut self, enabled: bool) -> Self {
+        self.include_hidden = enabled;
+        self
+    }
+
+    /// Descend into the work trees of Git submodules. Off by default, as
+    /// their files have no history in the enclosing repository; pair it with
+    /// [`GitHistory::with_submodules`](crate::git::GitHistory::with_submodules).
+    pub fn with_include_submodules(mut self, enabled: bool) -> Self {
+        self.include_submodules = enabled;
+        self
+    }
+
+    /// Whether files flagged `linguist-generated` or `export-ignore` in
+    /// `.gitattributes` are skipped (the default)
+    pub fn with_git_attributes(mut self, enabled: bool) -> Self {
+        self.git_attributes = enabled;
+        self
+    }
+
+    /// Add glob patterns that include files regardless of extension, and
+    /// patterns that exclude files. Exclusion always wins over inclusion.
+    pub fn with_globs(
+        mut self,
+        include: &[String],
+        exclude: &[String],
+    ) -> Result<Self> {
+        self.include_globs = build_glob_set(include)?;
+        self.exclude_globs = build_glob_set(exclude)?;
+        Ok(self)
+    }
+
+    /// Leave out these files, however they are reached; used for a config
+    /// file not named `.amc.toml`. Files that don't exist are ignored.
+    pub fn with_excluded_paths(mut self, paths: &[PathBuf]) -> Self {
+        self.excluded_paths = paths
+            .iter()
+            .filter_map(|path| path.canonicalize().ok())
+            .collect();
+        self
+    }
+
+    /// Leave out these exact paths, given relative to the scanned directory
+    /// (a leading `./` is ignored)
+    pub fn with_excluded_files(mut self, files: &[String]) -> Self {
+        self.excluded_files = files
+            .iter()
+            .map(|file| {
+                let path = Path::new(file);
+                path.strip_prefix(".").unwrap_or(path).to_path_buf()
+            })
+            .collect();
+        self
+    }
+
     pub fn walk<P: AsRef<Path>>(&self, dir: P) -> Result<Vec<FileEntry>> {
         let base_path = if dir.as_ref() == Path::new(".") {
             std::env::current_dir()?
@@ -34,42 +203,86 @@ impl FileWalker {
                 .context("Failed to resolve directory path")?
         };
 
+        let path_base = match &self.path_base {
+            Some(base) => Some(base.canonicalize().with_context(|| {
+                format!("Failed to resolve path base: {}", base.display())
+            })?),
+            None => None,
+        };
+
+        // A single file is annotated as is, named relative to its directory,
+        // whatever the extension and ignore rules say
+        if base_path.is_file() {
+            info!("Annotating single file: {}", base_path.display());
+            let relative_path = match (path_base.as_deref(), base_path.parent()) {
+                (Some(base), _) | (None, Some(base)) => {
+                    relative_to(&base_path, base)
+                }
+                (None, None) => base_path.clone(),
+            };
+            return Ok(vec![FileEntry {
+                absolute_path: base_path,
+                relative_path,
+            }]);
+        }
+
         info!("Starting file walk in directory: {}", base_path.display());
         info!("Looking for files with extensions: {:?}", self.extensions);
 
+        let submodules = if self.include_submodules {
+            Vec::new()
+        } else {
+            git::submodule_workdirs(&base_path)
+        };
+
         // Create the builder
         let mut builder = WalkBuilder::new(&base_path);
         builder
-            .hidden(false)
-            .git_ignore(true)
-            .git_global(true)
-            .git_exclude(true)
+            .hidden(!self.include_hidden)
+            .filter_entry(move |entry| {
+                let submodule = submodules.iter().any(|dir| entry.path() == dir);
+                if submodule {
+                    debug!("Skipping submodule: {}", entry.path().display());
+                }
+                entry.file_name() != ".git" && !submodule
+            })
+            .git_ignore(self.git_ignore)
+            .git_global(self.global_git_ignore)
+            .git_exclude(self.git_ignore)
             .require_git(false)
-            .ignore(true);
+            .ignore(true)
+            .follow_links(self.follow_symlinks)
+            .max_depth(self.max_depth)
+            // Highest precedence, so `!pattern` can re-include gitignored files
+            .add_custom_ignore_filename(IGNORE_FILE_NAME);
 
         // Add the gitignore file if it exists
         let gitignore_path = base_path.join(".gitignore");
-        if gitignore_path.exists() {
+        if self.git_ignore && gitignore_path.exists() {
             info!("Found .gitignore at: {}", gitignore_path.display());
             if let Some(err) = builder.add_ignore(&gitignore_path) {
-                eprintln!("Warning: Failed to add .gitignore file: {}", err);
+                warn!("Failed to add .gitignore file: {}", err);
             }
         }
 
+        let attributes = if self.git_attributes {
+            GitAttributes::open(&base_path)
+        } else {
+            None
+        };
+
         // Build the walker and collect files
         let files: Vec<FileEntry> = builder
             .build()
-            .filter_map(|entry| entry.ok())
-            .filter(|entry| entry.file_type().is_some_and(|ft| ft.is_file()))
-            .filter(|entry| {
-                let is_valid = self.is_valid_extension(entry.path());
-                debug!(
-                    "Checking file: {} - {}",
-                    entry.path().display(),
-                    if is_valid { "included" } else { "skipped" }
-                );
-                is_valid
+            .filter_map(|entry| match entry {
+                Ok(entry) => Some(entry),
+                // Includes symlink loops when following links
+                Err(err) => {
+                    warn!("Skipping entry: {}", err);
+                    None
+                }
             })
+            .filter(|entry| entry.file_type().is_some_and(|ft| ft.is_file()))
             .map(|entry| {
                 let absolute_path = entry.path().to_path_buf();
                 let relative_path = absolute_path
@@ -81,17 +294,111 @@ impl FileWalker {
                     relative_path,
                 }
             })
+            .filter(|file| {
+                let is_valid = self.is_included(&file.relative_path)
+                    && !self.is_excluded_path(&file.absolute_path);
+                debug!(
+                    "Checking file: {} - {}",
+                    file.absolute_path.display(),
+                    if is_valid { "included" } else { "skipped" }
+                );
+                is_valid
+            })
+            .filter(|file| {
+                let attribute = attributes.as_ref().and_then(|attrs| {
+                    attrs.excluding_attribute(&file.relative_path)
+                });
+                if let Some(attribute) = attribute {
+                    debug!(
+                        "Skipping {}: marked {} in .gitattributes",
+                        file.relative_path.display(),
+                        attribute
+                    );
+                }
+                attribute.is_none()
+            })
+            .map(|mut file| {
+                if let Some(base) = &path_base {
+                    file.relative_path = relative_to(&file.absolute_path, base);
+                }
+                file
+            })
             .collect();
 
         Ok(files)
     }
 
-    fn is_valid_extension(&self, path: &Path) -> bool {
-        if let Some(file_name) = path.file_name().and_then(|n| n.to_str()) {
-            if EXCLUDED_FILES.contains(&file_name) {
-                return false;
+    /// Walk several directories, concatenating their files. With more than one
+    /// root each relative path is prefixed by the directory it came from, and a
+    /// file reachable through two roots is only returned once.
+    pub fn walk_many<P: AsRef<Path>>(&self, dirs: &[P]) -> Result<Vec<FileEntry>> {
+        let mut seen = HashSet::new();
+        let mut files = Vec::new();
+
+        for dir in dirs {
+            for mut file in self.walk(dir)? {
+                let key = file
+                    .absolute_path
+                    .canonicalize()
+                    .unwrap_or_else(|_| file.absolute_path.clone());
+                if !seen.insert(key) {
+                    debug!("Skipping duplicate: {}", file.absolute_path.display());
+                    continue;
+                }
+                if dirs.len() > 1 && self.path_base.is_none() {
+                    file.relative_path = if dir.as_ref().is_file() {
+                        dir.as_ref().to_path_buf()
+                    } else {
+                        dir.as_ref().join(&file.relative_path)
+                    };
+                }
+                files.push(file);
             }
         }
+
+        Ok(files)
+    }
+
+    /// Whether `absolute_path` is one of the `excluded_paths`; only files with
+    /// a matching name are canonicalized
+    fn is_excluded_path(&self, absolute_path: &Path) -> bool {
+        let file_name = absolute_path.file_name();
+        self.excluded_paths
+            .iter()
+            .filter(|excluded| excluded.file_name() == file_name)
+            .any(|excluded| {
+                absolute_path
+                    .canonicalize()
+                    .is_ok_and(|path| path == *excluded)
+            })
+    }
+
+    /// Decide whether a file, given by its path relative to the scan root,
+    /// belongs in the output
+    fn is_included(&self, relative_path: &Path) -> bool {
+        let file_name = relative_path.file_name().unwrap_or_default();
+        if EXCLUDED_FILES.iter().any(|name| file_name == *name) {
+            return false;
+        }
+        if self.excluded_files.iter().any(|file| file == relative_path) {
+            return false;
+        }
+
+        let matches = |set: &GlobSet| {
+            set.is_match(relative_path) || set.is_match(Path::new(file_name))
+        };
+        if matches(&self.exclude_globs) {
+            return false;
+        }
+        if self.exclude_lockfiles && LOCKFILES.iter().any(|name| file_name == *name)
+        {
+            return matches(&self.include_globs);
+        }
+
+        self.is_valid_extension(relative_path) || matches(&self.include_globs)
+    }
+
+    fn is_valid_extension(&self, path: &Path) -> bool {
         path.extension()
             .and_then(|ext| ext.to_str())
             .map(|ext| self.extensions.iter().any(|allowed_ext| allowed_ext == ext))
@@ -99,6 +406,96 @@ impl FileWalker {
     }
 }
 
+/// `path` relative to `base`, stepping out with `..` when it lies outside.
+/// Both must be absolute; without any common prefix (another drive on
+/// Windows) `path` is returned unchanged.
+pub fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    let path_components: Vec<_> = path.components().collect();
+    let base_components: Vec<_> = base.components().collect();
+    let common = path_components
+        .iter()
+        .zip(&base_components)
+        .take_while(|(a, b)| a == b)
+        .count();
+    if common == 0 {
+        return path.to_path_buf();
+    }
+
+    let mut relative = PathBuf::new();
+    for _ in common..base_components.len() {
+        relative.push("..");
+    }
+    relative.extend(&path_components[common..]);
+    relative
+}
+
+/// Build entries for an explicit newline-separated file list instead of
+/// walking. Relative entries are resolved against the current directory and
+/// shown relative to `base` when they lie under it; missing files (such as
+/// deletions listed by `git diff --name-only`) are skipped with a warning.
+pub fn entries_from_list(list: &str, base: &Path) -> Result<Vec<FileEntry>> {
+    let base = base
+        .canonicalize()
+        .context("Failed to resolve directory path")?;
+    let mut seen = HashSet::new();
+    let mut files = Vec::new();
+
+    for line in list.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let Ok(absolute_path) = Path::new(line).canonicalize() else {
+            warn!("Skipping missing file from list: {}", line);
+            continue;
+        };
+        if !absolute_path.is_file() || !seen.insert(absolute_path.clone()) {
+            continue;
+        }
+        let relative_path = absolute_path
+            .strip_prefix(&base)
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|_| PathBuf::from(line));
+        files.push(FileEntry {
+            absolute_path,
+            relative_path,
+        });
+    }
+
+    Ok(files)
+}
+
+/// Return the first marker found in the leading `MARKER_SCAN_LINES` lines of
+/// the file, reading only that far so huge files aren't loaded
+pub fn find_marker<'a>(
+    path: &Path,
+    markers: &'a [String],
+) -> io::Result<Option<&'a str>> {
+    if markers.is_empty() {
+        return Ok(None);
+    }
+
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut line = Vec::new();
+    for _ in 0..MARKER_SCAN_LINES {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        let text = String::from_utf8_lossy(&line);
+        if let Some(marker) = markers.iter().find(|m| text.contains(m.as_str())) {
+            return Ok(Some(marker));
+        }
+    }
+    Ok(None)
+}
+
+pub(crate) fn build_glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern)
+            .with_context(|| format!("Invalid glob pattern: {}", pattern))?;
+        builder.add(glob);
+    }
+    Ok(builder.build()?)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -132,6 +529,41 @@ mod tests {
         Ok(temp_dir)
     }
 
+    #[test]
+    fn test_hidden_files_need_opting_in() -> Result<()> {
+        let temp_dir = setup_test_directory()?;
+        fs::write(temp_dir.path().join(".env.rs"), "SECRET")?;
+        fs::create_dir_all(temp_dir.path().join(".github"))?;
+        fs::write(temp_dir.path().join(".github/build.rs"), "ci")?;
+        fs::create_dir_all(temp_dir.path().join(".git"))?;
+        fs::write(temp_dir.path().join(".git/hook.rs"), "git internals")?;
+        // Ignore files in hidden directories still apply once they are walked
+        fs::write(temp_dir.path().join(".github/.gitignore"), "ignored.rs\n")?;
+        fs::write(temp_dir.path().join(".github/ignored.rs"), "ignored")?;
+
+        let walker = FileWalker::new(vec!["rs".to_string()]);
+        assert_eq!(walker.walk(temp_dir.path())?.len(), 3);
+
+        let mut paths: Vec<String> = walker
+            .with_include_hidden(true)
+            .walk(temp_dir.path())?
+            .iter()
+            .map(|f| f.relative_path.to_string_lossy().replace('\\', "/"))
+            .collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            [
+                ".env.rs",
+                ".github/build.rs",
+                "subdir/test4.rs",
+                "test1.rs",
+                "test2.rs"
+            ]
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_walk_with_extensions() -> Result<()> {
         let temp_dir = setup_test_directory()?;
@@ -181,6 +613,22 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_gitignore_can_be_disabled() -> Result<()> {
+        let temp_dir = setup_test_directory()?;
+        fs::create_dir_all(temp_dir.path().join("target"))?;
+        fs::write(temp_dir.path().join("target/ignored.rs"), "ignored content")?;
+
+        let walker =
+            FileWalker::new(vec!["rs".to_string()]).with_gitignore(false, false);
+        let files = walker.walk(temp_dir.path())?;
+
+        assert!(files
+            .iter()
+            .any(|file| file.relative_path == Path::new("target/ignored.rs")));
+        Ok(())
+    }
+
     #[test]
     fn test_relative_paths() -> Result<()> {
         let temp_dir = setup_test_directory()?;
@@ -205,6 +653,323 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_include_and_exclude_globs() -> Result<()> {
+        let temp_dir = setup_test_directory()?;
+        fs::write(temp_dir.path().join("Dockerfile"), "FROM rust")?;
+        fs::write(temp_dir.path().join("walk_test.rs"), "test")?;
+        fs::create_dir_all(temp_dir.path().join("src/generated"))?;
+        fs::write(temp_dir.path().join("src/generated/api.rs"), "gen")?;
+
+        let walker = FileWalker::new(vec!["rs".to_string()]).with_globs(
+            &["Dockerfile".to_string()],
+            &["*_test.rs".to_string(), "**/generated/**".to_string()],
+        )?;
+        let mut paths: Vec<String> = walker
+            .walk(temp_dir.path())?
+            .into_iter()
+            .map(|f| f.relative_path.to_string_lossy().replace('\\', "/"))
+            .collect();
+        paths.sort();
+
+        assert_eq!(
+            paths,
+            vec!["Dockerfile", "subdir/test4.rs", "test1.rs", "test2.rs"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_excluded_files_match_exact_paths() -> Result<()> {
+        let temp_dir = setup_test_directory()?;
+
+        let walker =
+            FileWalker::new(vec!["rs".to_string()]).with_excluded_files(&[
+                "./test1.rs".to_string(),
+                "test4.rs".to_string(),
+            ]);
+        let mut paths: Vec<String> = walker
+            .walk(temp_dir.path())?
+            .into_iter()
+            .map(|f| f.relative_path.to_string_lossy().replace('\\', "/"))
+            .collect();
+        paths.sort();
+
+        // `test4.rs` names no file at the root, so `subdir/test4.rs` stays
+        assert_eq!(paths, vec!["subdir/test4.rs", "test2.rs"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_depth_excludes_nested_files() -> Result<()> {
+        let temp_dir = setup_test_directory()?;
+
+        let walker =
+            FileWalker::new(vec!["rs".to_string()]).with_max_depth(Some(1));
+        let mut paths: Vec<PathBuf> = walker
+            .walk(temp_dir.path())?
+            .into_iter()
+            .map(|f| f.relative_path)
+            .collect();
+        paths.sort();
+
+        assert_eq!(
+            paths,
+            vec![PathBuf::from("test1.rs"), PathBuf::from("test2.rs")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_path_base_for_relative_paths() -> Result<()> {
+        let temp_dir = setup_test_directory()?;
+
+        let walker = FileWalker::new(vec!["rs".to_string()])
+            .with_globs(&[], &["test4.rs".to_string()])?
+            .with_path_base(temp_dir.path());
+        let files = walker.walk(temp_dir.path().join("subdir"))?;
+        assert!(
+            files.is_empty(),
+            "globs match against the scanned directory"
+        );
+
+        let walker =
+            FileWalker::new(vec!["rs".to_string()]).with_path_base(temp_dir.path());
+        let files = walker.walk_many(&[
+            temp_dir.path().join("subdir"),
+            temp_dir.path().to_path_buf(),
+        ])?;
+        let paths: Vec<String> = files
+            .iter()
+            .map(|f| f.relative_path.to_string_lossy().replace('\\', "/"))
+            .collect();
+        assert_eq!(paths[0], "subdir/test4.rs");
+        assert_eq!(paths.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_relative_to() {
+        let relative = |path: &str, base: &str| {
+            relative_to(Path::new(path), Path::new(base))
+                .to_string_lossy()
+                .replace('\\', "/")
+        };
+        assert_eq!(relative("/repo/src/main.rs", "/repo"), "src/main.rs");
+        assert_eq!(
+            relative("/repo/src/main.rs", "/repo/tests"),
+            "../src/main.rs"
+        );
+        assert_eq!(relative("/repo/a.rs", "/repo/x/y"), "../../a.rs");
+    }
+
+    #[test]
+    fn test_walk_many_prefixes_and_dedupes() -> Result<()> {
+        let temp_dir = setup_test_directory()?;
+        let root = temp_dir.path();
+        let walker = FileWalker::new(vec!["rs".to_string()]);
+
+        let files = walker.walk_many(&[root.to_path_buf(), root.join("subdir")])?;
+
+        // subdir/test4.rs is reached through both roots but listed once
+        assert_eq!(files.len(), 3);
+        for file in &files {
+            assert!(file.relative_path.starts_with(root));
+        }
+
+        let single = walker.walk_many(&[root.join("subdir")])?;
+        assert_eq!(single.len(), 1);
+        assert_eq!(single[0].relative_path, Path::new("test4.rs"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lockfiles_are_left_out_by_default() -> Result<()> {
+        let temp_dir = setup_test_directory()?;
+        let root = temp_dir.path();
+        fs::write(root.join("Cargo.lock"), "# generated")?;
+        fs::write(root.join("package-lock.json"), "{}")?;
+        fs::write(root.join("package.json"), "{}")?;
+        fs::write(root.join("subdir/yarn.lock"), "# generated")?;
+
+        let names = |walker: FileWalker| -> Result<Vec<String>> {
+            let mut names: Vec<String> = walker
+                .walk(root)?
+                .iter()
+                .map(|f| f.relative_path.to_string_lossy().replace('\\', "/"))
+                .filter(|name| !name.ends_with(".rs"))
+                .collect();
+            names.sort();
+            Ok(names)
+        };
+        let extensions = vec!["json".to_string(), "lock".to_string()];
+
+        assert_eq!(
+            names(FileWalker::new(extensions.clone()))?,
+            ["package.json"]
+        );
+        assert_eq!(
+            names(
+                FileWalker::new(extensions.clone()).with_exclude_lockfiles(false)
+            )?,
+            [
+                "Cargo.lock",
+                "package-lock.json",
+                "package.json",
+                "subdir/yarn.lock"
+            ]
+        );
+        // Asked for by name, a lockfile is included after all
+        let walker = FileWalker::new(extensions)
+            .with_globs(&["Cargo.lock".to_string()], &[])?;
+        assert_eq!(names(walker)?, ["Cargo.lock", "package.json"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_single_file_dir() -> Result<()> {
+        let temp_dir = setup_test_directory()?;
+        let root = temp_dir.path();
+        fs::write(root.join(".gitignore"), "notes.txt\n")?;
+        fs::write(root.join("notes.txt"), "todo")?;
+        let walker = FileWalker::new(vec!["rs".to_string()]);
+
+        let files = walker.walk(root.join("subdir/test4.rs"))?;
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].relative_path, Path::new("test4.rs"));
+        assert_eq!(
+            files[0].absolute_path,
+            root.join("subdir/test4.rs").canonicalize()?
+        );
+
+        // Named explicitly, so neither the extension nor .gitignore applies
+        let files = walker.walk(root.join("notes.txt"))?;
+        assert_eq!(files[0].relative_path, Path::new("notes.txt"));
+
+        // Next to a directory, a file keeps the path it was given
+        let dirs = [root.join("subdir"), root.join("test1.rs")];
+        let files = walker.walk_many(&dirs)?;
+        assert_eq!(files[1].relative_path, root.join("test1.rs"));
+
+        let files = walker
+            .with_path_base(root)
+            .walk(root.join("subdir/test4.rs"))?;
+        assert_eq!(files[0].relative_path, Path::new("subdir/test4.rs"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_skips_files_flagged_in_gitattributes() -> Result<()> {
+        let temp_dir = setup_test_directory()?;
+        git2::Repository::init(temp_dir.path())?;
+        fs::write(
+            temp_dir.path().join(".gitattributes"),
+            "test2.rs linguist-generated\nsubdir/** export-ignore\n",
+        )?;
+
+        let walker = FileWalker::new(vec!["rs".to_string()]);
+        let files = walker.walk(temp_dir.path())?;
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].relative_path, Path::new("test1.rs"));
+
+        let files = walker.with_git_attributes(false).walk(temp_dir.path())?;
+        assert_eq!(files.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_amcignore() -> Result<()> {
+        let temp_dir = setup_test_directory()?;
+        fs::write(temp_dir.path().join(".amcignore"), "test2.rs\n!test3.txt\n")?;
+        fs::write(temp_dir.path().join("subdir/.amcignore"), "*.rs\n")?;
+
+        let walker = FileWalker::new(vec!["rs".to_string(), "txt".to_string()]);
+        let mut paths: Vec<_> = walker
+            .walk(temp_dir.path())?
+            .into_iter()
+            .map(|f| f.relative_path)
+            .collect();
+        paths.sort();
+
+        // The negated pattern brings back a file .gitignore excludes
+        assert_eq!(paths, vec![Path::new("test1.rs"), Path::new("test3.txt")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_entries_from_list() -> Result<()> {
+        let temp_dir = setup_test_directory()?;
+        let root = temp_dir.path();
+        let list = format!(
+            "{}\n\n{}\n{}\n{}\n",
+            root.join("subdir/test4.rs").display(),
+            root.join("test3.txt").display(),
+            root.join("deleted.rs").display(),
+            root.join("test3.txt").display(),
+        );
+
+        let files = entries_from_list(&list, root)?;
+        let paths: Vec<&Path> =
+            files.iter().map(|f| f.relative_path.as_path()).collect();
+
+        // Ignore rules and extensions don't apply to an explicit list
+        assert_eq!(
+            paths,
+            vec![Path::new("subdir/test4.rs"), Path::new("test3.txt")]
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_follow_symlinks_without_loops_or_duplicates() -> Result<()> {
+        use std::os::unix::fs::symlink;
+
+        let temp_dir = setup_test_directory()?;
+        let root = temp_dir.path();
+        symlink(root.join("test1.rs"), root.join("alias.rs"))?;
+        // Points back at an ancestor, which would recurse forever
+        symlink(root, root.join("subdir/loop"))?;
+
+        let walker = FileWalker::new(vec!["rs".to_string()]);
+        assert_eq!(walker.walk_many(&[root])?.len(), 3);
+
+        let files = walker.with_follow_symlinks(true).walk_many(&[root])?;
+        let mut paths: Vec<_> =
+            files.iter().map(|f| f.relative_path.clone()).collect();
+        paths.sort();
+        // alias.rs and test1.rs are one file, found under whichever name the
+        // walk reached first
+        assert_eq!(paths.len(), 3);
+        assert!(paths.contains(&PathBuf::from("subdir/test4.rs")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_marker_in_file_head() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let markers = vec!["@generated".to_string(), "DO NOT EDIT".to_string()];
+
+        let generated = temp_dir.path().join("api.rs");
+        fs::write(
+            &generated,
+            "// Code generated by protoc. DO NOT EDIT.\nfn f() {}",
+        )?;
+        assert_eq!(find_marker(&generated, &markers)?, Some("DO NOT EDIT"));
+
+        // Markers past the scanned head don't count
+        let late = temp_dir.path().join("late.rs");
+        let body = "fn f() {}\n".repeat(MARKER_SCAN_LINES);
+        fs::write(&late, format!("{}// @generated\n", body))?;
+        assert_eq!(find_marker(&late, &markers)?, None);
+
+        assert_eq!(find_marker(&late, &[])?, None);
+        Ok(())
+    }
+
     #[test]
     fn test_exclude_config_file() -> Result<()> {
         let temp_dir = setup_test_directory()?;
@@ -222,4 +987,30 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_exclude_custom_config_path() -> Result<()> {
+        let temp_dir = setup_test_directory()?;
+        fs::create_dir_all(temp_dir.path().join("ci"))?;
+        fs::write(temp_dir.path().join("ci/settings.toml"), "content")?;
+        fs::write(temp_dir.path().join("settings.toml"), "content")?;
+        fs::write(temp_dir.path().join("Cargo.toml"), "content")?;
+
+        // Given relative to the current directory, as `-c` would be
+        let config = temp_dir.path().join("subdir/../ci/settings.toml");
+        let walker = FileWalker::new(vec!["toml".to_string()])
+            .with_excluded_paths(&[config]);
+        let paths: Vec<PathBuf> = walker
+            .walk(temp_dir.path())?
+            .into_iter()
+            .map(|f| f.relative_path)
+            .collect();
+
+        // Only the config itself is left out, not a same-named file elsewhere
+        assert_eq!(
+            paths,
+            vec![PathBuf::from("Cargo.toml"), PathBuf::from("settings.toml")]
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,497 @@
+//! End-to-end tests running the compiled `amc` binary against temporary
+//! Git repositories
+
+use assert_cmd::Command;
+use git2::{Commit, Oid, Repository, Signature, Time};
+use predicates::prelude::*;
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+fn commit_files(
+    repo: &Repository,
+    files: &[(&str, &str)],
+    message: &str,
+    time: i64,
+) -> Oid {
+    let workdir = repo.workdir().unwrap();
+    let mut index = repo.index().unwrap();
+    for (path, content) in files {
+        let full_path = workdir.join(path);
+        fs::create_dir_all(full_path.parent().unwrap()).unwrap();
+        fs::write(full_path, content).unwrap();
+        index.add_path(Path::new(path)).unwrap();
+    }
+    index.write().unwrap();
+
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let signature =
+        Signature::new("Test User", "test@example.com", &Time::new(time, 0))
+            .unwrap();
+    let parent = repo.head().ok().and_then(|h| h.peel_to_commit().ok());
+    let parents: Vec<&Commit> = parent.iter().collect();
+    repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        message,
+        &tree,
+        &parents,
+    )
+    .unwrap()
+}
+
+/// A repository where `a.rs` and `b.rs` were last changed by different commits
+fn setup_repo() -> (TempDir, Oid, Oid) {
+    let temp_dir = TempDir::new().unwrap();
+    let repo = Repository::init(temp_dir.path()).unwrap();
+    let first = commit_files(
+        &repo,
+        &[("a.rs", "fn a() {}\n"), ("src/b.rs", "fn b() {}\n")],
+        "Add a and b",
+        1_700_000_000,
+    );
+    let second = commit_files(
+        &repo,
+        &[("src/b.rs", "fn b2() {}\n")],
+        "Change b",
+        1_700_000_100,
+    );
+    (temp_dir, first, second)
+}
+
+fn amc(dir: &Path) -> Command {
+    let mut cmd = Command::cargo_bin("amc").unwrap();
+    cmd.current_dir(dir).arg("--dir").arg(dir);
+    cmd
+}
+
+#[test]
+fn writes_headers_with_per_file_commits() {
+    let (repo, first, second) = setup_repo();
+    let output = repo.path().join("out.txt");
+
+    amc(repo.path())
+        .args(["--no-prompt", "--output"])
+        .arg(&output)
+        .assert()
+        .success();
+
+    let text = fs::read_to_string(&output).unwrap();
+    assert_eq!(
+        text,
+        format!(
+            "---\nFile: a.rs\nLast commit: {first}\nLast update: 2023-11-14T22:13:20+00:00\n\
+             Author: Test User <test@example.com>\nMessage: Add a and b\n---\nfn a() {{}}\n\n\n\
+             ---\nFile: src/b.rs\nLast commit: {second}\nLast update: 2023-11-14T22:15:00+00:00\n\
+             Author: Test User <test@example.com>\nMessage: Change b\n---\nfn b2() {{}}\n\n\n\
+             ---\nManifest: 2 file(s)\n---\na.rs      10 B  {}\nsrc/b.rs  11 B  {}\n\n",
+            &first.to_string()[..7],
+            &second.to_string()[..7]
+        )
+    );
+}
+
+#[test]
+fn starts_with_the_prompt_and_marks_untracked_files() {
+    let (repo, _, _) = setup_repo();
+    fs::write(repo.path().join("new.rs"), "fn new() {}\n").unwrap();
+
+    amc(repo.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "This is a concatenated source code file",
+        ))
+        .stdout(predicate::str::contains(
+            "File: new.rs\nLast commit: untracked\n",
+        ));
+
+    amc(repo.path())
+        .arg("--skip-untracked")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("File: new.rs").not());
+}
+
+#[test]
+fn uses_the_discovered_config() {
+    let (repo, _, _) = setup_repo();
+    fs::write(repo.path().join("notes.md"), "# Notes\n").unwrap();
+    fs::write(
+        repo.path().join(".amc.toml"),
+        "delimiter = \"===\"\nextensions = [\"md\"]\nllm_prompt = \"Read this.\"\n",
+    )
+    .unwrap();
+
+    amc(repo.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "Read this.\n===\nFile: notes.md\nLast commit: untracked\n",
+        ))
+        .stdout(predicate::str::contains("File: a.rs").not());
+}
+
+#[test]
+fn config_falls_back_to_the_environment() {
+    let (repo, _, _) = setup_repo();
+    fs::write(
+        repo.path().join(".amc.toml"),
+        "delimiter = \"===\"\nextensions = [\"rs\"]\n",
+    )
+    .unwrap();
+    let env_config = repo.path().join("env.toml");
+    fs::write(&env_config, "delimiter = \"+++\"\nextensions = [\"rs\"]\n").unwrap();
+
+    amc(repo.path())
+        .arg("--no-prompt")
+        .env("AMC_CONFIG", &env_config)
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("+++\nFile: a.rs\n"));
+
+    amc(repo.path())
+        .args(["--no-prompt", "--config", ".amc.toml"])
+        .env("AMC_CONFIG", &env_config)
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("===\nFile: a.rs\n"));
+}
+
+#[test]
+fn json_format_is_valid() {
+    let (repo, _, second) = setup_repo();
+
+    let assert = amc(repo.path())
+        .args(["--format", "json"])
+        .assert()
+        .success();
+    let value: serde_json::Value =
+        serde_json::from_slice(&assert.get_output().stdout).unwrap();
+
+    assert_eq!(value["files"].as_array().unwrap().len(), 2);
+    assert_eq!(value["files"][1]["relative_path"], "src/b.rs");
+    assert_eq!(value["files"][1]["commit_hash"], second.to_string());
+}
+
+#[test]
+fn requires_a_git_repository_unless_no_git() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("a.rs"), "fn a() {}\n").unwrap();
+
+    amc(temp_dir.path())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("is not a Git repository"));
+
+    amc(temp_dir.path())
+        .args(["--no-git", "--no-prompt"])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("File: a.rs\nLast commit: no git\n")
+                .and(predicate::str::contains("(filesystem mtime)\n"))
+                .and(predicate::str::contains("fn a() {}\n")),
+        );
+}
+
+#[test]
+fn group_by_dir_writes_directory_headings() {
+    let (repo, _, _) = setup_repo();
+
+    let assert = amc(repo.path())
+        .args(["--no-prompt", "--no-manifest", "--group-by-dir"])
+        .assert()
+        .success();
+    let text = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    assert!(text.starts_with("### directory: .\n\n---\nFile: a.rs\n"));
+    assert!(text.contains("\n### directory: src\n\n---\nFile: src/b.rs\n"));
+}
+
+#[test]
+fn output_dir_splits_by_top_level_directory() {
+    let (repo, _, _) = setup_repo();
+    let out_dir = repo.path().join("context");
+
+    amc(repo.path())
+        .arg("--output-dir")
+        .arg(&out_dir)
+        .assert()
+        .success();
+
+    let root = fs::read_to_string(out_dir.join("_root.txt")).unwrap();
+    let src = fs::read_to_string(out_dir.join("src.txt")).unwrap();
+    assert!(root.contains("File: a.rs\n") && !root.contains("File: src/b.rs"));
+    assert!(src.contains("File: src/b.rs\n") && !src.contains("File: a.rs"));
+}
+
+#[test]
+fn split_tokens_writes_numbered_parts() {
+    let (repo, _, _) = setup_repo();
+
+    amc(repo.path())
+        .args(["--no-prompt", "--split-tokens", "8", "--output"])
+        .arg(repo.path().join("out.txt"))
+        .assert()
+        .success();
+
+    let part = |n: u32| {
+        fs::read_to_string(repo.path().join(format!("out.part0{n}.txt"))).unwrap()
+    };
+    assert!(part(1).starts_with("Part 1 of 2\n---\nFile: a.rs\n"));
+    assert!(part(2).starts_with("Part 2 of 2\n---\nFile: src/b.rs\n"));
+    assert!(!repo.path().join("out.part03.txt").exists());
+}
+
+#[test]
+fn repo_root_keeps_paths_relative_to_the_repository() {
+    let (repo, _, _) = setup_repo();
+    let run = |args: &[&str]| {
+        let output = Command::cargo_bin("amc")
+            .unwrap()
+            .current_dir(repo.path())
+            .args(["--dir", "src", "--no-prompt"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    assert!(run(&[]).contains("File: b.rs\n"));
+    assert!(run(&["--repo-root"]).contains("File: src/b.rs\n"));
+    assert!(run(&["--paths", "repo"]).contains("File: src/b.rs\n"));
+    assert!(run(&["--paths", "cwd"]).contains("File: src/b.rs\n"));
+
+    let output = Command::cargo_bin("amc")
+        .unwrap()
+        .current_dir(repo.path().join("src"))
+        .args(["--dir", "..", "--no-prompt", "--paths", "cwd"])
+        .output()
+        .unwrap();
+    let text = String::from_utf8(output.stdout).unwrap();
+    assert!(text.contains("File: ../a.rs\n") && text.contains("File: b.rs\n"));
+}
+
+#[test]
+fn annotates_a_single_file_dir() {
+    let (repo, _, second) = setup_repo();
+    let output = Command::cargo_bin("amc")
+        .unwrap()
+        .current_dir(repo.path())
+        .args(["--dir", "src/b.rs", "--no-prompt", "--no-manifest"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let text = String::from_utf8(output.stdout).unwrap();
+    assert!(text.contains("File: b.rs\n"), "{}", text);
+    assert!(text.contains(&format!("Last commit: {}\n", second)));
+    assert!(!text.contains("a.rs"));
+
+    Command::cargo_bin("amc")
+        .unwrap()
+        .current_dir(repo.path())
+        .args(["--dir", "src/b.rs", "--repo-root", "--no-prompt"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("File: src/b.rs\n"));
+}
+
+#[test]
+fn summary_sidecar_lists_included_and_skipped_files() {
+    let (repo, _, _) = setup_repo();
+    fs::write(repo.path().join("new.rs"), "fn new() {}\n").unwrap();
+    let summary = repo.path().join("out.meta.json");
+
+    amc(repo.path())
+        .args(["--skip-untracked", "--output"])
+        .arg(repo.path().join("out.txt"))
+        .arg("--summary")
+        .arg(&summary)
+        .assert()
+        .success();
+
+    let value: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&summary).unwrap()).unwrap();
+    assert_eq!(value["files"], 2);
+    assert_eq!(value["total_bytes"], 21);
+    assert_eq!(value["included"][0]["path"], "a.rs");
+    assert_eq!(
+        value["excluded"],
+        serde_json::json!([{ "path": "new.rs", "reason": "untracked" }])
+    );
+}
+
+#[test]
+fn diff_reports_changed_files_between_outputs() {
+    let (repo, _, _) = setup_repo();
+    let old = repo.path().join("old.txt");
+    let new = repo.path().join("new.txt");
+
+    amc(repo.path())
+        .arg("--output")
+        .arg(&old)
+        .assert()
+        .success();
+    fs::write(repo.path().join("a.rs"), "fn a() {}\nfn c() {}\n").unwrap();
+    fs::write(repo.path().join("new.rs"), "fn new() {}\n").unwrap();
+    amc(repo.path())
+        .arg("--output")
+        .arg(&new)
+        .assert()
+        .success();
+
+    amc(repo.path())
+        .arg("diff")
+        .arg(&old)
+        .arg(&new)
+        .arg("--full")
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "M  a.rs  (+1 -0)\nA  new.rs  (+1 -0)\n\
+             2 file(s) changed: 1 added, 0 removed, 1 modified\n",
+        ))
+        .stdout(predicate::str::contains("+++ b/a.rs\n"));
+}
+
+#[test]
+fn completions_are_generated_for_the_amc_binary() {
+    Command::cargo_bin("amc")
+        .unwrap()
+        .args(["completions", "bash"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("_amc()"))
+        .stdout(predicate::str::contains("--max-tokens"));
+}
+
+#[test]
+fn caches_git_lookups_in_the_git_directory() {
+    let (repo, _, second) = setup_repo();
+    let cache = repo.path().join(".git/amc-cache.json");
+
+    amc(repo.path()).arg("--no-cache").assert().success();
+    assert!(!cache.exists());
+
+    for _ in 0..2 {
+        amc(repo.path())
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(format!(
+                "File: src/b.rs\nLast commit: {second}\n"
+            )));
+        assert!(cache.exists());
+    }
+
+    amc(repo.path())
+        .arg("clear-cache")
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("Removed "));
+    assert!(!cache.exists());
+}
+
+#[test]
+fn reports_a_summary_line_unless_quiet() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("a.rs"), "fn a() {}\n").unwrap();
+    fs::write(temp_dir.path().join("b.rs"), "fn b() {}\n").unwrap();
+
+    let output = amc(temp_dir.path())
+        .args(["--no-git", "--no-prompt"])
+        .assert()
+        .success()
+        .stderr(
+            predicate::str::is_match(
+                r"Wrote 2 file\(s\), \d+ B, ~\d+ tokens in \d+\.\d\ds\n",
+            )
+            .unwrap(),
+        )
+        .get_output()
+        .clone();
+
+    // Both figures describe what was written, headers included
+    let written = output.stdout.len();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(&format!(
+        "{} B, ~{} tokens",
+        written,
+        written.div_ceil(4)
+    )));
+
+    amc(temp_dir.path())
+        .args(["--no-git", "--no-prompt", "--quiet"])
+        .assert()
+        .success()
+        .stderr("");
+}
+
+#[test]
+fn include_submodules_annotates_them_from_their_own_history() {
+    let temp_dir = TempDir::new().unwrap();
+    let lib_dir = temp_dir.path().join("lib");
+    let lib = Repository::init(&lib_dir).unwrap();
+    let lib_commit = commit_files(
+        &lib,
+        &[("parse.rs", "fn parse() {}\n")],
+        "Add parser",
+        1_700_000_000,
+    );
+
+    let app_dir = temp_dir.path().join("app");
+    let app = Repository::init(&app_dir).unwrap();
+    let mut submodule = app
+        .submodule(lib_dir.to_str().unwrap(), Path::new("vendor/lib"), true)
+        .unwrap();
+    submodule.clone(None).unwrap();
+    submodule.add_finalize().unwrap();
+    commit_files(
+        &app,
+        &[("main.rs", "fn main() {}\n")],
+        "Add app",
+        1_700_000_100,
+    );
+
+    amc(&app_dir).arg("--no-prompt").assert().success().stdout(
+        predicate::str::contains("File: main.rs\n")
+            .and(predicate::str::contains("vendor/lib").not()),
+    );
+
+    amc(&app_dir)
+        .args(["--no-prompt", "--include-submodules"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "File: vendor/lib/parse.rs\nLast commit: {}\n",
+            lib_commit
+        )));
+}
+
+#[test]
+fn discovered_filters_only_run_with_allow_filters() {
+    let (repo, _, _) = setup_repo();
+    let marker = repo.path().join("filtered");
+    fs::write(
+        repo.path().join(".amc.toml"),
+        "delimiter = \"---\"\nextensions = [\"rs\"]\n\n\
+         [filters]\nrs = \"touch filtered; tr a-z A-Z\"\n",
+    )
+    .unwrap();
+
+    amc(repo.path())
+        .arg("--no-prompt")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("fn a() {}\n"))
+        .stderr(predicate::str::contains("--allow-filters"));
+    assert!(!marker.exists());
+
+    amc(repo.path())
+        .args(["--no-prompt", "--allow-filters"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("FN A() {}\n"));
+    assert!(marker.exists());
+}